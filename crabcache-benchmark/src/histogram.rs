@@ -0,0 +1,139 @@
+//! Latencies in microseconds, counted in log-linear buckets.
+//!
+//! Values below `2 * SUB_BUCKETS` get a bucket each; above that every
+//! power of two is split into `SUB_BUCKETS` equal buckets, which keeps the
+//! error of any percentile under 1% however long the tail.
+
+/// Buckets per power of two.
+const SUB_BUCKETS: u64 = 128;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+fn bucket(value: u64) -> usize {
+    if value < 2 * SUB_BUCKETS {
+        return value as usize;
+    }
+    // The position of the highest bit picks the power of two, the bits
+    // after it the bucket within it.
+    let magnitude = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> magnitude) - SUB_BUCKETS;
+    ((magnitude as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// The highest value that lands in `index`.
+fn bucket_ceiling(index: usize) -> u64 {
+    let index = index as u64;
+    if index < 2 * SUB_BUCKETS {
+        return index;
+    }
+    let magnitude = index / SUB_BUCKETS - 1;
+    let sub_bucket = index % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub_bucket + 1) << magnitude) - 1
+}
+
+impl Histogram {
+    pub fn record(&mut self, micros: u64) {
+        self.record_n(micros, 1);
+    }
+
+    /// Records `n` operations that each took `micros`.
+    pub fn record_n(&mut self, micros: u64, n: u64) {
+        let index = bucket(micros);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += n;
+        self.count += n;
+        self.sum += micros * n;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// The smallest value at or below which `percentile` percent of the
+    /// recorded values fall, to within the bucket width.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_ceiling(index).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for value in [0, 1, 255, 256, 257, 1000, 123_456, 10_000_000_000] {
+            let index = bucket(value);
+            assert!(bucket_ceiling(index) >= value, "{}", value);
+            assert!(index == 0 || bucket_ceiling(index - 1) < value, "{}", value);
+            // Within 1% of the value.
+            assert!(bucket_ceiling(index) - value <= value / 100, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+        for micros in 1..=1000 {
+            histogram.record(micros);
+        }
+        let mut slow = Histogram::default();
+        slow.record_n(50_000, 10);
+        histogram.merge(&slow);
+
+        assert_eq!(histogram.count(), 1010);
+        assert_eq!(histogram.max(), 50_000);
+        assert!((histogram.mean() - (500_500.0 + 500_000.0) / 1010.0).abs() < 1e-9);
+        let p50 = histogram.percentile(50.0);
+        assert!((505..=510).contains(&p50), "{}", p50);
+        let p99 = histogram.percentile(99.0);
+        assert!((1000..=1008).contains(&p99), "{}", p99);
+        assert_eq!(histogram.percentile(99.9), 50_000);
+        assert_eq!(histogram.percentile(100.0), 50_000);
+    }
+}
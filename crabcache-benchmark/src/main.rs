@@ -0,0 +1,491 @@
+//! Drives a server with many connections and reports throughput and
+//! latency.
+//!
+//! ```text
+//! crabcache-benchmark [-h host] [-p port] [-s socket] [-c clients]
+//!     [-n requests | --duration seconds] [-P pipeline] [--mix get=80,set=20,del=0]
+//!     [--keyspace keys] [--value-size bytes|min-max] [--prefill] [--seed n]
+//!     [--format text|csv|json]
+//! ```
+//!
+//! Each connection runs on its own thread and sends its requests
+//! `pipeline` at a time. Every request in a batch is charged the batch's
+//! round trip, so latencies grow with the pipeline depth.
+
+mod histogram;
+mod workload;
+
+use anyhow::{anyhow, bail, Context, Result};
+use crabcache_client::{Address, Cmd, Connection, Value};
+use histogram::Histogram;
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use workload::{Mix, Op, Rng, ValueSize, Workload};
+
+const USAGE: &str = "usage: crabcache-benchmark [-h host] [-p port] [-s socket] [-c clients] \
+[-n requests | --duration seconds] [-P pipeline] [--mix get=80,set=20,del=0] [--keyspace keys] \
+[--value-size bytes|min-max] [--prefill] [--seed n] [--format text|csv|json]";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many SETs `--prefill` sends at a time.
+const PREFILL_BATCH: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Limit {
+    Requests(u64),
+    Duration(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Debug)]
+struct Options {
+    address: Address,
+    clients: usize,
+    limit: Limit,
+    pipeline: usize,
+    mix: Mix,
+    keyspace: u64,
+    value_size: ValueSize,
+    prefill: bool,
+    seed: u64,
+    format: Format,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            address: Address::Tcp("127.0.0.1:6379".to_string()),
+            clients: 50,
+            limit: Limit::Requests(100_000),
+            pipeline: 1,
+            mix: Mix::default(),
+            keyspace: 10_000,
+            value_size: ValueSize { min: 64, max: 64 },
+            prefill: false,
+            seed: 0,
+            format: Format::Text,
+        }
+    }
+}
+
+/// Parses the command line, returning `None` if help was asked for.
+fn parse_options(args: impl IntoIterator<Item = String>) -> Result<Option<Options>> {
+    fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T> {
+        value
+            .parse()
+            .map_err(|_| anyhow!("invalid {} '{}'", flag, value))
+    }
+
+    let mut options = Options::default();
+    let mut host = "127.0.0.1".to_string();
+    let mut port = 6379u16;
+    let mut socket = None;
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        if flag == "--help" {
+            return Ok(None);
+        }
+        if flag == "--prefill" {
+            options.prefill = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "-h" => host = value,
+            "-p" => port = number(&flag, &value)?,
+            "-s" => socket = Some(PathBuf::from(value)),
+            "-c" => options.clients = number(&flag, &value)?,
+            "-n" => options.limit = Limit::Requests(number(&flag, &value)?),
+            "--duration" => {
+                let seconds: f64 = number(&flag, &value)?;
+                options.limit = Limit::Duration(
+                    Duration::try_from_secs_f64(seconds)
+                        .map_err(|_| anyhow!("invalid --duration '{}'", value))?,
+                );
+            }
+            "-P" => options.pipeline = number(&flag, &value)?,
+            "--mix" => options.mix = Mix::parse(&value).context("invalid --mix")?,
+            "--keyspace" => options.keyspace = number(&flag, &value)?,
+            "--value-size" => {
+                options.value_size = ValueSize::parse(&value).context("invalid --value-size")?
+            }
+            "--seed" => options.seed = number(&flag, &value)?,
+            "--format" => {
+                options.format = match value.as_str() {
+                    "text" => Format::Text,
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    _ => bail!("invalid --format '{}'", value),
+                }
+            }
+            _ => bail!("unknown option '{}'", flag),
+        }
+    }
+    if options.clients == 0 || options.pipeline == 0 || options.keyspace == 0 {
+        bail!("-c, -P and --keyspace must be at least 1");
+    }
+    options.address = match socket {
+        Some(path) => Address::Unix(path),
+        None => Address::Tcp(format!("{}:{}", host, port)),
+    };
+    Ok(Some(options))
+}
+
+/// What one command did over a run.
+#[derive(Debug, Clone, Default)]
+struct OpStats {
+    latencies: Histogram,
+    errors: u64,
+}
+
+impl OpStats {
+    fn merge(&mut self, other: &OpStats) {
+        self.latencies.merge(&other.latencies);
+        self.errors += other.errors;
+    }
+}
+
+#[derive(Debug)]
+struct Report {
+    clients: usize,
+    pipeline: usize,
+    elapsed: Duration,
+    /// Indexed by `Op::index`.
+    ops: Vec<OpStats>,
+}
+
+impl Report {
+    /// One row per command that was sent, then the totals.
+    fn rows(&self) -> Vec<(&'static str, OpStats)> {
+        let mut total = OpStats::default();
+        let mut rows = Vec::new();
+        for op in Op::ALL {
+            let stats = &self.ops[op.index()];
+            if stats.latencies.count() > 0 {
+                total.merge(stats);
+                rows.push((op.name(), stats.clone()));
+            }
+        }
+        rows.push(("ALL", total));
+        rows
+    }
+
+    fn ops_per_sec(&self, stats: &OpStats) -> f64 {
+        stats.latencies.count() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn text(&self) -> String {
+        let rows = self.rows();
+        let total = rows.last().unwrap().1.latencies.count();
+        let mut text = format!(
+            "{} requests in {:.2}s from {} clients, pipeline {}\n\n",
+            total,
+            self.elapsed.as_secs_f64(),
+            self.clients,
+            self.pipeline
+        );
+        text += &format!(
+            "{:<7} {:>10} {:>8} {:>12} {:>9} {:>9} {:>9} {:>9} {:>9}\n",
+            "command",
+            "requests",
+            "errors",
+            "ops/sec",
+            "mean ms",
+            "p50 ms",
+            "p99 ms",
+            "p99.9 ms",
+            "max ms"
+        );
+        for (name, stats) in &rows {
+            let latencies = &stats.latencies;
+            text += &format!(
+                "{:<7} {:>10} {:>8} {:>12.1} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}\n",
+                name,
+                latencies.count(),
+                stats.errors,
+                self.ops_per_sec(stats),
+                latencies.mean() / 1000.0,
+                millis(latencies.percentile(50.0)),
+                millis(latencies.percentile(99.0)),
+                millis(latencies.percentile(99.9)),
+                millis(latencies.max()),
+            );
+        }
+        text
+    }
+
+    fn csv(&self) -> String {
+        let mut csv = "command,clients,pipeline,requests,errors,seconds,ops_per_sec,mean_ms,\
+p50_ms,p99_ms,p999_ms,max_ms\n"
+            .to_string();
+        for (name, stats) in self.rows() {
+            let latencies = &stats.latencies;
+            csv += &format!(
+                "{},{},{},{},{},{:.6},{:.1},{:.3},{:.3},{:.3},{:.3},{:.3}\n",
+                name,
+                self.clients,
+                self.pipeline,
+                latencies.count(),
+                stats.errors,
+                self.elapsed.as_secs_f64(),
+                self.ops_per_sec(&stats),
+                latencies.mean() / 1000.0,
+                millis(latencies.percentile(50.0)),
+                millis(latencies.percentile(99.0)),
+                millis(latencies.percentile(99.9)),
+                millis(latencies.max()),
+            );
+        }
+        csv
+    }
+
+    fn json(&self) -> String {
+        let commands: Vec<String> = self
+            .rows()
+            .into_iter()
+            .map(|(name, stats)| {
+                let latencies = &stats.latencies;
+                format!(
+                    "{{\"command\":\"{}\",\"requests\":{},\"errors\":{},\"ops_per_sec\":{:.1},\
+\"latency_ms\":{{\"mean\":{:.3},\"p50\":{:.3},\"p99\":{:.3},\"p99.9\":{:.3},\"max\":{:.3}}}}}",
+                    name,
+                    latencies.count(),
+                    stats.errors,
+                    self.ops_per_sec(&stats),
+                    latencies.mean() / 1000.0,
+                    millis(latencies.percentile(50.0)),
+                    millis(latencies.percentile(99.0)),
+                    millis(latencies.percentile(99.9)),
+                    millis(latencies.max()),
+                )
+            })
+            .collect();
+        format!(
+            "{{\"clients\":{},\"pipeline\":{},\"seconds\":{:.6},\"commands\":[{}]}}\n",
+            self.clients,
+            self.pipeline,
+            self.elapsed.as_secs_f64(),
+            commands.join(",")
+        )
+    }
+}
+
+fn millis(micros: u64) -> f64 {
+    micros as f64 / 1000.0
+}
+
+fn connect(options: &Options) -> Result<Connection> {
+    Connection::connect(&options.address, Some(CONNECT_TIMEOUT))
+        .with_context(|| format!("could not connect to {}", options.address))
+}
+
+/// Gives every key in the keyspace a value, so GETs find something.
+fn prefill(options: &Options, workload: &Workload) -> Result<()> {
+    let mut connection = connect(options)?;
+    let mut rng = Rng::new(options.seed);
+    let mut start = 0;
+    while start < options.keyspace {
+        let end = (start + PREFILL_BATCH).min(options.keyspace);
+        let cmds: Vec<Cmd> = (start..end)
+            .map(|key| workload.set(key, &mut rng))
+            .collect();
+        for reply in connection.query_all(&cmds)? {
+            reply.into_result().context("prefill failed")?;
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// Sends batches on `connection` until the requests run out or time is up.
+fn drive(
+    mut connection: Connection,
+    options: &Options,
+    workload: &Workload,
+    seed: u64,
+    remaining: &AtomicU64,
+    started: Instant,
+) -> Result<Vec<OpStats>> {
+    let mut rng = Rng::new(seed);
+    let mut stats = vec![OpStats::default(); Op::ALL.len()];
+    let mut ops = Vec::with_capacity(options.pipeline);
+    let mut cmds = Vec::with_capacity(options.pipeline);
+    loop {
+        let batch = match options.limit {
+            Limit::Requests(_) => {
+                let pipeline = options.pipeline as u64;
+                let left = remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                        (left > 0).then(|| left.saturating_sub(pipeline))
+                    })
+                    .unwrap_or(0);
+                left.min(pipeline) as usize
+            }
+            Limit::Duration(duration) if started.elapsed() < duration => options.pipeline,
+            Limit::Duration(_) => 0,
+        };
+        if batch == 0 {
+            return Ok(stats);
+        }
+        ops.clear();
+        cmds.clear();
+        for _ in 0..batch {
+            let (op, cmd) = workload.command(&mut rng);
+            ops.push(op);
+            cmds.push(cmd);
+        }
+        let sent = Instant::now();
+        let replies = connection.query_all(&cmds)?;
+        let micros = sent.elapsed().as_micros() as u64;
+        for (op, reply) in ops.iter().zip(replies) {
+            let stats = &mut stats[op.index()];
+            stats.latencies.record(micros);
+            if matches!(reply, Value::Error { .. }) {
+                stats.errors += 1;
+            }
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<Report> {
+    let workload = Workload::new(options.mix, options.keyspace, options.value_size);
+    if options.prefill {
+        prefill(options, &workload)?;
+    }
+    // Connect everyone before the clock starts.
+    let connections = (0..options.clients)
+        .map(|_| connect(options))
+        .collect::<Result<Vec<_>>>()?;
+    let remaining = AtomicU64::new(match options.limit {
+        Limit::Requests(requests) => requests,
+        Limit::Duration(_) => 0,
+    });
+    let started = Instant::now();
+    let results: Vec<Result<Vec<OpStats>>> = thread::scope(|scope| {
+        let workers: Vec<_> = connections
+            .into_iter()
+            .enumerate()
+            .map(|(index, connection)| {
+                let seed = options.seed.wrapping_add(index as u64 + 1);
+                let (workload, remaining) = (&workload, &remaining);
+                scope.spawn(move || drive(connection, options, workload, seed, remaining, started))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("benchmark thread panicked"))
+            .collect()
+    });
+    let elapsed = started.elapsed();
+
+    let mut ops = vec![OpStats::default(); Op::ALL.len()];
+    for result in results {
+        for (total, stats) in ops.iter_mut().zip(result?) {
+            total.merge(&stats);
+        }
+    }
+    Ok(Report {
+        clients: options.clients,
+        pipeline: options.pipeline,
+        elapsed,
+        ops,
+    })
+}
+
+fn main() -> Result<()> {
+    let Some(options) = parse_options(std::env::args().skip(1)).context(USAGE)? else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    let report = run(&options)?;
+    let output = match options.format {
+        Format::Text => report.text(),
+        Format::Csv => report.csv(),
+        Format::Json => report.json(),
+    };
+    print!("{}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabcache::Server;
+
+    fn parse(line: &str) -> Result<Option<Options>> {
+        parse_options(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse("-s /tmp/c.sock -c 4 --duration 1.5 -P 16 --mix set=1 --prefill")
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.address, Address::Unix("/tmp/c.sock".into()));
+        assert_eq!(options.clients, 4);
+        assert_eq!(options.limit, Limit::Duration(Duration::from_millis(1500)));
+        assert_eq!(options.pipeline, 16);
+        assert!(options.prefill);
+        assert_eq!(options.format, Format::Text);
+
+        assert!(parse("--help").unwrap().is_none());
+        assert!(parse("-c 0").is_err());
+        assert!(parse("-n").is_err());
+        assert!(parse("--format xml").is_err());
+        assert!(parse("--duration -1").is_err());
+    }
+
+    #[test]
+    fn test_run_against_a_server() {
+        let dir = std::env::temp_dir().join(format!("crabcache-benchmark-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = Server::builder()
+            .dir(&dir)
+            .port(0)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let address = Address::from(server.addr());
+
+        let options = Options {
+            address,
+            clients: 3,
+            limit: Limit::Requests(1000),
+            pipeline: 7,
+            mix: Mix::parse("get=2,set=1,del=1").unwrap(),
+            keyspace: 50,
+            value_size: ValueSize { min: 1, max: 100 },
+            prefill: true,
+            ..Options::default()
+        };
+        let report = run(&options).unwrap();
+        let rows = report.rows();
+        assert_eq!(
+            rows.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            ["GET", "SET", "DEL", "ALL"]
+        );
+        assert_eq!(rows[3].1.latencies.count(), 1000);
+        assert_eq!(rows[3].1.errors, 0);
+        assert_eq!(report.csv().lines().count(), 5);
+        assert!(report
+            .json()
+            .contains("\"command\":\"ALL\",\"requests\":1000,"));
+
+        server.stop().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
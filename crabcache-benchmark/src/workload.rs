@@ -0,0 +1,246 @@
+//! What the benchmark sends: which commands, on which keys, with how much
+//! data.
+
+use anyhow::{anyhow, bail, Context, Result};
+use crabcache_client::Cmd;
+
+/// The commands a workload can mix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Get,
+    Set,
+    Del,
+}
+
+impl Op {
+    pub const ALL: [Op; 3] = [Op::Get, Op::Set, Op::Del];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Get => "GET",
+            Op::Set => "SET",
+            Op::Del => "DEL",
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Relative weights of each command, as in `get=80,set=20`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mix {
+    weights: [u32; 3],
+}
+
+impl Mix {
+    pub fn parse(text: &str) -> Result<Mix> {
+        let mut weights = [0; 3];
+        for part in text.split(',') {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected command=weight, got '{}'", part))?;
+            let op = Op::ALL
+                .into_iter()
+                .find(|op| op.name().eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| anyhow!("unknown command '{}'", name))?;
+            weights[op.index()] = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid weight '{}'", weight))?;
+        }
+        if weights.iter().all(|weight| *weight == 0) {
+            bail!("every weight is zero");
+        }
+        Ok(Mix { weights })
+    }
+
+    fn pick(&self, rng: &mut Rng) -> Op {
+        let total: u32 = self.weights.iter().sum();
+        let mut roll = rng.below(total as u64) as u32;
+        for op in Op::ALL {
+            if roll < self.weights[op.index()] {
+                return op;
+            }
+            roll -= self.weights[op.index()];
+        }
+        unreachable!("the roll is below the total weight")
+    }
+}
+
+impl Default for Mix {
+    fn default() -> Mix {
+        Mix {
+            weights: [80, 20, 0],
+        }
+    }
+}
+
+/// How big SET values are: `100` for a fixed size, `10-1000` for sizes
+/// spread evenly over a range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueSize {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl ValueSize {
+    pub fn parse(text: &str) -> Result<ValueSize> {
+        let parse = |size: &str| {
+            size.trim()
+                .parse::<usize>()
+                .with_context(|| format!("invalid size '{}'", size))
+        };
+        let size = match text.split_once('-') {
+            Some((min, max)) => ValueSize {
+                min: parse(min)?,
+                max: parse(max)?,
+            },
+            None => {
+                let size = parse(text)?;
+                ValueSize {
+                    min: size,
+                    max: size,
+                }
+            }
+        };
+        if size.min > size.max {
+            bail!("the smallest size is above the largest");
+        }
+        Ok(size)
+    }
+
+    fn pick(&self, rng: &mut Rng) -> usize {
+        self.min + rng.below((self.max - self.min + 1) as u64) as usize
+    }
+}
+
+/// xorshift64*: fast, and the same seed always gives the same workload.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Zero is the one state xorshift never leaves; an odd one is never
+        // zero.
+        Rng(seed << 1 | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number below `bound`, which must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+pub struct Workload {
+    pub mix: Mix,
+    pub keyspace: u64,
+    pub value_size: ValueSize,
+    /// The bytes values are cut from, so generating one costs no more than
+    /// a copy.
+    data: Vec<u8>,
+}
+
+impl Workload {
+    pub fn new(mix: Mix, keyspace: u64, value_size: ValueSize) -> Workload {
+        let mut rng = Rng::new(0);
+        let data = (0..value_size.max * 2)
+            .map(|_| b'a' + rng.below(26) as u8)
+            .collect();
+        Workload {
+            mix,
+            keyspace,
+            value_size,
+            data,
+        }
+    }
+
+    pub fn key(index: u64) -> String {
+        format!("key:{:012}", index)
+    }
+
+    /// The SET that gives key `index` a value.
+    pub fn set(&self, index: u64, rng: &mut Rng) -> Cmd {
+        let size = self.value_size.pick(rng);
+        let start = rng.below((self.data.len() - size + 1) as u64) as usize;
+        Cmd::new("SET")
+            .arg(Workload::key(index))
+            .arg(&self.data[start..start + size])
+    }
+
+    /// The next command, picked at random.
+    pub fn command(&self, rng: &mut Rng) -> (Op, Cmd) {
+        let op = self.mix.pick(rng);
+        let index = rng.below(self.keyspace);
+        let cmd = match op {
+            Op::Get => Cmd::new("GET").arg(Workload::key(index)),
+            Op::Set => self.set(index, rng),
+            Op::Del => Cmd::new("DEL").arg(Workload::key(index)),
+        };
+        (op, cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() {
+        assert_eq!(
+            Mix::parse("get=3, SET=1").unwrap(),
+            Mix { weights: [3, 1, 0] }
+        );
+        assert!(Mix::parse("get=0").is_err());
+        assert!(Mix::parse("incr=1").is_err());
+        assert!(Mix::parse("get").is_err());
+        assert!(Mix::parse("get=-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_value_size() {
+        assert_eq!(
+            ValueSize::parse("100").unwrap(),
+            ValueSize { min: 100, max: 100 }
+        );
+        assert_eq!(
+            ValueSize::parse("10-1000").unwrap(),
+            ValueSize { min: 10, max: 1000 }
+        );
+        assert!(ValueSize::parse("10-1").is_err());
+        assert!(ValueSize::parse("big").is_err());
+    }
+
+    #[test]
+    fn test_workload_follows_the_mix() {
+        let workload = Workload::new(
+            Mix::parse("get=1,del=3").unwrap(),
+            10,
+            ValueSize::parse("1-8").unwrap(),
+        );
+        let mut rng = Rng::new(7);
+        let mut counts = [0; 3];
+        for _ in 0..4000 {
+            let (op, cmd) = workload.command(&mut rng);
+            counts[op.index()] += 1;
+            assert_eq!(cmd.args()[0], op.name().as_bytes());
+        }
+        assert_eq!(counts[Op::Set.index()], 0);
+        assert!(
+            (800..1200).contains(&counts[Op::Get.index()]),
+            "{:?}",
+            counts
+        );
+
+        let set = workload.set(3, &mut rng);
+        assert_eq!(set.args()[1], b"key:000000000003");
+        assert!((1..=8).contains(&set.args()[2].len()));
+    }
+}
@@ -0,0 +1,119 @@
+//! Splits a command line into arguments the way redis-cli does.
+//!
+//! Arguments are separated by whitespace. Double quotes allow spaces and the
+//! escapes `\n`, `\r`, `\t`, `\b`, `\a`, `\xHH`, `\\` and `\"`; single
+//! quotes allow spaces and `\'` only. A closing quote must end the argument.
+
+use anyhow::{bail, Result};
+
+pub fn split(line: &str) -> Result<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+        let mut arg = Vec::new();
+        match first {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        None => bail!("unbalanced quotes"),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => arg.push(b'\n'),
+                            Some('r') => arg.push(b'\r'),
+                            Some('t') => arg.push(b'\t'),
+                            Some('b') => arg.push(0x08),
+                            Some('a') => arg.push(0x07),
+                            Some('x') => {
+                                let hex: String = chars.clone().take(2).collect();
+                                match u8::from_str_radix(&hex, 16) {
+                                    Ok(byte) if hex.len() == 2 => {
+                                        arg.push(byte);
+                                        chars.nth(1);
+                                    }
+                                    _ => arg.push(b'x'),
+                                }
+                            }
+                            Some(c) => push_char(&mut arg, c),
+                            None => bail!("unbalanced quotes"),
+                        },
+                        Some(c) => push_char(&mut arg, c),
+                    }
+                }
+                closing_quote_ends_argument(chars.peek())?;
+            }
+            '\'' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        None => bail!("unbalanced quotes"),
+                        Some('\'') => break,
+                        Some('\\') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            arg.push(b'\'');
+                        }
+                        Some(c) => push_char(&mut arg, c),
+                    }
+                }
+                closing_quote_ends_argument(chars.peek())?;
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    push_char(&mut arg, c);
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn push_char(arg: &mut Vec<u8>, c: char) {
+    arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn closing_quote_ends_argument(next: Option<&char>) -> Result<()> {
+    match next {
+        Some(c) if !c.is_whitespace() => bail!("closing quote must be followed by a space"),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_str(line: &str) -> Vec<String> {
+        split(line)
+            .unwrap()
+            .into_iter()
+            .map(|arg| String::from_utf8(arg).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split_str("  SET  key value "), ["SET", "key", "value"]);
+        assert_eq!(
+            split_str(r#"SET "a key" 'it\'s "here"'"#),
+            ["SET", "a key", r#"it's "here""#]
+        );
+        assert_eq!(
+            split_str(r#""tab\there" "\x41\x4a\xZZ" 'no\n'"#),
+            ["tab\there", "AJxZZ", "no\\n"]
+        );
+        assert_eq!(split_str(r#""" ''"#), ["", ""]);
+        assert!(split_str("").is_empty());
+        assert_eq!(split(r#""\xff""#).unwrap(), [vec![0xff]]);
+    }
+
+    #[test]
+    fn test_split_errors() {
+        assert!(split(r#"GET "key"#).is_err());
+        assert!(split("GET 'key").is_err());
+        assert!(split(r#"GET "key"suffix"#).is_err());
+    }
+}
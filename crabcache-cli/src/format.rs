@@ -0,0 +1,97 @@
+//! Renders replies for a person to read, in the style of redis-cli.
+
+use crabcache_client::Value;
+
+pub fn format(value: &Value) -> String {
+    match value {
+        Value::Nil => "(nil)".to_string(),
+        Value::Error { code, message } => format!("(error {}) {}", code.as_num(), message),
+        Value::Integer(value) => format!("(integer) {}", value),
+        Value::String(bytes) => quote(bytes),
+        Value::Array(items) if items.is_empty() => "(empty array)".to_string(),
+        Value::Array(items) => {
+            let width = items.len().to_string().len();
+            let mut lines = Vec::new();
+            for (index, item) in items.iter().enumerate() {
+                let prefix = format!("{:>width$}) ", index + 1, width = width);
+                let indent = " ".repeat(prefix.len());
+                for (line_number, line) in format(item).lines().enumerate() {
+                    if line_number == 0 {
+                        lines.push(format!("{}{}", prefix, line));
+                    } else {
+                        lines.push(format!("{}{}", indent, line));
+                    }
+                }
+            }
+            lines.join("\n")
+        }
+    }
+}
+
+/// Quotes a string, escaping anything that is not printable ASCII.
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabcache_client::ErrorCode;
+
+    fn string(value: &[u8]) -> Value {
+        Value::String(value.to_vec())
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(&Value::Nil), "(nil)");
+        assert_eq!(format(&Value::Integer(-3)), "(integer) -3");
+        assert_eq!(format(&string(b"a \"b\"\n\xff")), r#""a \"b\"\n\xff""#);
+        assert_eq!(
+            format(&Value::Error {
+                code: ErrorCode::Type,
+                message: "WRONGTYPE wrong kind of value".to_string()
+            }),
+            "(error 3) WRONGTYPE wrong kind of value"
+        );
+        assert_eq!(format(&Value::Array(vec![])), "(empty array)");
+    }
+
+    #[test]
+    fn test_format_nested_arrays() {
+        let mut items = vec![string(b"a"); 9];
+        items.push(Value::Array(vec![Value::Integer(1), Value::Array(vec![])]));
+        assert_eq!(
+            format(&Value::Array(items)),
+            [
+                " 1) \"a\"",
+                " 2) \"a\"",
+                " 3) \"a\"",
+                " 4) \"a\"",
+                " 5) \"a\"",
+                " 6) \"a\"",
+                " 7) \"a\"",
+                " 8) \"a\"",
+                " 9) \"a\"",
+                "10) 1) (integer) 1",
+                "    2) (empty array)",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! An interactive client for crabcache's native protocol.
+//!
+//! ```text
+//! crabcache-cli [-h host] [-p port] [-s socket] [command [arg ...]]
+//! ```
+//!
+//! With a command it runs it and exits; otherwise it reads commands from a
+//! prompt, or one per line when standard input is not a terminal. The exit
+//! status is 1 if any reply was an error.
+
+mod args;
+mod format;
+
+use anyhow::{anyhow, bail, Context, Result};
+use crabcache_client::{Address, Cmd, Connection, Value};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::{
+    io::{self, BufRead, IsTerminal},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+const USAGE: &str = "usage: crabcache-cli [-h host] [-p port] [-s socket] [command [arg ...]]";
+
+struct Options {
+    address: Address,
+    command: Vec<String>,
+}
+
+/// Parses the command line, returning `None` if help was asked for.
+fn parse_options(args: impl IntoIterator<Item = String>) -> Result<Option<Options>> {
+    let mut args = args.into_iter();
+    let mut host = "127.0.0.1".to_string();
+    let mut port = 6379u16;
+    let mut socket = None;
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "-h" => host = value()?,
+            "-p" => {
+                let value = value()?;
+                port = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid port '{}'", value))?;
+            }
+            "-s" => socket = Some(PathBuf::from(value()?)),
+            "--help" => return Ok(None),
+            _ if arg.starts_with('-') => bail!("unknown option '{}'", arg),
+            _ => {
+                command.push(arg);
+                command.extend(args);
+                break;
+            }
+        }
+    }
+    let address = match socket {
+        Some(path) => Address::Unix(path),
+        None => Address::Tcp(format!("{}:{}", host, port)),
+    };
+    Ok(Some(Options { address, command }))
+}
+
+/// A connection that is opened on first use and again after it breaks, so
+/// a prompt outlives a server restart.
+struct Session {
+    address: Address,
+    connection: Option<Connection>,
+    failed: bool,
+}
+
+impl Session {
+    fn new(address: Address) -> Session {
+        Session {
+            address,
+            connection: None,
+            failed: false,
+        }
+    }
+
+    /// Sends one command and prints its reply.
+    fn run(&mut self, args: Vec<Vec<u8>>) {
+        let Some((name, rest)) = args.split_first() else {
+            return;
+        };
+        let cmd = rest
+            .iter()
+            .fold(Cmd::new(&String::from_utf8_lossy(name)), Cmd::arg);
+        match self.query(&cmd) {
+            Ok(value) => {
+                self.failed |= matches!(value, Value::Error { .. });
+                println!("{}", format::format(&value));
+            }
+            Err(err) => {
+                self.failed = true;
+                eprintln!("{:#}", err);
+            }
+        }
+    }
+
+    fn query(&mut self, cmd: &Cmd) -> Result<Value> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(
+                Connection::connect(&self.address, None)
+                    .with_context(|| format!("could not connect to {}", self.address))?,
+            ),
+        };
+        let result = connection.query_all(std::slice::from_ref(cmd));
+        if connection.is_broken() {
+            self.connection = None;
+        }
+        Ok(result?.pop().unwrap())
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".crabcache_cli_history"))
+}
+
+fn repl(session: &mut Session) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history yet on first use.
+        let _ = editor.load_history(path);
+    }
+    let prompt = format!("{}> ", session.address);
+    loop {
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        if matches!(line.trim(), "quit" | "exit") {
+            break;
+        }
+        match args::split(&line) {
+            Ok(args) => session.run(args),
+            Err(err) => eprintln!("Invalid argument(s): {}", err),
+        }
+    }
+    if let Some(path) = &history {
+        editor
+            .save_history(path)
+            .with_context(|| format!("failed to save history to {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Runs each line of standard input as a command.
+fn script(session: &mut Session) -> Result<()> {
+    for (number, line) in io::stdin().lock().lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match args::split(&line) {
+            Ok(args) => session.run(args),
+            Err(err) => {
+                session.failed = true;
+                eprintln!("line {}: invalid argument(s): {}", number + 1, err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<ExitCode> {
+    let Some(options) = parse_options(std::env::args().skip(1)).context(USAGE)? else {
+        println!("{}", USAGE);
+        return Ok(ExitCode::SUCCESS);
+    };
+    let mut session = Session::new(options.address);
+    if !options.command.is_empty() {
+        session.run(
+            options
+                .command
+                .into_iter()
+                .map(String::into_bytes)
+                .collect(),
+        );
+    } else if io::stdin().is_terminal() {
+        repl(&mut session)?;
+    } else {
+        script(&mut session)?;
+    }
+    Ok(if session.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Option<Options> {
+        parse_options(line.split_whitespace().map(String::from)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse("-h example.org -p 6380 GET -p").unwrap();
+        assert_eq!(
+            options.address,
+            Address::Tcp("example.org:6380".to_string())
+        );
+        assert_eq!(options.command, ["GET", "-p"]);
+
+        let options = parse("-s /tmp/crabcache.sock").unwrap();
+        assert_eq!(options.address, Address::Unix("/tmp/crabcache.sock".into()));
+        assert!(options.command.is_empty());
+
+        assert!(parse("--help").is_none());
+        assert!(parse_options(["-p".to_string()]).is_err());
+        assert!(parse_options(["-p".to_string(), "http".to_string()]).is_err());
+        assert!(parse_options(["-x".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::protocol::encode_request;
+
+/// Something that can be sent as one argument of a request.
+pub trait Arg {
+    fn write_arg(&self, out: &mut Vec<u8>);
+}
+
+impl Arg for [u8] {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl<const N: usize> Arg for [u8; N] {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl Arg for Vec<u8> {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl Arg for str {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Arg for String {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: Arg + ?Sized> Arg for &T {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        (**self).write_arg(out);
+    }
+}
+
+macro_rules! display_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl Arg for $ty {
+                fn write_arg(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(self.to_string().as_bytes());
+                }
+            }
+        )*
+    };
+}
+
+display_arg!(i32, i64, u32, u64, usize, f64);
+
+/// A request: the command name followed by its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmd {
+    args: Vec<Vec<u8>>,
+}
+
+impl Cmd {
+    pub fn new(name: &str) -> Cmd {
+        Cmd {
+            args: vec![name.as_bytes().to_vec()],
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Arg) -> Cmd {
+        let mut encoded = Vec::new();
+        arg.write_arg(&mut encoded);
+        self.args.push(encoded);
+        self
+    }
+
+    pub fn args(&self) -> &[Vec<u8>] {
+        &self.args
+    }
+
+    /// Appends the encoded request to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_request(&self.args, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        let cmd = Cmd::new("SET")
+            .arg("key")
+            .arg(b"value")
+            .arg(String::from("PX"))
+            .arg(1500u64)
+            .arg(-2.5);
+        let args: Vec<&[u8]> = cmd.args().iter().map(Vec::as_slice).collect();
+        assert_eq!(
+            args,
+            [&b"SET"[..], b"key", b"value", b"PX", b"1500", b"-2.5"]
+        );
+    }
+}
@@ -0,0 +1,561 @@
+//! Typed methods for the server's commands.
+//!
+//! HELLO is left out: it negotiates RESP versions, which a native connection
+//! does not speak.
+
+use crate::{
+    cmd::{Arg, Cmd},
+    error::{Error, Result},
+    protocol::Value,
+};
+use std::io;
+
+/// The time to live given to a key by `Commands::set_with_expiry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// `EX`: seconds from now.
+    Seconds(u64),
+    /// `PX`: milliseconds from now.
+    Millis(u64),
+    /// `EXAT`: a Unix time in seconds.
+    AtSeconds(u64),
+    /// `PXAT`: a Unix time in milliseconds.
+    AtMillis(u64),
+    /// `KEEPTTL`: whatever time to live the key already has.
+    KeepTtl,
+}
+
+/// One end of a ZRANGEBYSCORE interval. Infinite scores are allowed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl Arg for Bound {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        let value = match self {
+            Bound::Inclusive(value) => value,
+            Bound::Exclusive(value) => {
+                out.push(b'(');
+                value
+            }
+        };
+        value.write_arg(out);
+    }
+}
+
+/// Which SHUTDOWN asks for, overriding the configured save points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    Default,
+    Save,
+    NoSave,
+}
+
+fn unexpected<T>(value: Value) -> Result<T> {
+    Err(Error::UnexpectedReply(value))
+}
+
+/// Writes are acknowledged with a nil, administrative commands with `OK`.
+fn ack(value: Value) -> Result<()> {
+    match value {
+        Value::Nil | Value::String(_) => Ok(()),
+        value => unexpected(value),
+    }
+}
+
+fn integer(value: Value) -> Result<i64> {
+    match value {
+        Value::Integer(value) => Ok(value),
+        value => unexpected(value),
+    }
+}
+
+fn count(value: Value) -> Result<u64> {
+    match value {
+        Value::Integer(value) if value >= 0 => Ok(value as u64),
+        value => unexpected(value),
+    }
+}
+
+fn flag(value: Value) -> Result<bool> {
+    match value {
+        Value::Integer(0) => Ok(false),
+        Value::Integer(1) => Ok(true),
+        value => unexpected(value),
+    }
+}
+
+fn bytes(value: Value) -> Result<Vec<u8>> {
+    match value {
+        Value::String(value) => Ok(value),
+        value => unexpected(value),
+    }
+}
+
+fn optional_bytes(value: Value) -> Result<Option<Vec<u8>>> {
+    match value {
+        Value::Nil => Ok(None),
+        value => bytes(value).map(Some),
+    }
+}
+
+fn text(value: Value) -> Result<String> {
+    match value {
+        Value::String(value) => String::from_utf8(value)
+            .map_err(|err| Error::UnexpectedReply(Value::String(err.into_bytes()))),
+        value => unexpected(value),
+    }
+}
+
+/// Scores are sent as strings in the native protocol.
+fn double(value: Value) -> Result<f64> {
+    match value {
+        Value::String(text) => match std::str::from_utf8(&text).ok().and_then(|t| t.parse().ok()) {
+            Some(score) => Ok(score),
+            None => unexpected(Value::String(text)),
+        },
+        value => unexpected(value),
+    }
+}
+
+fn array(value: Value) -> Result<Vec<Value>> {
+    match value {
+        Value::Array(items) => Ok(items),
+        value => unexpected(value),
+    }
+}
+
+fn members(value: Value) -> Result<Vec<Vec<u8>>> {
+    array(value)?.into_iter().map(bytes).collect()
+}
+
+/// Members are followed by their scores in one flat array.
+fn members_with_scores(value: Value) -> Result<Vec<(Vec<u8>, f64)>> {
+    let items = array(value)?;
+    if items.len() % 2 != 0 {
+        return unexpected(Value::Array(items));
+    }
+    let mut items = items.into_iter();
+    let mut pairs = Vec::with_capacity(items.len() / 2);
+    while let (Some(member), Some(score)) = (items.next(), items.next()) {
+        pairs.push((bytes(member)?, double(score)?));
+    }
+    Ok(pairs)
+}
+
+fn score_range(key: impl Arg, min: Bound, max: Bound, limit: Option<(i64, i64)>) -> Cmd {
+    let cmd = Cmd::new("ZRANGEBYSCORE").arg(key).arg(min).arg(max);
+    match limit {
+        Some((offset, count)) => cmd.arg("LIMIT").arg(offset).arg(count),
+        None => cmd,
+    }
+}
+
+/// The server's commands, for anything that can send a request and wait for
+/// its reply. Error replies come back as `Error::Server`.
+pub trait Commands {
+    /// Sends `cmd` and returns its reply.
+    fn query(&mut self, cmd: &Cmd) -> Result<Value>;
+
+    /// The value at `key`, if any.
+    ///
+    /// The native protocol reports a missing key as the string `not found`,
+    /// so a value that is exactly those bytes reads as `None` too; `mget`
+    /// tells the two apart.
+    fn get(&mut self, key: impl Arg) -> Result<Option<Vec<u8>>> {
+        match self.query(&Cmd::new("GET").arg(key))? {
+            Value::String(value) if value == b"not found" => Ok(None),
+            value => optional_bytes(value),
+        }
+    }
+
+    fn set(&mut self, key: impl Arg, value: impl Arg) -> Result<()> {
+        ack(self.query(&Cmd::new("SET").arg(key).arg(value))?)
+    }
+
+    fn set_with_expiry(&mut self, key: impl Arg, value: impl Arg, expiry: Expiry) -> Result<()> {
+        let cmd = Cmd::new("SET").arg(key).arg(value);
+        let cmd = match expiry {
+            Expiry::Seconds(seconds) => cmd.arg("EX").arg(seconds),
+            Expiry::Millis(millis) => cmd.arg("PX").arg(millis),
+            Expiry::AtSeconds(seconds) => cmd.arg("EXAT").arg(seconds),
+            Expiry::AtMillis(millis) => cmd.arg("PXAT").arg(millis),
+            Expiry::KeepTtl => cmd.arg("KEEPTTL"),
+        };
+        ack(self.query(&cmd)?)
+    }
+
+    /// Deletes `keys`, returning how many existed.
+    fn del<K: Arg>(&mut self, keys: &[K]) -> Result<u64> {
+        let cmd = keys.iter().fold(Cmd::new("DEL"), Cmd::arg);
+        count(self.query(&cmd)?)
+    }
+
+    fn mget<K: Arg>(&mut self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        let cmd = keys.iter().fold(Cmd::new("MGET"), Cmd::arg);
+        array(self.query(&cmd)?)?
+            .into_iter()
+            .map(optional_bytes)
+            .collect()
+    }
+
+    fn mset<K: Arg, V: Arg>(&mut self, pairs: &[(K, V)]) -> Result<()> {
+        let cmd = pairs.iter().fold(Cmd::new("MSET"), |cmd, (key, value)| {
+            cmd.arg(key).arg(value)
+        });
+        ack(self.query(&cmd)?)
+    }
+
+    /// Adds one to the integer at `key`, returning the new value.
+    fn incr(&mut self, key: impl Arg) -> Result<i64> {
+        integer(self.query(&Cmd::new("INCR").arg(key))?)
+    }
+
+    fn decr(&mut self, key: impl Arg) -> Result<i64> {
+        integer(self.query(&Cmd::new("DECR").arg(key))?)
+    }
+
+    fn incrby(&mut self, key: impl Arg, increment: i64) -> Result<i64> {
+        integer(self.query(&Cmd::new("INCRBY").arg(key).arg(increment))?)
+    }
+
+    fn decrby(&mut self, key: impl Arg, decrement: i64) -> Result<i64> {
+        integer(self.query(&Cmd::new("DECRBY").arg(key).arg(decrement))?)
+    }
+
+    fn incrbyfloat(&mut self, key: impl Arg, increment: f64) -> Result<f64> {
+        double(self.query(&Cmd::new("INCRBYFLOAT").arg(key).arg(increment))?)
+    }
+
+    /// Sets a time to live in seconds, returning whether the key exists.
+    fn expire(&mut self, key: impl Arg, seconds: i64) -> Result<bool> {
+        flag(self.query(&Cmd::new("EXPIRE").arg(key).arg(seconds))?)
+    }
+
+    fn pexpire(&mut self, key: impl Arg, millis: i64) -> Result<bool> {
+        flag(self.query(&Cmd::new("PEXPIRE").arg(key).arg(millis))?)
+    }
+
+    fn expireat(&mut self, key: impl Arg, unix_seconds: i64) -> Result<bool> {
+        flag(self.query(&Cmd::new("EXPIREAT").arg(key).arg(unix_seconds))?)
+    }
+
+    fn pexpireat(&mut self, key: impl Arg, unix_millis: i64) -> Result<bool> {
+        flag(self.query(&Cmd::new("PEXPIREAT").arg(key).arg(unix_millis))?)
+    }
+
+    /// The seconds `key` has left to live: -1 if it has no time to live, -2
+    /// if it does not exist.
+    fn ttl(&mut self, key: impl Arg) -> Result<i64> {
+        integer(self.query(&Cmd::new("TTL").arg(key))?)
+    }
+
+    /// As `ttl`, in milliseconds.
+    fn pttl(&mut self, key: impl Arg) -> Result<i64> {
+        integer(self.query(&Cmd::new("PTTL").arg(key))?)
+    }
+
+    /// Removes the time to live of `key`, returning whether it had one.
+    fn persist(&mut self, key: impl Arg) -> Result<bool> {
+        flag(self.query(&Cmd::new("PERSIST").arg(key))?)
+    }
+
+    /// Adds `(score, member)` pairs, returning how many members are new.
+    fn zadd<M: Arg>(&mut self, key: impl Arg, pairs: &[(f64, M)]) -> Result<u64> {
+        let cmd = pairs
+            .iter()
+            .fold(Cmd::new("ZADD").arg(key), |cmd, (score, member)| {
+                cmd.arg(score).arg(member)
+            });
+        count(self.query(&cmd)?)
+    }
+
+    fn zrem<M: Arg>(&mut self, key: impl Arg, members: &[M]) -> Result<u64> {
+        let cmd = members.iter().fold(Cmd::new("ZREM").arg(key), Cmd::arg);
+        count(self.query(&cmd)?)
+    }
+
+    fn zscore(&mut self, key: impl Arg, member: impl Arg) -> Result<Option<f64>> {
+        match self.query(&Cmd::new("ZSCORE").arg(key).arg(member))? {
+            Value::Nil => Ok(None),
+            value => double(value).map(Some),
+        }
+    }
+
+    /// Members by rank, from `start` to `stop` inclusive; negative ranks
+    /// count from the end.
+    fn zrange(&mut self, key: impl Arg, start: i64, stop: i64) -> Result<Vec<Vec<u8>>> {
+        members(self.query(&Cmd::new("ZRANGE").arg(key).arg(start).arg(stop))?)
+    }
+
+    fn zrange_with_scores(
+        &mut self,
+        key: impl Arg,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(Vec<u8>, f64)>> {
+        let cmd = Cmd::new("ZRANGE").arg(key).arg(start).arg(stop);
+        members_with_scores(self.query(&cmd.arg("WITHSCORES"))?)
+    }
+
+    fn zrevrange(&mut self, key: impl Arg, start: i64, stop: i64) -> Result<Vec<Vec<u8>>> {
+        members(self.query(&Cmd::new("ZREVRANGE").arg(key).arg(start).arg(stop))?)
+    }
+
+    fn zrevrange_with_scores(
+        &mut self,
+        key: impl Arg,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(Vec<u8>, f64)>> {
+        let cmd = Cmd::new("ZREVRANGE").arg(key).arg(start).arg(stop);
+        members_with_scores(self.query(&cmd.arg("WITHSCORES"))?)
+    }
+
+    /// Members with scores between `min` and `max`, skipping `offset` and
+    /// returning at most `count` of them when `limit` is given.
+    fn zrangebyscore(
+        &mut self,
+        key: impl Arg,
+        min: Bound,
+        max: Bound,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<Vec<u8>>> {
+        members(self.query(&score_range(key, min, max, limit))?)
+    }
+
+    fn zrangebyscore_with_scores(
+        &mut self,
+        key: impl Arg,
+        min: Bound,
+        max: Bound,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<(Vec<u8>, f64)>> {
+        let cmd = score_range(key, min, max, limit).arg("WITHSCORES");
+        members_with_scores(self.query(&cmd)?)
+    }
+
+    fn zrank(&mut self, key: impl Arg, member: impl Arg) -> Result<Option<u64>> {
+        match self.query(&Cmd::new("ZRANK").arg(key).arg(member))? {
+            Value::Nil => Ok(None),
+            value => count(value).map(Some),
+        }
+    }
+
+    fn zcard(&mut self, key: impl Arg) -> Result<u64> {
+        count(self.query(&Cmd::new("ZCARD").arg(key))?)
+    }
+
+    /// Adds `increment` to the score of `member`, returning the new score.
+    fn zincrby(&mut self, key: impl Arg, increment: f64, member: impl Arg) -> Result<f64> {
+        double(self.query(&Cmd::new("ZINCRBY").arg(key).arg(increment).arg(member))?)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("SAVE"))?)
+    }
+
+    fn bgsave(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("BGSAVE"))?)
+    }
+
+    /// The Unix time of the last successful save.
+    fn lastsave(&mut self) -> Result<i64> {
+        integer(self.query(&Cmd::new("LASTSAVE"))?)
+    }
+
+    fn bgrewriteaof(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("BGREWRITEAOF"))?)
+    }
+
+    /// The INFO report for `sections`, or the default sections if empty.
+    fn info(&mut self, sections: &[&str]) -> Result<String> {
+        let cmd = sections.iter().fold(Cmd::new("INFO"), Cmd::arg);
+        text(self.query(&cmd)?)
+    }
+
+    fn client_id(&mut self) -> Result<u64> {
+        count(self.query(&Cmd::new("CLIENT").arg("ID"))?)
+    }
+
+    /// This connection's line of the CLIENT LIST report.
+    fn client_info(&mut self) -> Result<String> {
+        text(self.query(&Cmd::new("CLIENT").arg("INFO"))?)
+    }
+
+    fn client_list(&mut self) -> Result<String> {
+        text(self.query(&Cmd::new("CLIENT").arg("LIST"))?)
+    }
+
+    fn client_setname(&mut self, name: &str) -> Result<()> {
+        ack(self.query(&Cmd::new("CLIENT").arg("SETNAME").arg(name))?)
+    }
+
+    fn client_getname(&mut self) -> Result<Option<String>> {
+        match self.query(&Cmd::new("CLIENT").arg("GETNAME"))? {
+            Value::Nil => Ok(None),
+            value => text(value).map(Some),
+        }
+    }
+
+    /// Closes the connection with the given id, returning whether there
+    /// was one.
+    fn client_kill_id(&mut self, id: u64) -> Result<bool> {
+        let cmd = Cmd::new("CLIENT").arg("KILL").arg("ID").arg(id);
+        Ok(count(self.query(&cmd)?)? > 0)
+    }
+
+    /// Closes the connections from `addr` (`ip:port`), returning how many
+    /// there were.
+    fn client_kill_addr(&mut self, addr: &str) -> Result<u64> {
+        count(self.query(&Cmd::new("CLIENT").arg("KILL").arg("ADDR").arg(addr))?)
+    }
+
+    /// The number of commands the server knows.
+    fn command_count(&mut self) -> Result<u64> {
+        count(self.query(&Cmd::new("COMMAND").arg("COUNT"))?)
+    }
+
+    /// The COMMAND description of every command, as sent.
+    fn command(&mut self) -> Result<Value> {
+        self.query(&Cmd::new("COMMAND"))
+    }
+
+    /// The settings matching `pattern`, as name/value pairs.
+    fn config_get(&mut self, pattern: &str) -> Result<Vec<(String, String)>> {
+        let items = array(self.query(&Cmd::new("CONFIG").arg("GET").arg(pattern))?)?;
+        if items.len() % 2 != 0 {
+            return unexpected(Value::Array(items));
+        }
+        let mut items = items.into_iter();
+        let mut settings = Vec::new();
+        while let (Some(name), Some(value)) = (items.next(), items.next()) {
+            settings.push((text(name)?, text(value)?));
+        }
+        Ok(settings)
+    }
+
+    fn config_set(&mut self, name: &str, value: &str) -> Result<()> {
+        ack(self.query(&Cmd::new("CONFIG").arg("SET").arg(name).arg(value))?)
+    }
+
+    fn config_rewrite(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("CONFIG").arg("REWRITE"))?)
+    }
+
+    /// Stops the server. It closes the connection instead of replying once
+    /// the shutdown goes ahead, so that is what success looks like.
+    fn shutdown(&mut self, mode: ShutdownMode) -> Result<()> {
+        let cmd = match mode {
+            ShutdownMode::Default => Cmd::new("SHUTDOWN"),
+            ShutdownMode::Save => Cmd::new("SHUTDOWN").arg("SAVE"),
+            ShutdownMode::NoSave => Cmd::new("SHUTDOWN").arg("NOSAVE"),
+        };
+        match self.query(&cmd) {
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+            Err(err) => Err(err),
+            Ok(value) => unexpected(value),
+        }
+    }
+
+    /// Cancels a shutdown that is waiting for replicas or a save.
+    fn shutdown_abort(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("SHUTDOWN").arg("ABORT"))?)
+    }
+
+    fn ping(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("PING"))?)
+    }
+
+    /// PING with a message, which the server sends back.
+    fn echo(&mut self, message: impl Arg) -> Result<Vec<u8>> {
+        bytes(self.query(&Cmd::new("PING").arg(message))?)
+    }
+
+    /// Asks the server to close the connection once it has replied.
+    fn quit(&mut self) -> Result<()> {
+        ack(self.query(&Cmd::new("QUIT"))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use std::collections::VecDeque;
+
+    /// Answers with canned replies and records what it was sent.
+    #[derive(Default)]
+    struct Canned {
+        sent: Vec<Cmd>,
+        replies: VecDeque<Value>,
+    }
+
+    impl Commands for Canned {
+        fn query(&mut self, cmd: &Cmd) -> Result<Value> {
+            self.sent.push(cmd.clone());
+            self.replies.pop_front().unwrap().into_result()
+        }
+    }
+
+    fn string(value: &str) -> Value {
+        Value::String(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_typed_replies() {
+        let mut canned = Canned::default();
+        canned.replies.extend([
+            string("not found"),
+            Value::Array(vec![string("a"), string("1.5"), string("b"), string("inf")]),
+            Value::Nil,
+            Value::Error {
+                code: ErrorCode::Type,
+                message: "WRONGTYPE".to_string(),
+            },
+            Value::Integer(3),
+        ]);
+
+        assert_eq!(canned.get("k").unwrap(), None);
+        assert_eq!(
+            canned
+                .zrangebyscore_with_scores(
+                    "z",
+                    Bound::Exclusive(1.0),
+                    Bound::Inclusive(f64::INFINITY),
+                    Some((0, 2))
+                )
+                .unwrap(),
+            vec![(b"a".to_vec(), 1.5), (b"b".to_vec(), f64::INFINITY)]
+        );
+        assert_eq!(canned.zrank("z", "c").unwrap(), None);
+        assert!(matches!(
+            canned.zcard("k"),
+            Err(Error::Server {
+                code: ErrorCode::Type,
+                ..
+            })
+        ));
+        assert!(matches!(
+            canned.persist("k"),
+            Err(Error::UnexpectedReply(_))
+        ));
+
+        let range: Vec<&[u8]> = canned.sent[1].args().iter().map(Vec::as_slice).collect();
+        assert_eq!(
+            range,
+            [
+                &b"ZRANGEBYSCORE"[..],
+                b"z",
+                b"(1",
+                b"inf",
+                b"LIMIT",
+                b"0",
+                b"2",
+                b"WITHSCORES"
+            ]
+        );
+    }
+}
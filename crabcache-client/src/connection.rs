@@ -0,0 +1,224 @@
+use crate::{
+    cmd::Cmd,
+    commands::Commands,
+    error::{Error, Result},
+    protocol::{decode_reply, Value},
+};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Where a server listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// `host:port`, resolved on every connect.
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            Address::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl From<std::net::SocketAddr> for Address {
+    fn from(addr: std::net::SocketAddr) -> Address {
+        Address::Tcp(addr.to_string())
+    }
+}
+
+impl From<&str> for Address {
+    fn from(addr: &str) -> Address {
+        Address::Tcp(addr.to_string())
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A blocking connection: each request waits for its reply.
+pub struct Connection {
+    stream: Stream,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    broken: bool,
+}
+
+impl Connection {
+    /// Connects to `address`, giving up after `timeout` if one is given.
+    pub fn connect(address: &Address, timeout: Option<Duration>) -> Result<Connection> {
+        let stream = match address {
+            Address::Tcp(addr) => Stream::Tcp(connect_tcp(addr, timeout)?),
+            Address::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+        };
+        if let Stream::Tcp(stream) = &stream {
+            // Requests are written whole, so there is nothing to gain from
+            // holding back small ones.
+            stream.set_nodelay(true)?;
+        }
+        Ok(Connection {
+            stream,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            broken: false,
+        })
+    }
+
+    /// Bounds how long a request waits to be written or for its reply.
+    /// `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match &self.stream {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+            }
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an I/O or protocol error has left the connection unusable.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Sends `cmds` in one write and returns their replies in order, with
+    /// error replies left in place.
+    pub fn query_all(&mut self, cmds: &[Cmd]) -> Result<Vec<Value>> {
+        if self.broken {
+            return Err(Error::Io(io::ErrorKind::NotConnected.into()));
+        }
+        let result = self.exchange(cmds);
+        if let Err(err) = &result {
+            self.broken = err.is_fatal();
+        }
+        result
+    }
+
+    fn exchange(&mut self, cmds: &[Cmd]) -> Result<Vec<Value>> {
+        self.write_buffer.clear();
+        for cmd in cmds {
+            cmd.encode(&mut self.write_buffer);
+        }
+        self.stream.write_all(&self.write_buffer)?;
+        let mut replies = Vec::with_capacity(cmds.len());
+        while replies.len() < cmds.len() {
+            replies.push(self.read_reply()?);
+        }
+        Ok(replies)
+    }
+
+    fn read_reply(&mut self) -> Result<Value> {
+        loop {
+            if let Some((value, used)) = decode_reply(&self.read_buffer)? {
+                self.read_buffer.drain(..used);
+                return Ok(value);
+            }
+            let mut chunk = [0; 16 * 1024];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(read) => self.read_buffer.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Commands for Connection {
+    fn query(&mut self, cmd: &Cmd) -> Result<Value> {
+        let mut replies = self.query_all(std::slice::from_ref(cmd))?;
+        replies.pop().unwrap().into_result()
+    }
+}
+
+fn connect_tcp(addr: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addr);
+    };
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    }))
+}
+
+/// Requests queued up to be sent together, saving a round trip each.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    cmds: Vec<Cmd>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub fn add(&mut self, cmd: Cmd) -> &mut Pipeline {
+        self.cmds.push(cmd);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cmds.clear();
+    }
+
+    /// Sends the queued requests on `connection` and returns a reply for
+    /// each. One failing does not stop the others: its reply is a
+    /// `Value::Error`.
+    pub fn execute(&self, connection: &mut Connection) -> Result<Vec<Value>> {
+        connection.query_all(&self.cmds)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::protocol::Value;
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The code sent with an error reply. The numbers are fixed by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Unknown,
+    TooBig,
+    Type,
+    Arg,
+    Protocol,
+    Internal,
+    Busy,
+    /// A code this client does not know yet.
+    Other(u32),
+}
+
+impl ErrorCode {
+    pub fn from_num(code: u32) -> ErrorCode {
+        match code {
+            1 => ErrorCode::Unknown,
+            2 => ErrorCode::TooBig,
+            3 => ErrorCode::Type,
+            4 => ErrorCode::Arg,
+            5 => ErrorCode::Protocol,
+            6 => ErrorCode::Internal,
+            7 => ErrorCode::Busy,
+            other => ErrorCode::Other(other),
+        }
+    }
+
+    pub fn as_num(&self) -> u32 {
+        match self {
+            ErrorCode::Unknown => 1,
+            ErrorCode::TooBig => 2,
+            ErrorCode::Type => 3,
+            ErrorCode::Arg => 4,
+            ErrorCode::Protocol => 5,
+            ErrorCode::Internal => 6,
+            ErrorCode::Busy => 7,
+            ErrorCode::Other(code) => *code,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The server answered with an error reply.
+    Server {
+        code: ErrorCode,
+        message: String,
+    },
+    /// The server sent bytes that are not a valid reply.
+    Protocol(String),
+    /// A well-formed reply of a type the command never returns.
+    UnexpectedReply(Value),
+    /// No pooled connection became free in time.
+    PoolTimeout,
+}
+
+impl Error {
+    /// Whether the connection that produced this error can no longer be
+    /// used: once a reply is lost or garbled, later ones can't be matched to
+    /// their requests.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Error::Io(_) | Error::Protocol(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // The I/O error itself is the source, so chains don't repeat it.
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::Server { message, .. } => write!(f, "{}", message),
+            Error::Protocol(reason) => write!(f, "invalid reply: {}", reason),
+            Error::UnexpectedReply(value) => write!(f, "unexpected reply {:?}", value),
+            Error::PoolTimeout => write!(f, "timed out waiting for a pooled connection"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_is_the_source() {
+        let err = Error::from(io::Error::other("connection reset"));
+        let chain = format!("{:#}", anyhow::Error::new(err));
+        assert_eq!(chain, "I/O error: connection reset");
+    }
+}
@@ -0,0 +1,27 @@
+//! A client for crabcache's native protocol.
+//!
+//! ```no_run
+//! use crabcache_client::{Address, Commands, Connection};
+//!
+//! # fn main() -> crabcache_client::Result<()> {
+//! let mut connection = Connection::connect(&Address::from("127.0.0.1:6379"), None)?;
+//! connection.set("greeting", "hello")?;
+//! assert_eq!(connection.get("greeting")?, Some(b"hello".to_vec()));
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod cmd;
+pub mod commands;
+pub mod connection;
+pub mod error;
+pub mod nonblocking;
+pub mod pool;
+pub mod protocol;
+
+pub use cmd::{Arg, Cmd};
+pub use commands::{Bound, Commands, Expiry, ShutdownMode};
+pub use connection::{Address, Connection, Pipeline};
+pub use error::{Error, ErrorCode, Result};
+pub use pool::{Pool, PooledConnection};
+pub use protocol::Value;
@@ -0,0 +1,187 @@
+//! A connection for a mio event loop.
+//!
+//! Requests are queued with `send` and written by `flush` as far as the
+//! socket allows; `read_replies` collects whatever replies have arrived.
+//! Register the connection with a `mio::Poll` like any other source, for
+//! readable and, while `wants_write` is true, writable events.
+
+use crate::{
+    cmd::Cmd,
+    error::{Error, Result},
+    protocol::{decode_reply, Value},
+};
+use mio::{
+    event::Source,
+    net::{TcpStream, UnixStream},
+    Interest, Registry, Token,
+};
+use std::{
+    io::{self, Read, Write},
+    net::SocketAddr,
+    path::Path,
+};
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            Stream::Tcp(stream) => stream,
+            Stream::Unix(stream) => stream,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Whether an I/O error only means the socket is not ready yet. A TCP
+/// connect still in progress reports `NotConnected` on writes.
+fn would_block(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::NotConnected | io::ErrorKind::Interrupted
+    )
+}
+
+pub struct Connection {
+    stream: Stream,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    write_buffer_sent: usize,
+    pending: usize,
+}
+
+impl Connection {
+    /// Starts connecting to `addr`. The connection completes in the
+    /// background; requests can be queued straight away.
+    pub fn connect(addr: SocketAddr) -> Result<Connection> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection::new(Stream::Tcp(stream)))
+    }
+
+    pub fn connect_unix(path: impl AsRef<Path>) -> Result<Connection> {
+        Ok(Connection::new(Stream::Unix(UnixStream::connect(path)?)))
+    }
+
+    fn new(stream: Stream) -> Connection {
+        Connection {
+            stream,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            write_buffer_sent: 0,
+            pending: 0,
+        }
+    }
+
+    /// Queues `cmd` to be written by `flush`.
+    pub fn send(&mut self, cmd: &Cmd) {
+        cmd.encode(&mut self.write_buffer);
+        self.pending += 1;
+    }
+
+    /// The number of requests whose replies have not been read yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Whether queued requests are still waiting to be written.
+    pub fn wants_write(&self) -> bool {
+        self.write_buffer_sent < self.write_buffer.len()
+    }
+
+    /// Writes queued requests until they are all sent, returning true, or
+    /// the socket is full, returning false.
+    pub fn flush(&mut self) -> Result<bool> {
+        while self.wants_write() {
+            match self
+                .stream
+                .write(&self.write_buffer[self.write_buffer_sent..])
+            {
+                Ok(0) => return Err(Error::Io(io::ErrorKind::WriteZero.into())),
+                Ok(written) => self.write_buffer_sent += written,
+                Err(err) if would_block(&err) => return Ok(false),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        self.write_buffer.clear();
+        self.write_buffer_sent = 0;
+        Ok(true)
+    }
+
+    /// Reads until the socket has nothing more and returns the replies that
+    /// are complete, in the order their requests were sent.
+    pub fn read_replies(&mut self) -> Result<Vec<Value>> {
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(read) => self.read_buffer.extend_from_slice(&chunk[..read]),
+                Err(err) if would_block(&err) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let mut replies = Vec::new();
+        let mut used = 0;
+        while let Some((value, length)) = decode_reply(&self.read_buffer[used..])? {
+            used += length;
+            replies.push(value);
+        }
+        self.read_buffer.drain(..used);
+        if replies.len() > self.pending {
+            return Err(Error::Protocol("reply without a request".to_string()));
+        }
+        self.pending -= replies.len();
+        Ok(replies)
+    }
+}
+
+impl Source for Connection {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.stream.source().register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.stream.source().reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.stream.source().deregister(registry)
+    }
+}
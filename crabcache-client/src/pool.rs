@@ -0,0 +1,231 @@
+//! A pool of blocking connections shared between threads.
+//!
+//! Connections are opened on demand up to the pool's size and handed back
+//! when their `PooledConnection` is dropped. One that has sat idle for a
+//! while is pinged before it is handed out again, and one left broken by an
+//! I/O error is closed; either way a fresh connection takes its place, so a
+//! server restart costs callers no more than the requests in flight.
+
+use crate::{
+    commands::Commands,
+    connection::{Address, Connection},
+    error::{Error, Result},
+};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct Builder {
+    address: Address,
+    max_size: usize,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    checkout_timeout: Duration,
+    health_check_after: Duration,
+}
+
+impl Builder {
+    /// The most connections open at once. Defaults to 8.
+    pub fn max_size(mut self, max_size: usize) -> Builder {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    /// How long opening a TCP connection may take. Defaults to 5 seconds.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Builder {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// The `Connection::set_timeout` of every connection. Defaults to none.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Builder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long `Pool::get` waits for a connection to be returned when all
+    /// are in use. Defaults to 30 seconds.
+    pub fn checkout_timeout(mut self, timeout: Duration) -> Builder {
+        self.checkout_timeout = timeout;
+        self
+    }
+
+    /// How long a connection may sit idle before it is pinged on checkout.
+    /// Zero pings every time. Defaults to 30 seconds.
+    pub fn health_check_after(mut self, idle: Duration) -> Builder {
+        self.health_check_after = idle;
+        self
+    }
+
+    /// Creates the pool. No connection is opened until one is needed.
+    pub fn build(self) -> Pool {
+        Pool {
+            shared: Arc::new(Shared {
+                settings: self,
+                state: Mutex::new(State {
+                    idle: Vec::new(),
+                    open: 0,
+                }),
+                returned: Condvar::new(),
+            }),
+        }
+    }
+}
+
+struct Idle {
+    connection: Connection,
+    since: Instant,
+}
+
+struct State {
+    /// Connections ready to be handed out, most recently returned last.
+    idle: Vec<Idle>,
+    /// Idle connections plus those checked out.
+    open: usize,
+}
+
+struct Shared {
+    settings: Builder,
+    state: Mutex<State>,
+    returned: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Gives up a connection's place in the pool.
+    fn forget(&self) {
+        self.lock().open -= 1;
+        self.returned.notify_one();
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        let settings = &self.settings;
+        let mut connection = Connection::connect(&settings.address, settings.connect_timeout)?;
+        connection.set_timeout(settings.timeout)?;
+        Ok(connection)
+    }
+}
+
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+impl Pool {
+    pub fn builder(address: impl Into<Address>) -> Builder {
+        Builder {
+            address: address.into(),
+            max_size: 8,
+            connect_timeout: Some(Duration::from_secs(5)),
+            timeout: None,
+            checkout_timeout: Duration::from_secs(30),
+            health_check_after: Duration::from_secs(30),
+        }
+    }
+
+    /// A pool with the default settings.
+    pub fn new(address: impl Into<Address>) -> Pool {
+        Pool::builder(address).build()
+    }
+
+    /// Hands out a connection, opening one if none is idle and the pool is
+    /// not full, or else waiting for one to be returned.
+    pub fn get(&self) -> Result<PooledConnection> {
+        let shared = &self.shared;
+        let deadline = Instant::now() + shared.settings.checkout_timeout;
+        let mut state = shared.lock();
+        loop {
+            if let Some(idle) = state.idle.pop() {
+                drop(state);
+                let mut connection = idle.connection;
+                if idle.since.elapsed() < shared.settings.health_check_after
+                    || connection.ping().is_ok()
+                {
+                    return Ok(self.wrap(connection));
+                }
+                shared.forget();
+                state = shared.lock();
+                continue;
+            }
+            if state.open < shared.settings.max_size {
+                state.open += 1;
+                drop(state);
+                return match shared.connect() {
+                    Ok(connection) => Ok(self.wrap(connection)),
+                    Err(err) => {
+                        shared.forget();
+                        Err(err)
+                    }
+                };
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::PoolTimeout);
+            }
+            state = shared
+                .returned
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    /// The number of connections open, idle or in use.
+    pub fn open_connections(&self) -> usize {
+        self.shared.lock().open
+    }
+
+    pub fn idle_connections(&self) -> usize {
+        self.shared.lock().idle.len()
+    }
+
+    fn wrap(&self, connection: Connection) -> PooledConnection {
+        PooledConnection {
+            connection: Some(connection),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// A connection checked out of a `Pool`, returned to it on drop.
+pub struct PooledConnection {
+    connection: Option<Connection>,
+    shared: Arc<Shared>,
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let connection = self.connection.take().unwrap();
+        if connection.is_broken() {
+            self.shared.forget();
+            return;
+        }
+        self.shared.lock().idle.push(Idle {
+            connection,
+            since: Instant::now(),
+        });
+        self.shared.returned.notify_one();
+    }
+}
@@ -0,0 +1,170 @@
+//! The native wire format.
+//!
+//! A request is the argument count followed by each argument's length and
+//! bytes. A reply is a frame: its payload length, then one typed value.
+//! Every number is little-endian.
+
+use crate::error::{Error, ErrorCode, Result};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+/// The type bytes that start every value in a reply.
+const NULL: u8 = 0;
+const ERR: u8 = 1;
+const INTEGER: u8 = 2;
+const STRING: u8 = 3;
+const ARRAY: u8 = 4;
+
+/// A decoded reply.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    /// An error reply. `Connection::query` turns these into `Error::Server`;
+    /// pipelines return them in place so the other replies are kept.
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+    Integer(i64),
+    String(Vec<u8>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Turns an error reply into `Err`, passing any other value through.
+    pub fn into_result(self) -> Result<Value> {
+        match self {
+            Value::Error { code, message } => Err(Error::Server { code, message }),
+            value => Ok(value),
+        }
+    }
+}
+
+/// Appends the encoding of a request made of `args` to `out`.
+pub fn encode_request<A: AsRef<[u8]>>(args: &[A], out: &mut Vec<u8>) {
+    out.write_u32::<LittleEndian>(args.len() as u32).unwrap();
+    for arg in args {
+        let arg = arg.as_ref();
+        out.write_u32::<LittleEndian>(arg.len() as u32).unwrap();
+        out.extend_from_slice(arg);
+    }
+}
+
+/// Decodes the reply frame at the start of `buf`, returning it with the
+/// number of bytes it took, or `None` if the frame is not complete yet.
+pub fn decode_reply(buf: &[u8]) -> Result<Option<(Value, usize)>> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let length = LittleEndian::read_u32(&buf[..4]) as usize;
+    let Some(payload) = buf.get(4..4 + length) else {
+        return Ok(None);
+    };
+    let mut decoder = Decoder { buf: payload };
+    let value = decoder.value()?;
+    if !decoder.buf.is_empty() {
+        return Err(Error::Protocol(format!(
+            "{} stray bytes after the reply",
+            decoder.buf.len()
+        )));
+    }
+    Ok(Some((value, 4 + length)))
+}
+
+/// Reads values off the front of one frame's payload.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(Error::Protocol("truncated reply".to_string()));
+        }
+        let (taken, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.take(1)?[0] {
+            NULL => Ok(Value::Nil),
+            ERR => {
+                let code = ErrorCode::from_num(self.u32()?);
+                let length = self.u32()? as usize;
+                let message = String::from_utf8_lossy(self.take(length)?).into_owned();
+                Ok(Value::Error { code, message })
+            }
+            INTEGER => Ok(Value::Integer(LittleEndian::read_i64(self.take(8)?))),
+            STRING => {
+                let length = self.u32()? as usize;
+                Ok(Value::String(self.take(length)?.to_vec()))
+            }
+            ARRAY => {
+                let count = self.u32()? as usize;
+                // Every value takes at least its type byte, which bounds how
+                // many a frame of this size can really hold.
+                let mut items = Vec::with_capacity(count.min(self.buf.len()));
+                for _ in 0..count {
+                    items.push(self.value()?);
+                }
+                Ok(Value::Array(items))
+            }
+            other => Err(Error::Protocol(format!("unknown reply type {}", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut buf = (payload.len() as u32).to_le_bytes().to_vec();
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let mut out = Vec::new();
+        encode_request(&["GET", "key"], &mut out);
+        assert_eq!(
+            out,
+            [
+                &2u32.to_le_bytes()[..],
+                &3u32.to_le_bytes(),
+                b"GET",
+                &3u32.to_le_bytes(),
+                b"key"
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_decode_waits_for_the_whole_frame() {
+        let buf = frame(&[STRING, 5, 0, 0, 0, b'v', b'a', b'l', b'u', b'e']);
+        for end in 0..buf.len() {
+            assert_eq!(decode_reply(&buf[..end]).unwrap(), None);
+        }
+        let mut two = buf.clone();
+        two.extend_from_slice(&buf);
+        assert_eq!(
+            decode_reply(&two).unwrap(),
+            Some((Value::String(b"value".to_vec()), buf.len()))
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_frames() {
+        // The array claims more items than the frame holds.
+        assert!(decode_reply(&frame(&[ARRAY, 2, 0, 0, 0, NULL])).is_err());
+        assert!(decode_reply(&frame(&[NULL, NULL])).is_err());
+        assert!(decode_reply(&frame(&[9])).is_err());
+        assert!(decode_reply(&frame(&[])).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Runs the client against an in-process server on an ephemeral port.
+
+use crabcache::{
+    serialization::{
+        response_array, response_err, response_integer, response_nil, response_string, Output,
+    },
+    Server, ServerHandle,
+};
+use crabcache_client::{
+    nonblocking, protocol::decode_reply, Address, Bound, Cmd, Commands, Connection, Error,
+    ErrorCode, Expiry, Pipeline, Pool, Value,
+};
+use mio::{Events, Interest, Poll, Token};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// A fresh directory for a test's snapshot.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("crabcache-client-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs a server on an ephemeral port.
+fn start(dir: &Path) -> ServerHandle {
+    Server::builder()
+        .dir(dir)
+        .port(0)
+        .build()
+        .unwrap()
+        .spawn()
+        .unwrap()
+}
+
+fn connect(server: &ServerHandle) -> Connection {
+    Connection::connect(&Address::from(server.addr()), None).unwrap()
+}
+
+#[test]
+fn test_decoder_covers_every_reply_type() {
+    let mut out = Output::default();
+    response_array(&mut out, 4);
+    response_nil(&mut out);
+    response_err(&mut out, 3, "WRONGTYPE");
+    response_integer(&mut out, -7);
+    response_string(&mut out, b"value");
+    let mut frame = (out.as_bytes().len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(out.as_bytes());
+
+    let (value, used) = decode_reply(&frame).unwrap().unwrap();
+    assert_eq!(used, frame.len());
+    assert_eq!(
+        value,
+        Value::Array(vec![
+            Value::Nil,
+            Value::Error {
+                code: ErrorCode::Type,
+                message: "WRONGTYPE".to_string()
+            },
+            Value::Integer(-7),
+            Value::String(b"value".to_vec()),
+        ])
+    );
+}
+
+#[test]
+fn test_typed_commands() {
+    let dir = temp_dir("commands");
+    let server = start(&dir);
+    let mut connection = connect(&server);
+
+    connection.ping().unwrap();
+    assert_eq!(connection.echo("hi").unwrap(), b"hi");
+    connection.set("a", "1").unwrap();
+    connection
+        .set_with_expiry("b", b"2", Expiry::Seconds(100))
+        .unwrap();
+    assert_eq!(connection.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(connection.get("missing").unwrap(), None);
+    assert_eq!(
+        connection.mget(&["a", "missing", "b"]).unwrap(),
+        vec![Some(b"1".to_vec()), None, Some(b"2".to_vec())]
+    );
+    assert!((99..=100).contains(&connection.ttl("b").unwrap()));
+    assert!(connection.persist("b").unwrap());
+    assert_eq!(connection.ttl("b").unwrap(), -1);
+    assert_eq!(connection.del(&["a", "b", "missing"]).unwrap(), 2);
+
+    assert_eq!(connection.incr("n").unwrap(), 1);
+    assert_eq!(connection.incrby("n", 41).unwrap(), 42);
+    assert_eq!(connection.decrby("n", 2).unwrap(), 40);
+    assert_eq!(connection.decr("n").unwrap(), 39);
+    assert_eq!(connection.incrbyfloat("n", 0.5).unwrap(), 39.5);
+    assert_eq!(connection.get("n").unwrap(), Some(b"39.5".to_vec()));
+    match connection.incr("n") {
+        Err(Error::Server { code, .. }) => assert_eq!(code, ErrorCode::Arg),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    assert_eq!(connection.zadd("z", &[(1.0, "x"), (2.5, "y")]).unwrap(), 2);
+    assert_eq!(connection.zincrby("z", 2.0, "x").unwrap(), 3.0);
+    assert_eq!(
+        connection.zrange_with_scores("z", 0, -1).unwrap(),
+        vec![(b"y".to_vec(), 2.5), (b"x".to_vec(), 3.0)]
+    );
+    assert_eq!(
+        connection
+            .zrangebyscore(
+                "z",
+                Bound::Exclusive(2.5),
+                Bound::Inclusive(f64::INFINITY),
+                None
+            )
+            .unwrap(),
+        vec![b"x".to_vec()]
+    );
+    assert_eq!(connection.zrank("z", "x").unwrap(), Some(1));
+    assert_eq!(connection.zscore("z", "nope").unwrap(), None);
+    match connection.get("z") {
+        Err(Error::Server { code, .. }) => assert_eq!(code, ErrorCode::Type),
+        other => panic!("expected a type error, got {:?}", other),
+    }
+    // An error reply leaves the connection usable.
+    assert!(!connection.is_broken());
+
+    connection.client_setname("typed").unwrap();
+    assert_eq!(
+        connection.client_getname().unwrap().as_deref(),
+        Some("typed")
+    );
+    assert!(connection.client_list().unwrap().contains("name=typed"));
+    assert!(connection
+        .info(&["clients"])
+        .unwrap()
+        .contains("connected_clients:1"));
+    assert_eq!(
+        connection.config_get("maxclients").unwrap(),
+        vec![("maxclients".to_string(), "10000".to_string())]
+    );
+    assert!(connection.command_count().unwrap() > 0);
+
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pipeline() {
+    let dir = temp_dir("pipeline");
+    let server = start(&dir);
+    let mut connection = connect(&server);
+
+    let mut pipeline = Pipeline::new();
+    for i in 0..100 {
+        pipeline.add(Cmd::new("SET").arg(format!("k{}", i)).arg(i));
+    }
+    pipeline.add(Cmd::new("ZCARD").arg("k0"));
+    pipeline.add(Cmd::new("GET").arg("k99"));
+    let replies = pipeline.execute(&mut connection).unwrap();
+    assert_eq!(replies.len(), 102);
+    assert!(replies[..100].iter().all(|reply| *reply == Value::Nil));
+    assert!(matches!(
+        replies[100],
+        Value::Error {
+            code: ErrorCode::Type,
+            ..
+        }
+    ));
+    assert_eq!(replies[101], Value::String(b"99".to_vec()));
+
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_nonblocking_connection() {
+    let dir = temp_dir("nonblocking");
+    let server = start(&dir);
+    let mut poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(16);
+    let mut connection = nonblocking::Connection::connect(server.addr()).unwrap();
+    poll.registry()
+        .register(
+            &mut connection,
+            Token(0),
+            Interest::READABLE | Interest::WRITABLE,
+        )
+        .unwrap();
+
+    connection.send(&Cmd::new("SET").arg("key").arg("value"));
+    connection.send(&Cmd::new("GET").arg("key"));
+    let mut replies = Vec::new();
+    while connection.pending() > 0 {
+        poll.poll(&mut events, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(!events.is_empty(), "timed out");
+        for event in &events {
+            if event.is_writable() {
+                connection.flush().unwrap();
+            }
+            if event.is_readable() {
+                replies.extend(connection.read_replies().unwrap());
+            }
+        }
+    }
+    assert_eq!(replies, [Value::Nil, Value::String(b"value".to_vec())]);
+
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pool_replaces_broken_connections() {
+    let dir = temp_dir("pool");
+    let server = start(&dir);
+    let pool = Pool::builder(server.addr())
+        .max_size(1)
+        .checkout_timeout(Duration::from_millis(100))
+        .health_check_after(Duration::ZERO)
+        .build();
+
+    let id = {
+        let mut pooled = pool.get().unwrap();
+        // The only connection is in use.
+        assert!(matches!(pool.get(), Err(Error::PoolTimeout)));
+        pooled.client_id().unwrap()
+    };
+    assert_eq!(pool.idle_connections(), 1);
+
+    // Killed while idle: the health check notices and reconnects.
+    assert!(connect(&server).client_kill_id(id).unwrap());
+    let mut pooled = pool.get().unwrap();
+    let id = pooled.client_id().unwrap();
+    drop(pooled);
+
+    // Killed while in use: the failed request breaks the connection, which
+    // is closed instead of being returned.
+    let mut pooled = pool.get().unwrap();
+    assert!(connect(&server).client_kill_id(id).unwrap());
+    assert!(pooled.ping().unwrap_err().is_fatal());
+    assert!(pooled.is_broken());
+    drop(pooled);
+    assert_eq!(pool.open_connections(), 0);
+    pool.get().unwrap().ping().unwrap();
+    assert_eq!(pool.open_connections(), 1);
+
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,708 @@
+//! Append-only file. Every write is logged as a request frame in the wire
+//! format, so the keyspace can be rebuilt on startup by replaying the log.
+
+use crate::{
+    commands::{
+        self, decode_request, format_float, request_length, set::Expiry, Command, Request,
+        RequestLimits,
+    },
+    entry::{now_ms, Data, Value},
+    fork::{self, Child},
+    serialization::Output,
+};
+use anyhow::{anyhow, Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use log::{error, warn};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_PATH: &str = "appendonly.aof";
+
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When appended writes are forced to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every write, before its reply is sent.
+    Always,
+    /// At most once a second, off the event loop thread.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn parse(value: &str) -> Option<FsyncPolicy> {
+        match value.to_ascii_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "everysec" => Some(FsyncPolicy::EverySec),
+            "no" => Some(FsyncPolicy::No),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        }
+    }
+}
+
+fn write_request(out: &mut Vec<u8>, args: &[&[u8]]) {
+    out.write_u32::<LittleEndian>(args.len() as u32).unwrap();
+    for arg in args {
+        out.write_u32::<LittleEndian>(arg.len() as u32).unwrap();
+        out.extend_from_slice(arg);
+    }
+}
+
+/// The request frames to log for `command`, or `None` if it does not write.
+/// Relative deadlines are resolved against `now`, so replaying the log later
+/// expires keys at the same instant.
+pub fn record(command: &Command, now: u64) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    match command {
+        Command::Set(key, value, expiry) => {
+            let deadline = match expiry {
+                Expiry::Clear | Expiry::KeepTtl => None,
+                Expiry::After(millis) => Some(now.saturating_add(*millis)),
+                Expiry::At(deadline) => Some(*deadline),
+            };
+            match (expiry, deadline) {
+                (_, Some(deadline)) => {
+                    let deadline = deadline.to_string();
+                    write_request(
+                        &mut out,
+                        &[b"SET", key, value, b"PXAT", deadline.as_bytes()],
+                    )
+                }
+                (Expiry::KeepTtl, None) => {
+                    write_request(&mut out, &[b"SET", key, value, b"KEEPTTL"])
+                }
+                _ => write_request(&mut out, &[b"SET", key, value]),
+            }
+        }
+        Command::Del(keys) => {
+            let mut args: Vec<&[u8]> = vec![b"DEL"];
+            args.extend(keys.iter().map(Vec::as_slice));
+            write_request(&mut out, &args);
+        }
+        Command::MSet(pairs) => {
+            let mut args: Vec<&[u8]> = vec![b"MSET"];
+            for (key, value) in pairs {
+                args.push(key);
+                args.push(value);
+            }
+            write_request(&mut out, &args);
+        }
+        Command::Expire(key, seconds) => {
+            let deadline = (now as i64).saturating_add(seconds.saturating_mul(1000));
+            write_request(
+                &mut out,
+                &[b"PEXPIREAT", key, deadline.to_string().as_bytes()],
+            )
+        }
+        Command::PExpire(key, millis) => {
+            let deadline = (now as i64).saturating_add(*millis);
+            write_request(
+                &mut out,
+                &[b"PEXPIREAT", key, deadline.to_string().as_bytes()],
+            )
+        }
+        Command::ExpireAt(key, seconds) => {
+            let deadline = seconds.saturating_mul(1000);
+            write_request(
+                &mut out,
+                &[b"PEXPIREAT", key, deadline.to_string().as_bytes()],
+            )
+        }
+        Command::PExpireAt(key, deadline) => write_request(
+            &mut out,
+            &[b"PEXPIREAT", key, deadline.to_string().as_bytes()],
+        ),
+        Command::Persist(key) => write_request(&mut out, &[b"PERSIST", key]),
+        Command::ZAdd(key, pairs) => {
+            let scores: Vec<String> = pairs
+                .iter()
+                .map(|(score, _)| format_float(*score))
+                .collect();
+            let mut args: Vec<&[u8]> = vec![b"ZADD", key];
+            for ((_, member), score) in pairs.iter().zip(&scores) {
+                args.push(score.as_bytes());
+                args.push(member);
+            }
+            write_request(&mut out, &args);
+        }
+        Command::ZRem(key, members) => {
+            let mut args: Vec<&[u8]> = vec![b"ZREM", key];
+            args.extend(members.iter().map(Vec::as_slice));
+            write_request(&mut out, &args);
+        }
+        Command::ZIncrBy(key, increment, member) => {
+            let increment = format_float(*increment);
+            write_request(&mut out, &[b"ZINCRBY", key, increment.as_bytes(), member]);
+        }
+        Command::IncrBy(key, increment) => {
+            let increment = increment.to_string();
+            write_request(&mut out, &[b"INCRBY", key, increment.as_bytes()]);
+        }
+        Command::IncrByFloat(key, increment) => {
+            let increment = format_float(*increment);
+            write_request(&mut out, &[b"INCRBYFLOAT", key, increment.as_bytes()]);
+        }
+        _ => return None,
+    }
+    Some(out)
+}
+
+/// Writes the shortest log that recreates `data`: one write per key, or per
+/// member for sorted sets, followed by its deadline.
+fn write_rewrite<W: Write>(data: &Data, out: &mut W) -> io::Result<()> {
+    let now = now_ms();
+    let mut frames = Vec::new();
+    for entry in data.entries() {
+        let Some(value) = &entry.value else {
+            continue;
+        };
+        if entry.expire_at().is_some_and(|deadline| deadline <= now) {
+            continue;
+        }
+        frames.clear();
+        match value {
+            Value::String(_) | Value::Integer(_) => {
+                let string = value.string_bytes().unwrap();
+                write_request(&mut frames, &[b"SET", &entry.key, &string]);
+            }
+            Value::ZSet(zset) => {
+                for (member, score) in zset.iter() {
+                    let score = format_float(score);
+                    write_request(
+                        &mut frames,
+                        &[b"ZADD", &entry.key, score.as_bytes(), member],
+                    );
+                }
+            }
+        }
+        if let Some(deadline) = entry.expire_at() {
+            let deadline = deadline.to_string();
+            write_request(
+                &mut frames,
+                &[b"PEXPIREAT", &entry.key, deadline.as_bytes()],
+            );
+        }
+        out.write_all(&frames)?;
+    }
+    out.flush()
+}
+
+fn rewrite_to(data: &Data, path: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_rewrite(data, &mut writer)?;
+    writer.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// Replays the log at `path`, handing each request to `apply`, and returns
+/// how many were replayed. A record cut short by a crash is dropped and the
+/// file truncated to the last complete one; anything else that does not
+/// parse is an error.
+pub fn replay(path: &Path, mut apply: impl FnMut(Request) -> Result<()>) -> Result<usize> {
+    let log = match fs::read(path) {
+        Ok(log) => log,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut pos = 0;
+    let mut replayed = 0;
+    while pos < log.len() {
+        let context = || format!("corrupt record at byte {} of {}", pos, path.display());
+        let Some(len) =
+            request_length(&log[pos..], RequestLimits::unlimited()).with_context(context)?
+        else {
+            warn!(
+                "Dropping truncated record at byte {} of {}",
+                pos,
+                path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(pos as u64)?;
+            break;
+        };
+        let request = decode_request(&log[pos..pos + len])
+            .and_then(|args| Request::parse(&args))
+            .with_context(context)?;
+        apply(request).with_context(context)?;
+        pos += len;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+/// Replays the log at `path` into `data`. Keys are not expired while it
+/// runs: each write meets the keys it met when it was made, and a key that
+/// expired then was logged as deleted.
+pub fn load(path: &Path, data: &mut Data) -> Result<usize> {
+    data.set_loading(true);
+    let replayed = replay(path, |request| {
+        commands::execute(data, request, &mut Output::default())
+    });
+    data.set_loading(false);
+    replayed
+}
+
+/// A BGREWRITEAOF in progress. Writes made while the child runs go to the
+/// current log as usual and are also kept in `buffer`, to be appended to the
+/// rewritten log before it replaces the current one.
+struct Rewrite {
+    child: Child,
+    temp: PathBuf,
+    buffer: Vec<u8>,
+}
+
+pub struct Aof {
+    path: PathBuf,
+    file: File,
+    policy: FsyncPolicy,
+    last_fsync: Instant,
+    unsynced: bool,
+    fsync_thread: Option<JoinHandle<()>>,
+    rewrite: Option<Rewrite>,
+    /// Started once the background save that is running finishes.
+    rewrite_scheduled: bool,
+    /// Why the last append failed. The log is missing a write from then on,
+    /// so nothing more is appended until a rewrite replaces it.
+    write_error: Option<String>,
+}
+
+impl Aof {
+    pub fn open(path: impl Into<PathBuf>, policy: FsyncPolicy) -> Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(Self {
+            path,
+            file,
+            policy,
+            last_fsync: Instant::now(),
+            unsynced: false,
+            fsync_thread: None,
+            rewrite: None,
+            rewrite_scheduled: false,
+            write_error: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn policy(&self) -> FsyncPolicy {
+        self.policy
+    }
+
+    /// Switches fsync policy. Writes appended under the old policy are
+    /// covered by the next sync the new one makes.
+    pub fn set_policy(&mut self, policy: FsyncPolicy) {
+        self.policy = policy;
+    }
+
+    /// Logs a write that has already been applied. A failure leaves the log
+    /// broken, as reported by `write_error`.
+    pub fn append(&mut self, frames: &[u8]) -> Result<()> {
+        if let Some(err) = &self.write_error {
+            return Err(anyhow!("the log is missing writes since: {}", err));
+        }
+        // Buffered first, so a rewrite that is running still picks the write
+        // up if the log cannot.
+        if let Some(rewrite) = &mut self.rewrite {
+            rewrite.buffer.extend_from_slice(frames);
+        }
+        let result = self
+            .file
+            .write_all(frames)
+            .and_then(|()| match self.policy {
+                FsyncPolicy::Always => self.file.sync_data(),
+                FsyncPolicy::EverySec => {
+                    self.unsynced = true;
+                    Ok(())
+                }
+                FsyncPolicy::No => Ok(()),
+            });
+        if let Err(err) = &result {
+            self.write_error = Some(err.to_string());
+        }
+        Ok(result?)
+    }
+
+    /// Why the log stopped taking writes, if it has. Writes should be
+    /// refused until a rewrite, which starts from the keyspace itself,
+    /// succeeds.
+    pub fn write_error(&self) -> Option<&str> {
+        self.write_error.as_deref()
+    }
+
+    /// How long the event loop may sleep before `tick` has work to do.
+    pub fn next_tick(&self) -> Option<Duration> {
+        if self.policy != FsyncPolicy::EverySec || !self.unsynced {
+            return None;
+        }
+        Some(FSYNC_INTERVAL.saturating_sub(self.last_fsync.elapsed()))
+    }
+
+    /// Starts the once-a-second fsync of the `everysec` policy when it is
+    /// due. The fsync runs on its own thread so a slow disk does not stall
+    /// the event loop; a new one is not started while the last is running.
+    pub fn tick(&mut self) {
+        if self.next_tick().is_none_or(|wait| !wait.is_zero()) {
+            return;
+        }
+        if self
+            .fsync_thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+        {
+            return;
+        }
+        match self.file.try_clone() {
+            Ok(file) => {
+                self.fsync_thread = Some(thread::spawn(move || {
+                    if let Err(err) = file.sync_data() {
+                        error!("Failed to fsync the append only file: {}", err);
+                    }
+                }));
+                self.last_fsync = Instant::now();
+                self.unsynced = false;
+            }
+            Err(err) => error!("Failed to fsync the append only file: {}", err),
+        }
+    }
+
+    /// Forces everything appended so far to disk, waiting for an `everysec`
+    /// fsync that is already running.
+    pub fn sync(&mut self) -> Result<()> {
+        if let Some(thread) = self.fsync_thread.take() {
+            let _ = thread.join();
+        }
+        self.file.sync_data()?;
+        self.last_fsync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewrite.is_some()
+    }
+
+    /// Asks for a rewrite once the background save that is running has
+    /// finished, as the two are not run at the same time.
+    pub fn schedule_rewrite(&mut self) {
+        self.rewrite_scheduled = true;
+    }
+
+    pub fn rewrite_scheduled(&self) -> bool {
+        self.rewrite_scheduled
+    }
+
+    /// Rewrites the log from a forked child's view of `data`. Call `reap`
+    /// from the event loop to finish the rewrite once the child exits.
+    pub fn background_rewrite(&mut self, data: &Data) -> Result<()> {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".rewrite");
+        let temp = self.path.with_file_name(name);
+        self.rewrite_scheduled = false;
+        let child = fork::spawn("Background AOF rewrite", || rewrite_to(data, &temp))?;
+        self.rewrite = Some(Rewrite {
+            child,
+            temp,
+            buffer: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Kills a running rewrite and discards what it wrote. The current log
+    /// is untouched.
+    pub fn abort_rewrite(&mut self) {
+        self.rewrite_scheduled = false;
+        if let Some(rewrite) = self.rewrite.take() {
+            rewrite.child.kill();
+            let _ = fs::remove_file(&rewrite.temp);
+        }
+    }
+
+    /// Completes a rewrite whose child has exited by appending the writes
+    /// made in the meantime and renaming the new log over the old one.
+    /// Returns `None` while no rewrite has finished.
+    pub fn reap(&mut self) -> Option<Result<()>> {
+        let success = self.rewrite.as_ref()?.child.try_wait()?;
+        let rewrite = self.rewrite.take()?;
+        let result = if success {
+            self.finish_rewrite(&rewrite)
+        } else {
+            Err(anyhow!("the rewrite child failed"))
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&rewrite.temp);
+        }
+        Some(result)
+    }
+
+    fn finish_rewrite(&mut self, rewrite: &Rewrite) -> Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&rewrite.temp)?;
+        file.write_all(&rewrite.buffer)?;
+        file.sync_all()?;
+        fs::rename(&rewrite.temp, &self.path)?;
+        self.file = file;
+        // The new log holds every write, including any the old one missed.
+        self.write_error = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{self, table};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crabcache-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Makes the write and logs it as the server does, after deletions for
+    /// the keys it found expired.
+    fn apply(aof: &mut Aof, data: &mut Data, request: &str) {
+        let args: Vec<Vec<u8>> = request.split(' ').map(|arg| arg.into()).collect();
+        let request = Request::parse(&args).unwrap();
+        let frames = record(&request.command, now_ms()).unwrap();
+        commands::execute(data, request, &mut Output::default()).unwrap();
+        let expired = data.take_expired();
+        if !expired.is_empty() {
+            aof.append(&record(&Command::Del(expired), now_ms()).unwrap())
+                .unwrap();
+        }
+        aof.append(&frames).unwrap();
+    }
+
+    #[test]
+    fn test_only_writes_are_recorded() {
+        let requests = [
+            "get k",
+            "set k v",
+            "del k",
+            "mget k",
+            "mset k v",
+            "incr k",
+            "decr k",
+            "incrby k 1",
+            "decrby k 1",
+            "incrbyfloat k 1",
+            "expire k 1",
+            "pexpire k 1",
+            "expireat k 1",
+            "pexpireat k 1",
+            "ttl k",
+            "pttl k",
+            "persist k",
+            "zadd z 1 a",
+            "zrem z a",
+            "zscore z a",
+            "zrange z 0 1",
+            "zrevrange z 0 1",
+            "zrangebyscore z 0 1",
+            "zrank z a",
+            "zcard z",
+            "zincrby z 1 a",
+        ];
+        for request in requests {
+            let args: Vec<Vec<u8>> = request.split(' ').map(|arg| arg.into()).collect();
+            let Request { spec, command } = Request::parse(&args).unwrap();
+            assert_eq!(
+                record(&command, 0).is_some(),
+                spec.has_flag(table::WRITE),
+                "{}",
+                request
+            );
+        }
+        // Every keyspace command is covered.
+        let keyspace = table::commands()
+            .iter()
+            .filter(|spec| spec.has_flag(table::WRITE) || spec.has_flag(table::READONLY));
+        assert_eq!(keyspace.count(), requests.len());
+    }
+
+    #[test]
+    fn test_record_resolves_relative_deadlines() {
+        let now = 1_000_000;
+        let set = Command::Set(b"k".to_vec(), b"v".to_vec(), Expiry::After(500));
+        let mut expected = Vec::new();
+        write_request(&mut expected, &[b"SET", b"k", b"v", b"PXAT", b"1000500"]);
+        assert_eq!(record(&set, now), Some(expected));
+
+        let expire = Command::Expire(b"k".to_vec(), 2);
+        let mut expected = Vec::new();
+        write_request(&mut expected, &[b"PEXPIREAT", b"k", b"1002000"]);
+        assert_eq!(record(&expire, now), Some(expected));
+
+        assert_eq!(record(&Command::Get(b"k".to_vec()), now), None);
+    }
+
+    #[test]
+    fn test_append_and_replay() {
+        let dir = temp_dir("aof");
+        let path = dir.join(DEFAULT_PATH);
+        let mut aof = Aof::open(&path, FsyncPolicy::Always).unwrap();
+        let commands = [
+            Command::Set(b"a".to_vec(), b"1".to_vec(), Expiry::Clear),
+            Command::Set(b"b".to_vec(), b"2".to_vec(), Expiry::After(60_000)),
+            Command::ZAdd(
+                b"z".to_vec(),
+                vec![(1.5, b"x".to_vec()), (-2.0, b"y".to_vec())],
+            ),
+            Command::ZIncrBy(b"z".to_vec(), 1.0, b"y".to_vec()),
+            Command::MSet(vec![
+                (b"c".to_vec(), b"3".to_vec()),
+                (b"d".to_vec(), b"4".to_vec()),
+            ]),
+            Command::Del(vec![b"a".to_vec(), b"c".to_vec()]),
+            Command::IncrBy(b"n".to_vec(), -3),
+            Command::IncrByFloat(b"f".to_vec(), 0.25),
+        ];
+        for command in &commands {
+            aof.append(&record(command, now_ms()).unwrap()).unwrap();
+        }
+        // A crash in the middle of a write leaves a partial record behind.
+        let mut partial = record(&commands[0], now_ms()).unwrap();
+        partial.truncate(partial.len() - 1);
+        aof.append(&partial).unwrap();
+
+        let mut data = Data::new();
+        assert_eq!(load(&path, &mut data).unwrap(), commands.len());
+        assert!(data.lookup(b"a").is_none());
+        assert!(data.lookup(b"c").is_none());
+        assert!(data.lookup(b"d").is_some());
+        assert!(data.lookup(b"b").unwrap().expire_at().is_some());
+        assert!(matches!(
+            data.lookup(b"n").unwrap().value,
+            Some(Value::Integer(-3))
+        ));
+        match &data.lookup(b"f").unwrap().value {
+            Some(Value::String(string)) => assert_eq!(string, b"0.25"),
+            _ => panic!("expected a string"),
+        }
+        match &data.lookup(b"z").unwrap().value {
+            Some(Value::ZSet(zset)) => {
+                let members: Vec<_> = zset.iter().collect();
+                assert_eq!(members, vec![(&b"y"[..], -1.0), (&b"x"[..], 1.5)]);
+            }
+            _ => panic!("expected a sorted set"),
+        }
+        // The partial record was cut off, so a second replay sees a clean log.
+        let mut data = Data::new();
+        assert_eq!(load(&path, &mut data).unwrap(), commands.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_keeps_deadlines_of_later_writes() {
+        let dir = temp_dir("aof-expiry");
+        let path = dir.join(DEFAULT_PATH);
+        let mut aof = Aof::open(&path, FsyncPolicy::Always).unwrap();
+        let mut data = Data::new();
+        for request in [
+            "set k 5 px 100",
+            "incrby k 1",
+            "set j x px 100",
+            "set j y keepttl",
+            "zadd z 1 a",
+            "pexpire z 100",
+            "zincrby z 1 a",
+        ] {
+            apply(&mut aof, &mut data, request);
+        }
+        thread::sleep(Duration::from_millis(200));
+
+        // Restarted past the deadlines, with nothing logged since.
+        let mut restored = Data::new();
+        assert_eq!(load(&path, &mut restored).unwrap(), 7);
+        for key in [&b"k"[..], b"j", b"z"] {
+            assert!(restored.lookup(key).is_none());
+        }
+
+        // A write after the key expired starts it afresh, without a TTL.
+        apply(&mut aof, &mut data, "incrby k 1");
+        let mut restored = Data::new();
+        assert_eq!(load(&path, &mut restored).unwrap(), 9);
+        let k = restored.lookup(b"k").unwrap();
+        assert!(matches!(k.value, Some(Value::Integer(1))));
+        assert_eq!(k.expire_at(), None);
+        assert!(restored.lookup(b"j").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_append_breaks_the_log() {
+        let dir = temp_dir("aof-broken");
+        let path = dir.join(DEFAULT_PATH);
+        let mut aof = Aof::open(&path, FsyncPolicy::Always).unwrap();
+        let set = Command::Set(b"k".to_vec(), b"v".to_vec(), Expiry::Clear);
+        let frames = record(&set, now_ms()).unwrap();
+        aof.append(&frames).unwrap();
+        assert_eq!(aof.write_error(), None);
+
+        // A descriptor opened only for reading refuses the write.
+        aof.file = File::open(&path).unwrap();
+        assert!(aof.append(&frames).is_err());
+        assert!(aof.write_error().is_some());
+        // Even once the file works again, the log stays short of the write
+        // it missed until a rewrite replaces it.
+        aof.file = OpenOptions::new().append(true).open(&path).unwrap();
+        assert!(aof.append(&frames).is_err());
+        assert_eq!(load(&path, &mut Data::new()).unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_recreates_keyspace() {
+        let mut data = Data::new();
+        data.lookup_or_insert(b"s").value = Some(Value::String(b"v".to_vec()));
+        data.set_expiry(b"s", Some(now_ms() + 60_000));
+        data.lookup_or_insert(b"n").value = Some(Value::Integer(-7));
+        let mut zset = crate::zset::ZSet::new();
+        zset.insert(b"m", f64::INFINITY);
+        data.lookup_or_insert(b"z").value = Some(Value::ZSet(Box::new(zset)));
+
+        let dir = temp_dir("aof-rewrite");
+        let path = dir.join(DEFAULT_PATH);
+        rewrite_to(&data, &path).unwrap();
+        let mut restored = Data::new();
+        assert_eq!(load(&path, &mut restored).unwrap(), 4);
+        match &restored.lookup(b"n").unwrap().value {
+            Some(Value::String(string)) => assert_eq!(string, b"-7"),
+            _ => panic!("expected a string"),
+        }
+        assert_eq!(
+            restored.lookup(b"s").unwrap().expire_at(),
+            data.lookup(b"s").unwrap().expire_at()
+        );
+        match &restored.lookup(b"z").unwrap().value {
+            Some(Value::ZSet(zset)) => {
+                assert_eq!(zset.iter().next(), Some((&b"m"[..], f64::INFINITY)))
+            }
+            _ => panic!("expected a sorted set"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,2 @@
-
-
 pub mod node;
 pub mod set;
-
-
-
-
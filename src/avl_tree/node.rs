@@ -1,7 +1,6 @@
 use std::{cmp::max, fmt::Debug};
 pub type AvlTree<T> = Option<Box<AvlNode<T>>>;
 
-
 #[derive(Debug)]
 pub enum AVLNodeError {
     RotateError(RotateError),
@@ -18,6 +17,8 @@ pub enum RotateError {
 pub struct AvlNode<T: Ord> {
     pub value: T,
     pub height: usize,
+    /// Number of nodes in the subtree rooted here, used for rank queries.
+    pub size: usize,
     pub left: AvlTree<T>,
     pub right: AvlTree<T>,
 }
@@ -33,6 +34,22 @@ impl<T: Ord> AvlNode<T> {
         self.height = 1 + max(self.left_height(), self.right_height());
     }
 
+    pub fn left_size(&self) -> usize {
+        self.left.as_ref().map_or(0, |l| l.size)
+    }
+    pub fn right_size(&self) -> usize {
+        self.right.as_ref().map_or(0, |r| r.size)
+    }
+    pub fn update_size(&mut self) {
+        self.size = 1 + self.left_size() + self.right_size();
+    }
+
+    /// Recomputes the augmented fields from the children.
+    pub fn update(&mut self) {
+        self.update_height();
+        self.update_size();
+    }
+
     pub fn balance_factor(&self) -> i8 {
         self.left_height() as i8 - self.right_height() as i8
     }
@@ -40,20 +57,20 @@ impl<T: Ord> AvlNode<T> {
     pub fn rotate_right(&mut self) -> Result<(), RotateError> {
         let mut left_node = self.left.take().ok_or(RotateError::NoLeftChild)?;
         self.left = left_node.right.take();
-        self.update_height();
+        self.update();
         std::mem::swap(self, &mut left_node);
         self.right = Some(left_node);
-        self.update_height();
+        self.update();
         Ok(())
     }
 
     pub fn rotate_left(&mut self) -> Result<(), RotateError> {
         let mut right_node = self.right.take().ok_or(RotateError::NoRightChild)?;
         self.right = right_node.left.take();
-        self.update_height();
+        self.update();
         std::mem::swap(self, right_node.as_mut());
         self.left = Some(right_node);
-        self.update_height();
+        self.update();
         Ok(())
     }
 
@@ -77,7 +94,7 @@ impl<T: Ord> AvlNode<T> {
                 self.rotate_right().map_err(AVLNodeError::RotateError)?;
                 Ok(())
             }
-            _ => Ok({}),
+            _ => Ok(()),
         }
     }
 }
@@ -1,6 +1,12 @@
-use quickcheck::{Arbitrary, Gen};
-use std::{cmp::Ordering, fmt::Debug, mem::swap};
 use super::node::{AVLNodeError, AvlNode, AvlTree};
+#[cfg(test)]
+use quickcheck::{Arbitrary, Gen};
+use std::{
+    cmp::Ordering,
+    fmt::Debug,
+    mem::swap,
+    ops::{Bound, RangeBounds},
+};
 
 #[derive(Debug)]
 pub enum AvlTreeSetError {
@@ -20,10 +26,16 @@ pub enum DeleteError {
 }
 
 #[derive(Debug, PartialEq, Clone)]
-struct AvlTreeSet<T: Ord> {
+pub struct AvlTreeSet<T: Ord> {
     root: AvlTree<T>,
 }
 
+impl<T: Ord + Debug> Default for AvlTreeSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a, T: 'a + Ord + Debug> AvlTreeSet<T> {
     pub fn new() -> Self {
         Self { root: None }
@@ -58,7 +70,7 @@ impl<'a, T: 'a + Ord + Debug> AvlTreeSet<T> {
                     if parent
                         .left
                         .as_ref()
-                        .map_or(false, |left_node| target.value == left_node.value)
+                        .is_some_and(|left_node| target.value == left_node.value)
                     {
                         parent.left = None
                     } else {
@@ -74,13 +86,9 @@ impl<'a, T: 'a + Ord + Debug> AvlTreeSet<T> {
             }
             (true, true) => {
                 let right_tree = &mut target.right;
-                if right_tree
-                    .as_ref()
-                    .map_or(false, |node| node.left.is_none())
-                {
+                if right_tree.as_ref().is_some_and(|node| node.left.is_none()) {
                     let mut right_node = right_tree.take().unwrap();
                     right_node.left = target.left.take();
-                    right_node.right = target.right.take();
                     swap(target, &mut right_node);
                 } else {
                     let mut next_tree = right_tree;
@@ -93,21 +101,21 @@ impl<'a, T: 'a + Ord + Debug> AvlTreeSet<T> {
                     }
                     let parent_left_node = unsafe { &mut *inner_path.pop().unwrap() };
                     let mut leftmost_node = parent_left_node.left.take().unwrap();
+                    parent_left_node.left = leftmost_node.right.take();
 
                     leftmost_node.left = target.left.take();
                     leftmost_node.right = target.right.take();
 
                     swap(target, &mut leftmost_node);
-                    swap(&mut parent_left_node.left, &mut leftmost_node.right);
 
-                    parent_left_node.update_height();
+                    parent_left_node.update();
                     parent_left_node
                         .rebalance()
                         .map_err(DeleteError::AvlError)?;
 
                     Self::rebalance_path(&inner_path)?;
                 }
-                target.update_height();
+                target.update();
                 target.rebalance().map_err(DeleteError::AvlError)?;
             }
         };
@@ -115,10 +123,12 @@ impl<'a, T: 'a + Ord + Debug> AvlTreeSet<T> {
         Self::rebalance_path(&path)
     }
 
+    /// Fixes heights and balance along a root-to-leaf `path`, deepest node
+    /// first.
     fn rebalance_path(path: &[*mut AvlNode<T>]) -> Result<(), DeleteError> {
-        for node_ptr in path {
+        for node_ptr in path.iter().rev() {
             let node = unsafe { &mut **node_ptr };
-            node.update_height();
+            node.update();
             node.rebalance().map_err(DeleteError::AvlError)?;
         }
 
@@ -140,23 +150,203 @@ impl<'a, T: 'a + Ord + Debug> AvlTreeSet<T> {
         *current_tree = Some(Box::new(AvlNode {
             value,
             height: 1,
+            size: 1,
             left: None,
             right: None,
         }));
 
         for node_ptr in prev_ptrs.into_iter().rev() {
             let node = unsafe { &mut *node_ptr };
-            node.update_height();
+            node.update();
             node.rebalance().map_err(InsertError::AvlError)?;
         }
 
         Ok(())
     }
 
-    pub fn iter(&'a self) -> impl Iterator<Item = &'a T> + 'a {
-        self.node_iter().map(|node| &node.value)
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |root| root.size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Zero-based position of `value` in ascending order, in O(log n).
+    pub fn rank(&self, value: &T) -> Option<usize> {
+        let mut rank = 0;
+        let mut current = &self.root;
+        while let Some(node) = current {
+            match value.cmp(&node.value) {
+                Ordering::Less => current = &node.left,
+                Ordering::Greater => {
+                    rank += node.left_size() + 1;
+                    current = &node.right;
+                }
+                Ordering::Equal => return Some(rank + node.left_size()),
+            }
+        }
+        None
+    }
+
+    /// The element at zero-based position `k` in ascending order, in O(log n).
+    pub fn select(&self, mut k: usize) -> Option<&T> {
+        let mut current = &self.root;
+        while let Some(node) = current {
+            let left_size = node.left_size();
+            match k.cmp(&left_size) {
+                Ordering::Less => current = &node.left,
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Greater => {
+                    k -= left_size + 1;
+                    current = &node.right;
+                }
+            }
+        }
+        None
+    }
+
+    /// The element `delta` positions after `value` (before it if negative),
+    /// or `None` if `value` is absent or the target falls outside the set.
+    pub fn offset(&self, value: &T, delta: i64) -> Option<&T> {
+        let rank = self.rank(value)? as i64;
+        let target = rank.checked_add(delta)?;
+        if target < 0 {
+            return None;
+        }
+        self.select(target as usize)
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        let mut node = self.root.as_ref()?;
+        while let Some(left) = &node.left {
+            node = left;
+        }
+        Some(&node.value)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        let mut node = self.root.as_ref()?;
+        while let Some(right) = &node.right {
+            node = right;
+        }
+        Some(&node.value)
+    }
+
+    pub fn pop_first(&mut self) -> Option<T> {
+        let mut path = Vec::<*mut AvlNode<T>>::new();
+        let mut current = &mut self.root;
+        while current.as_ref()?.left.is_some() {
+            let node = current.as_mut().unwrap();
+            path.push(&mut **node);
+            current = &mut node.left;
+        }
+        let mut first = current.take()?;
+        *current = first.right.take();
+        // Rotations cannot fail on a tree that was balanced before the removal.
+        let _ = Self::rebalance_path(&path);
+        Some(first.value)
+    }
+
+    pub fn pop_last(&mut self) -> Option<T> {
+        let mut path = Vec::<*mut AvlNode<T>>::new();
+        let mut current = &mut self.root;
+        while current.as_ref()?.right.is_some() {
+            let node = current.as_mut().unwrap();
+            path.push(&mut **node);
+            current = &mut node.right;
+        }
+        let mut last = current.take()?;
+        *current = last.left.take();
+        let _ = Self::rebalance_path(&path);
+        Some(last.value)
+    }
+
+    pub fn iter(&'a self) -> Iter<'a, T> {
+        self.range(..)
+    }
+
+    /// Iterates over the elements in `range`, from either end.
+    pub fn range<R: RangeBounds<T>>(&'a self, range: R) -> Iter<'a, T> {
+        let start = range.start_bound();
+        let end = range.end_bound();
+        self.range_by(
+            |value| match start {
+                Bound::Included(start) => value >= start,
+                Bound::Excluded(start) => value > start,
+                Bound::Unbounded => true,
+            },
+            |value| match end {
+                Bound::Included(end) => value <= end,
+                Bound::Excluded(end) => value < end,
+                Bound::Unbounded => true,
+            },
+        )
+    }
+
+    /// Iterates from the first element that is greater than or equal to
+    /// `value`.
+    pub fn seek(&'a self, value: &T) -> Iter<'a, T> {
+        self.range((Bound::Included(value), Bound::Unbounded))
+    }
+
+    /// Like `range`, but with the bounds given as predicates so callers can
+    /// bound on part of the element. `after_start` must be false for a prefix
+    /// of the set and true afterwards; `before_end` must be true for a prefix
+    /// and false afterwards.
+    pub fn range_by(
+        &'a self,
+        after_start: impl Fn(&T) -> bool,
+        before_end: impl Fn(&T) -> bool,
+    ) -> Iter<'a, T> {
+        let mut front = Vec::new();
+        let mut current = &self.root;
+        while let Some(node) = current {
+            if after_start(&node.value) {
+                front.push(&**node);
+                current = &node.left;
+            } else {
+                current = &node.right;
+            }
+        }
+
+        let mut back = Vec::new();
+        let mut current = &self.root;
+        while let Some(node) = current {
+            if before_end(&node.value) {
+                back.push(&**node);
+                current = &node.right;
+            } else {
+                current = &node.left;
+            }
+        }
+
+        let below_start = self.count_prefix(|value| !after_start(value));
+        let up_to_end = self.count_prefix(before_end);
+        Iter {
+            front,
+            back,
+            remaining: up_to_end.saturating_sub(below_start),
+        }
+    }
+
+    /// Number of leading elements satisfying `pred`, which must hold for a
+    /// prefix of the set and not after it.
+    fn count_prefix(&self, pred: impl Fn(&T) -> bool) -> usize {
+        let mut count = 0;
+        let mut current = &self.root;
+        while let Some(node) = current {
+            if pred(&node.value) {
+                count += node.left_size() + 1;
+                current = &node.right;
+            } else {
+                current = &node.left;
+            }
+        }
+        count
     }
 
+    #[cfg(test)]
     fn node_iter(&'a self) -> impl Iterator<Item = &'a AvlNode<T>> + 'a {
         AvlTreeSetIter {
             prev_nodes: Vec::new(),
@@ -187,11 +377,61 @@ impl<T: Ord + Debug> FromIterator<T> for AvlTreeSet<T> {
     }
 }
 
+/// In-order iterator over a range of an `AvlTreeSet`. Each end keeps the
+/// path to its next element, and `remaining` stops them once they meet.
+pub struct Iter<'a, T: Ord> {
+    front: Vec<&'a AvlNode<T>>,
+    back: Vec<&'a AvlNode<T>>,
+    remaining: usize,
+}
+
+impl<'a, T: 'a + Ord> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = self.front.pop()?;
+        let mut current = &node.right;
+        while let Some(next) = current {
+            self.front.push(next);
+            current = &next.left;
+        }
+        self.remaining -= 1;
+        Some(&node.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T: 'a + Ord> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = self.back.pop()?;
+        let mut current = &node.left;
+        while let Some(next) = current {
+            self.back.push(next);
+            current = &next.right;
+        }
+        self.remaining -= 1;
+        Some(&node.value)
+    }
+}
+
+impl<'a, T: 'a + Ord> ExactSizeIterator for Iter<'a, T> {}
+
+#[cfg(test)]
 struct AvlTreeSetIter<'a, T: Ord> {
     prev_nodes: Vec<&'a AvlNode<T>>,
     current_tree: &'a AvlTree<T>,
 }
 
+#[cfg(test)]
 impl<'a, T: 'a + Ord> Iterator for AvlTreeSetIter<'a, T> {
     type Item = &'a AvlNode<T>;
 
@@ -203,7 +443,7 @@ impl<'a, T: 'a + Ord> Iterator for AvlTreeSetIter<'a, T> {
                         return None;
                     }
 
-                    Some(ref prev_node) => {
+                    Some(prev_node) => {
                         self.current_tree = &prev_node.right;
                         return Some(prev_node);
                     }
@@ -211,7 +451,7 @@ impl<'a, T: 'a + Ord> Iterator for AvlTreeSetIter<'a, T> {
 
                 Some(ref current_node) => {
                     if current_node.left.is_some() {
-                        self.prev_nodes.push(&current_node);
+                        self.prev_nodes.push(current_node);
                         self.current_tree = &current_node.left;
                         continue;
                     }
@@ -253,11 +493,125 @@ mod tests {
         TestResult::from_bool(equal(set.iter(), btree.iter()))
     }
 
+    #[quickcheck]
+    fn subtree_sizes(set: AvlTreeSet<u16>, deletes: Vec<u16>) -> bool {
+        let mut set = set;
+        for value in deletes {
+            let _ = set.delete(&value);
+        }
+        set.len() == set.iter().count()
+            && all(set.node_iter(), |node| {
+                node.size == 1 + node.left_size() + node.right_size()
+            })
+    }
+
+    #[quickcheck]
+    fn rank_and_select_match_sorted_order(btree: BTreeSet<u16>, probes: Vec<u16>) -> bool {
+        let set = btree.iter().cloned().collect::<AvlTreeSet<_>>();
+        let sorted: Vec<u16> = btree.iter().cloned().collect();
+        let ranks_ok = probes
+            .iter()
+            .all(|probe| set.rank(probe) == sorted.iter().position(|v| v == probe));
+        let selects_ok = (0..=sorted.len()).all(|k| set.select(k) == sorted.get(k));
+        ranks_ok && selects_ok
+    }
+
+    #[quickcheck]
+    fn offset_moves_by_rank(btree: BTreeSet<u16>, delta: i8) -> bool {
+        let set = btree.iter().cloned().collect::<AvlTreeSet<_>>();
+        let sorted: Vec<u16> = btree.iter().cloned().collect();
+        sorted.iter().enumerate().all(|(rank, value)| {
+            let target = rank as i64 + delta as i64;
+            let expected = if target < 0 {
+                None
+            } else {
+                sorted.get(target as usize)
+            };
+            set.offset(value, delta as i64) == expected
+        })
+    }
+
     #[quickcheck]
     fn balanced_nodes(set: AvlTreeSet<u16>) -> bool {
         all(set.node_iter(), |node| node.balance_factor().abs() < 2)
     }
 
+    #[quickcheck]
+    fn delete_matches_btreeset(values: Vec<u16>, deletes: Vec<u16>) -> bool {
+        let mut set = values.iter().cloned().collect::<AvlTreeSet<_>>();
+        let mut btree = values.iter().cloned().collect::<BTreeSet<_>>();
+        for value in deletes.iter().chain(values.iter().step_by(2)) {
+            assert_eq!(set.delete(value).is_ok(), btree.remove(value));
+        }
+        equal(set.iter(), btree.iter())
+            && all(set.node_iter(), |node| {
+                node.balance_factor().abs() < 2
+                    && node.height == 1 + max(node.left_height(), node.right_height())
+            })
+    }
+
+    #[quickcheck]
+    fn range_matches_btreeset(btree: BTreeSet<u16>, a: u16, b: u16) -> bool {
+        let set = btree.iter().cloned().collect::<AvlTreeSet<_>>();
+        let (lo, hi) = (a.min(b), a.max(b));
+        equal(set.range(lo..hi), btree.range(lo..hi))
+            && equal(set.range(lo..=hi), btree.range(lo..=hi))
+            && equal(set.range(..hi), btree.range(..hi))
+            && equal(
+                set.range((Bound::Excluded(lo), Bound::Unbounded)),
+                btree.range((Bound::Excluded(lo), Bound::Unbounded)),
+            )
+            && equal(set.range(lo..=hi).rev(), btree.range(lo..=hi).rev())
+            && set.range(lo..=hi).len() == btree.range(lo..=hi).count()
+            && equal(set.seek(&a), btree.range(a..))
+    }
+
+    #[quickcheck]
+    fn iterating_from_both_ends_meets_once(btree: BTreeSet<u16>, steps: Vec<bool>) -> bool {
+        let set = btree.iter().cloned().collect::<AvlTreeSet<_>>();
+        let mut ours = set.iter();
+        let mut theirs = btree.iter();
+        for from_back in steps
+            .into_iter()
+            .chain(std::iter::repeat_n(false, btree.len() + 1))
+        {
+            let (a, b) = if from_back {
+                (ours.next_back(), theirs.next_back())
+            } else {
+                (ours.next(), theirs.next())
+            };
+            if a != b {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[quickcheck]
+    fn first_last_and_pops_match_btreeset(values: Vec<u16>, pops: Vec<bool>) -> bool {
+        let mut set = values.iter().cloned().collect::<AvlTreeSet<_>>();
+        let mut btree = values.iter().cloned().collect::<BTreeSet<_>>();
+        for from_back in pops {
+            if set.first() != btree.first() || set.last() != btree.last() {
+                return false;
+            }
+            let popped = if from_back {
+                (set.pop_last(), btree.pop_last())
+            } else {
+                (set.pop_first(), btree.pop_first())
+            };
+            if popped.0 != popped.1 {
+                return false;
+            }
+        }
+        set.len() == btree.len()
+            && equal(set.iter(), btree.iter())
+            && all(set.node_iter(), |node| {
+                node.balance_factor().abs() < 2
+                    && node.size == 1 + node.left_size() + node.right_size()
+            })
+    }
+
     #[test]
     fn test_delete() {
         let values = vec![20, 10, 30, 5, 15, 25, 35, 3, 13, 33];
@@ -266,14 +620,14 @@ mod tests {
         for i in set.iter() {
             print!("{i}->");
         }
-        println!("");
+        println!();
         match set.delete(&10) {
             Ok(_) => {
                 println!("************deleted**********");
                 for i in set.iter() {
                     print!("{i}->");
                 }
-                println!("");
+                println!();
             }
             Err(err) => println!("error occured {:#?}", err),
         }
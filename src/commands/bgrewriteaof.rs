@@ -0,0 +1,29 @@
+use super::ProtocolError;
+use crate::{
+    aof::Aof,
+    entry::Data,
+    rdb::Snapshots,
+    serialization::{response_status, Output},
+};
+use anyhow::{anyhow, Result};
+
+pub fn invoke(
+    aof: Option<&mut Aof>,
+    snapshots: &Snapshots,
+    data: &Data,
+    out: &mut Output,
+) -> Result<()> {
+    let aof = aof.ok_or_else(|| anyhow!("append only file is disabled"))?;
+    if aof.rewrite_in_progress() {
+        return Err(ProtocolError::BackgroundRewriteInProgress.into());
+    }
+    // Both fork a copy of the keyspace, so one waits for the other.
+    if snapshots.in_progress() {
+        aof.schedule_rewrite();
+        response_status(out, "Background append only file rewriting scheduled");
+        return Ok(());
+    }
+    aof.background_rewrite(data)?;
+    response_status(out, "Background append only file rewriting started");
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use super::ProtocolError;
+use crate::{
+    aof::Aof,
+    entry::Data,
+    rdb::Snapshots,
+    serialization::{response_status, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(
+    snapshots: &mut Snapshots,
+    aof: Option<&Aof>,
+    data: &Data,
+    out: &mut Output,
+) -> Result<()> {
+    if snapshots.in_progress() {
+        return Err(ProtocolError::BackgroundSaveInProgress.into());
+    }
+    // Both fork a copy of the keyspace, so one waits for the other.
+    if aof.is_some_and(Aof::rewrite_in_progress) {
+        snapshots.schedule();
+        response_status(out, "Background saving scheduled");
+        return Ok(());
+    }
+    snapshots.background_save(data)?;
+    response_status(out, "Background saving started");
+    Ok(())
+}
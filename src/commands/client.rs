@@ -0,0 +1,410 @@
+use super::{parse_integer, ProtocolError};
+use crate::{
+    connection::{Connection, ConnectionState},
+    serialization::{
+        response_integer, response_nil, response_status, response_string, Output, Protocol,
+    },
+};
+use anyhow::Result;
+use mio::Token;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    os::fd::AsRawFd,
+    time::Duration,
+};
+
+/// The only user there is until ACLs exist.
+pub const DEFAULT_USER: &str = "default";
+
+/// The kinds of client Redis distinguishes. Every client here is a normal
+/// one, but the others are accepted so filters written for Redis work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientType {
+    Normal,
+    Master,
+    Replica,
+    PubSub,
+}
+
+impl ClientType {
+    fn parse(arg: &[u8]) -> Result<ClientType> {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"normal" => Ok(ClientType::Normal),
+            b"master" => Ok(ClientType::Master),
+            b"replica" | b"slave" => Ok(ClientType::Replica),
+            b"pubsub" => Ok(ClientType::PubSub),
+            _ => Err(
+                ProtocolError::UnknownClientType(String::from_utf8_lossy(arg).into_owned()).into(),
+            ),
+        }
+    }
+}
+
+/// Which clients CLIENT KILL closes. Every criterion given must match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    pub kind: Option<ClientType>,
+    /// Only clients connected for at least this long.
+    pub max_age: Option<Duration>,
+    /// Leave the client sending the command alone.
+    pub skip_me: bool,
+}
+
+impl KillFilter {
+    fn matches<S: Read + Write>(&self, connection: &Connection<S>, is_caller: bool) -> bool {
+        let info = &connection.info;
+        !(is_caller && self.skip_me)
+            && self.id.is_none_or(|id| id == info.id)
+            && self.addr.as_ref().is_none_or(|addr| *addr == info.addr)
+            && self.laddr.as_ref().is_none_or(|laddr| *laddr == info.laddr)
+            && self.kind.is_none_or(|kind| kind == ClientType::Normal)
+            && self
+                .max_age
+                .is_none_or(|max_age| info.created.elapsed() >= max_age)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Subcommand {
+    Id,
+    /// CLIENT INFO: the CLIENT LIST line for the caller.
+    Info,
+    /// CLIENT LIST [TYPE type] [ID id ...]
+    List {
+        kind: Option<ClientType>,
+        ids: Vec<u64>,
+    },
+    /// CLIENT SETNAME name; an empty name clears it.
+    SetName(String),
+    GetName,
+    /// CLIENT KILL ip:port, the old form, which fails if nothing matches.
+    KillAddr(String),
+    /// CLIENT KILL <filter> value [<filter> value ...]
+    Kill(KillFilter),
+}
+
+impl Subcommand {
+    pub fn parse(args: &[Vec<u8>]) -> Result<Subcommand> {
+        let (name, rest) = args
+            .split_first()
+            .ok_or(ProtocolError::WrongArity("client"))?;
+        let text = |arg: &Vec<u8>| String::from_utf8_lossy(arg).into_owned();
+        match (name.to_ascii_uppercase().as_slice(), rest) {
+            (b"ID", []) => Ok(Subcommand::Id),
+            (b"INFO", []) => Ok(Subcommand::Info),
+            (b"LIST", _) => parse_list(rest),
+            (b"SETNAME", [name]) => Ok(Subcommand::SetName(parse_name(name)?)),
+            (b"GETNAME", []) => Ok(Subcommand::GetName),
+            (b"KILL", [addr]) => Ok(Subcommand::KillAddr(text(addr))),
+            (b"KILL", [_, _, ..]) => parse_kill(rest),
+            (b"ID", _) => Err(ProtocolError::WrongArity("client|id").into()),
+            (b"INFO", _) => Err(ProtocolError::WrongArity("client|info").into()),
+            (b"SETNAME", _) => Err(ProtocolError::WrongArity("client|setname").into()),
+            (b"GETNAME", _) => Err(ProtocolError::WrongArity("client|getname").into()),
+            (b"KILL", _) => Err(ProtocolError::WrongArity("client|kill").into()),
+            _ => Err(ProtocolError::UnknownSubcommand {
+                command: "CLIENT",
+                name: text(name),
+            }
+            .into()),
+        }
+    }
+}
+
+/// A client name as given to CLIENT SETNAME or HELLO. As in Redis, names
+/// are printable ASCII without spaces.
+pub fn parse_name(name: &[u8]) -> Result<String> {
+    if !name.iter().all(|b| (b'!'..=b'~').contains(b)) {
+        return Err(ProtocolError::InvalidClientName.into());
+    }
+    Ok(String::from_utf8_lossy(name).into_owned())
+}
+
+fn parse_list(args: &[Vec<u8>]) -> Result<Subcommand> {
+    let mut kind = None;
+    let mut ids = Vec::new();
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"TYPE" => {
+                let value = args.next().ok_or(ProtocolError::SyntaxError)?;
+                kind = Some(ClientType::parse(value)?);
+            }
+            b"ID" => {
+                // Every remaining argument is an id.
+                ids = args
+                    .by_ref()
+                    .map(|id| parse_id(id))
+                    .collect::<Result<_>>()?;
+                if ids.is_empty() {
+                    return Err(ProtocolError::SyntaxError.into());
+                }
+            }
+            _ => return Err(ProtocolError::SyntaxError.into()),
+        }
+    }
+    Ok(Subcommand::List { kind, ids })
+}
+
+fn parse_kill(args: &[Vec<u8>]) -> Result<Subcommand> {
+    if !args.len().is_multiple_of(2) {
+        return Err(ProtocolError::SyntaxError.into());
+    }
+    let mut filter = KillFilter {
+        id: None,
+        addr: None,
+        laddr: None,
+        kind: None,
+        max_age: None,
+        skip_me: true,
+    };
+    for pair in args.chunks(2) {
+        let value = &pair[1];
+        match pair[0].to_ascii_uppercase().as_slice() {
+            b"ID" => filter.id = Some(parse_id(value)?),
+            b"ADDR" => filter.addr = Some(String::from_utf8_lossy(value).into_owned()),
+            b"LADDR" => filter.laddr = Some(String::from_utf8_lossy(value).into_owned()),
+            b"TYPE" => filter.kind = Some(ClientType::parse(value)?),
+            // Every client belongs to the one user, so naming it matches
+            // them all.
+            b"USER" if value.as_slice() == DEFAULT_USER.as_bytes() => {}
+            b"USER" => {
+                return Err(
+                    ProtocolError::NoSuchUser(String::from_utf8_lossy(value).into_owned()).into(),
+                )
+            }
+            b"SKIPME" => match value.to_ascii_lowercase().as_slice() {
+                b"yes" => filter.skip_me = true,
+                b"no" => filter.skip_me = false,
+                _ => return Err(ProtocolError::SyntaxError.into()),
+            },
+            b"MAXAGE" => {
+                let seconds = parse_integer(value)?;
+                let seconds = u64::try_from(seconds).map_err(|_| ProtocolError::NotAnInteger)?;
+                filter.max_age = Some(Duration::from_secs(seconds));
+            }
+            _ => return Err(ProtocolError::SyntaxError.into()),
+        }
+    }
+    Ok(Subcommand::Kill(filter))
+}
+
+fn parse_id(arg: &[u8]) -> Result<u64> {
+    parse_integer(arg)
+        .ok()
+        .and_then(|id| u64::try_from(id).ok())
+        .filter(|id| *id > 0)
+        .ok_or_else(|| ProtocolError::InvalidClientId.into())
+}
+
+/// Runs CLIENT for `client`, with `others` holding every other connection.
+/// Returns the other connections CLIENT KILL picked, for the caller to
+/// close; the client itself is closed after its reply if it killed itself.
+pub fn invoke<S: Read + Write + AsRawFd>(
+    subcommand: Subcommand,
+    client: &mut Connection<S>,
+    others: &HashMap<Token, Connection<S>>,
+    out: &mut Output,
+) -> Result<Vec<Token>> {
+    match subcommand {
+        Subcommand::Id => response_integer(out, client.info.id as i64),
+        Subcommand::Info => response_string(out, format!("{}\n", describe(client)).as_bytes()),
+        Subcommand::List { kind, ids } => {
+            let mut clients: Vec<_> = others.values().chain([&*client]).collect();
+            clients.retain(|connection| {
+                kind.is_none_or(|kind| kind == ClientType::Normal)
+                    && (ids.is_empty() || ids.contains(&connection.info.id))
+            });
+            clients.sort_by_key(|connection| connection.info.id);
+            let list: String = clients
+                .into_iter()
+                .map(|connection| format!("{}\n", describe(connection)))
+                .collect();
+            response_string(out, list.as_bytes());
+        }
+        Subcommand::SetName(name) => {
+            client.info.name = (!name.is_empty()).then_some(name);
+            response_status(out, "OK");
+        }
+        Subcommand::GetName => match &client.info.name {
+            Some(name) => response_string(out, name.as_bytes()),
+            None => response_nil(out),
+        },
+        Subcommand::KillAddr(addr) => {
+            let (killed, killed_self) =
+                kill(client, others, |connection, _| connection.info.addr == addr);
+            if killed.is_empty() && !killed_self {
+                return Err(ProtocolError::NoSuchClient.into());
+            }
+            response_status(out, "OK");
+            return Ok(killed);
+        }
+        Subcommand::Kill(filter) => {
+            let (killed, killed_self) = kill(client, others, |connection, is_caller| {
+                filter.matches(connection, is_caller)
+            });
+            let count = killed.len() + killed_self as usize;
+            response_integer(out, count as i64);
+            return Ok(killed);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// The other connections `matches` picks, and whether it picked `client`
+/// too, in which case the client is closed once it has its reply.
+fn kill<S: Read + Write>(
+    client: &mut Connection<S>,
+    others: &HashMap<Token, Connection<S>>,
+    matches: impl Fn(&Connection<S>, bool) -> bool,
+) -> (Vec<Token>, bool) {
+    let killed_self = matches(client, true);
+    if killed_self {
+        client.close_after_reply();
+    }
+    let killed = others
+        .iter()
+        .filter(|(_, connection)| matches(connection, false))
+        .map(|(token, _)| *token)
+        .collect();
+    (killed, killed_self)
+}
+
+/// One line of CLIENT LIST, in Redis's `field=value` format.
+pub fn describe<S: Read + Write + AsRawFd>(connection: &Connection<S>) -> String {
+    let info = &connection.info;
+    let flags = match connection.should_close_after_reply() {
+        true => "c",
+        false => "N",
+    };
+    let events = match connection.state() {
+        ConnectionState::ReadyToRead => "r",
+        ConnectionState::ReadyToWrite => "w",
+        ConnectionState::Closing => "",
+    };
+    let resp = match connection.protocol() {
+        Protocol::Binary => "native",
+        Protocol::Resp2 => "2",
+        Protocol::Resp3 => "3",
+    };
+    format!(
+        "id={} addr={} laddr={} fd={} name={} age={} idle={} flags={} db=0 \
+         qbuf={} qbuf-free={} obl={} omem={} events={} cmd={} user={} resp={}",
+        info.id,
+        info.addr,
+        info.laddr,
+        connection.stream().as_raw_fd(),
+        info.name.as_deref().unwrap_or(""),
+        info.created.elapsed().as_secs(),
+        connection.idle_time().as_secs(),
+        flags,
+        connection.query_buffer_len(),
+        connection.read_buffer.capacity() - connection.read_buffer.len(),
+        connection.pending_response().len(),
+        connection.write_buffer.capacity(),
+        events,
+        info.last_command.unwrap_or("NULL"),
+        DEFAULT_USER,
+        resp,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ClientInfo;
+    use mio::net::UnixStream;
+
+    fn parse(args: &[&str]) -> Result<Subcommand> {
+        let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        Subcommand::parse(&args)
+    }
+
+    fn client(id: u64, addr: &str) -> (Connection<UnixStream>, UnixStream) {
+        let (server, peer) = UnixStream::pair().unwrap();
+        let mut connection = Connection::new(server);
+        connection.info = ClientInfo::new(id, addr.to_string(), "127.0.0.1:6379".to_string());
+        (connection, peer)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&["id"]).unwrap(), Subcommand::Id);
+        assert_eq!(
+            parse(&["LIST", "TYPE", "normal", "ID", "3", "1"]).unwrap(),
+            Subcommand::List {
+                kind: Some(ClientType::Normal),
+                ids: vec![3, 1]
+            }
+        );
+        assert!(parse(&["LIST", "TYPE", "bogus"]).is_err());
+        assert!(parse(&["LIST", "ID", "0"]).is_err());
+        assert_eq!(
+            parse(&["setname", "worker-1"]).unwrap(),
+            Subcommand::SetName("worker-1".to_string())
+        );
+        assert!(parse(&["SETNAME", "two words"]).is_err());
+        assert_eq!(
+            parse(&["KILL", "127.0.0.1:5000"]).unwrap(),
+            Subcommand::KillAddr("127.0.0.1:5000".to_string())
+        );
+        let Subcommand::Kill(filter) = parse(&["KILL", "ID", "7", "SKIPME", "no"]).unwrap() else {
+            panic!("expected a filter");
+        };
+        assert_eq!(filter.id, Some(7));
+        assert!(!filter.skip_me);
+        assert!(parse(&["KILL", "USER", "admin"]).is_err());
+        assert!(parse(&["KILL", "ID", "7", "SKIPME"]).is_err());
+        assert!(parse(&["PAUSE"]).is_err());
+    }
+
+    #[test]
+    fn test_kill() {
+        let (mut caller, _a) = client(1, "127.0.0.1:5001");
+        let (other, _b) = client(2, "127.0.0.1:5002");
+        let others = HashMap::from([(Token(9), other)]);
+
+        let mut out = Output::default();
+        let killed = invoke(
+            parse(&["KILL", "ADDR", "127.0.0.1:5001"]).unwrap(),
+            &mut caller,
+            &others,
+            &mut out,
+        )
+        .unwrap();
+        // The caller is skipped unless it asks otherwise.
+        assert!(killed.is_empty() && !caller.should_close_after_reply());
+
+        let killed = invoke(
+            parse(&["KILL", "USER", "default", "SKIPME", "no"]).unwrap(),
+            &mut caller,
+            &others,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(killed, vec![Token(9)]);
+        assert!(caller.should_close_after_reply());
+
+        assert!(invoke(
+            Subcommand::KillAddr("127.0.0.1:5003".to_string()),
+            &mut client(3, "127.0.0.1:5004").0,
+            &others,
+            &mut out,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_describe() {
+        let (mut connection, _peer) = client(5, "127.0.0.1:5005");
+        connection.info.name = Some("worker".to_string());
+        let line = describe(&connection);
+        assert!(line.starts_with("id=5 addr=127.0.0.1:5005 laddr=127.0.0.1:6379 fd="));
+        assert!(line.contains(" name=worker age=0 idle=0 flags=N db=0 qbuf=0 "));
+        assert!(line.ends_with(" events=r cmd=NULL user=default resp=native"));
+    }
+}
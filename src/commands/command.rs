@@ -0,0 +1,105 @@
+use super::{
+    table::{self, CommandSpec},
+    ProtocolError,
+};
+use crate::serialization::{
+    response_array, response_integer, response_map, response_nil, response_set, response_status,
+    response_string, Output,
+};
+use anyhow::Result;
+
+#[derive(Debug, PartialEq)]
+pub enum Subcommand {
+    /// COMMAND: every command, as COMMAND INFO describes them.
+    List,
+    Count,
+    /// COMMAND INFO [name ...]; no names means every command.
+    Info(Vec<Vec<u8>>),
+    /// COMMAND DOCS [name ...]; no names means every command.
+    Docs(Vec<Vec<u8>>),
+}
+
+impl Subcommand {
+    pub fn parse(args: &[Vec<u8>]) -> Result<Subcommand> {
+        let Some((name, rest)) = args.split_first() else {
+            return Ok(Subcommand::List);
+        };
+        match name.to_ascii_uppercase().as_slice() {
+            b"COUNT" if rest.is_empty() => Ok(Subcommand::Count),
+            b"COUNT" => Err(ProtocolError::WrongArity("command|count").into()),
+            b"INFO" => Ok(Subcommand::Info(rest.to_vec())),
+            b"DOCS" => Ok(Subcommand::Docs(rest.to_vec())),
+            _ => Err(ProtocolError::UnknownSubcommand {
+                command: "COMMAND",
+                name: String::from_utf8_lossy(name).into_owned(),
+            }
+            .into()),
+        }
+    }
+}
+
+pub fn invoke(subcommand: Subcommand, out: &mut Output) -> Result<()> {
+    match subcommand {
+        Subcommand::List => {
+            response_array(out, table::commands().len() as u32);
+            table::commands()
+                .iter()
+                .for_each(|spec| response_info(out, spec));
+        }
+        Subcommand::Count => response_integer(out, table::commands().len() as i64),
+        Subcommand::Info(names) if names.is_empty() => {
+            return invoke(Subcommand::List, out);
+        }
+        Subcommand::Info(names) => {
+            response_array(out, names.len() as u32);
+            for name in names {
+                match table::lookup(&name) {
+                    Some(spec) => response_info(out, spec),
+                    None => response_nil(out),
+                }
+            }
+        }
+        Subcommand::Docs(names) => {
+            let specs: Vec<&CommandSpec> = if names.is_empty() {
+                table::commands().iter().collect()
+            } else {
+                names
+                    .iter()
+                    .filter_map(|name| table::lookup(name))
+                    .collect()
+            };
+            response_map(out, specs.len() as u32);
+            for spec in specs {
+                response_string(out, spec.name.as_bytes());
+                response_map(out, 2);
+                response_string(out, b"summary");
+                response_string(out, spec.summary.as_bytes());
+                response_string(out, b"group");
+                response_string(out, spec.group.as_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One command in the layout of Redis's COMMAND INFO reply. Tips, key
+/// specifications and subcommands are not tracked and are always empty.
+fn response_info(out: &mut Output, spec: &CommandSpec) {
+    response_array(out, 10);
+    response_string(out, spec.name.as_bytes());
+    response_integer(out, spec.arity as i64);
+    let flags: Vec<&str> = spec.flag_names().collect();
+    response_set(out, flags.len() as u32);
+    flags.iter().for_each(|flag| response_status(out, flag));
+    response_integer(out, spec.first_key as i64);
+    response_integer(out, spec.last_key as i64);
+    response_integer(out, spec.key_step as i64);
+    let categories = spec.acl_categories();
+    response_set(out, categories.len() as u32);
+    categories
+        .iter()
+        .for_each(|category| response_status(out, category));
+    for _ in 0..3 {
+        response_array(out, 0);
+    }
+}
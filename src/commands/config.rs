@@ -0,0 +1,65 @@
+use super::ProtocolError;
+use crate::{
+    config::Config,
+    serialization::{response_map, response_status, response_string, Output},
+};
+use anyhow::Result;
+
+#[derive(Debug, PartialEq)]
+pub enum Subcommand {
+    /// CONFIG GET pattern [pattern ...]
+    Get(Vec<String>),
+    /// CONFIG SET name value [name value ...]
+    Set(Vec<(String, String)>),
+    Rewrite,
+}
+
+impl Subcommand {
+    pub fn parse(args: &[Vec<u8>]) -> Result<Subcommand> {
+        let (name, rest) = args
+            .split_first()
+            .ok_or(ProtocolError::WrongArity("config"))?;
+        let text = |arg: &Vec<u8>| String::from_utf8_lossy(arg).into_owned();
+        match name.to_ascii_uppercase().as_slice() {
+            b"GET" if !rest.is_empty() => Ok(Subcommand::Get(rest.iter().map(text).collect())),
+            b"GET" => Err(ProtocolError::WrongArity("config|get").into()),
+            b"SET" if !rest.is_empty() && rest.len() % 2 == 0 => Ok(Subcommand::Set(
+                rest.chunks(2)
+                    .map(|pair| (text(&pair[0]), text(&pair[1])))
+                    .collect(),
+            )),
+            b"SET" => Err(ProtocolError::WrongArity("config|set").into()),
+            b"REWRITE" if rest.is_empty() => Ok(Subcommand::Rewrite),
+            b"REWRITE" => Err(ProtocolError::WrongArity("config|rewrite").into()),
+            _ => Err(ProtocolError::UnknownSubcommand {
+                command: "CONFIG",
+                name: String::from_utf8_lossy(name).into_owned(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Runs CONFIG against `config`. The caller applies whatever CONFIG SET
+/// changed to the running server.
+pub fn invoke(config: &mut Config, subcommand: Subcommand, out: &mut Output) -> Result<()> {
+    match subcommand {
+        Subcommand::Get(patterns) => {
+            let settings = config.get(&patterns);
+            response_map(out, settings.len() as u32);
+            for (name, value) in settings {
+                response_string(out, name.as_bytes());
+                response_string(out, value.as_bytes());
+            }
+        }
+        Subcommand::Set(pairs) => {
+            config.set(&pairs)?;
+            response_status(out, "OK");
+        }
+        Subcommand::Rewrite => {
+            config.rewrite()?;
+            response_status(out, "OK");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::{
+    entry::{now_ms, Data},
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT, with the deadline in
+/// milliseconds since the Unix epoch. A deadline that is not in the future
+/// deletes the key right away, as in Redis, except while loading, when the
+/// key keeps it until loading is done.
+pub fn invoke(data: &mut Data, key: Vec<u8>, deadline: i64, out: &mut Output) -> Result<()> {
+    if data.lookup(&key).is_none() {
+        response_integer(out, 0);
+        return Ok(());
+    }
+    if deadline <= now_ms() as i64 && !data.loading() {
+        drop(data.remove(&key));
+    } else {
+        data.set_expiry(&key, Some(deadline.max(0) as u64));
+    }
+    response_integer(out, 1);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use super::{client, parse_integer, ProtocolError};
+use crate::serialization::{
+    response_array, response_integer, response_map, response_string, Output, Protocol,
+};
+use anyhow::Result;
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub version: Option<i64>,
+    /// The user to authenticate as. Without ACLs only the default user
+    /// exists, and it takes any password.
+    pub user: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Request {
+    pub fn parse(args: &[Vec<u8>]) -> Result<Request> {
+        let mut request = Request::default();
+        let Some((version, options)) = args.split_first() else {
+            return Ok(request);
+        };
+        request.version = Some(parse_integer(version)?);
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"AUTH" => {
+                    let (Some(user), Some(_password)) = (options.next(), options.next()) else {
+                        return Err(ProtocolError::SyntaxError.into());
+                    };
+                    request.user = Some(String::from_utf8_lossy(user).into_owned());
+                }
+                b"SETNAME" => {
+                    let name = options.next().ok_or(ProtocolError::SyntaxError)?;
+                    request.name = Some(client::parse_name(name)?);
+                }
+                _ => return Err(ProtocolError::SyntaxError.into()),
+            }
+        }
+        Ok(request)
+    }
+
+    /// Checks the credentials given with AUTH, if any.
+    pub fn authenticate(&self) -> Result<()> {
+        match &self.user {
+            Some(user) if user != client::DEFAULT_USER => Err(ProtocolError::WrongPass.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The protocol a connection speaking `current` switches to on
+/// `HELLO [protover]`. Only RESP connections can negotiate.
+pub fn negotiate(current: Protocol, version: Option<i64>) -> Result<Protocol> {
+    match (current, version) {
+        (Protocol::Binary, _) => Err(ProtocolError::UnsupportedProtocol.into()),
+        (current, None) => Ok(current),
+        (_, Some(2)) => Ok(Protocol::Resp2),
+        (_, Some(3)) => Ok(Protocol::Resp3),
+        (_, Some(_)) => Err(ProtocolError::UnsupportedProtocol.into()),
+    }
+}
+
+/// Describes the server, in the protocol just negotiated.
+pub fn invoke(out: &mut Output) -> Result<()> {
+    let proto = match out.protocol() {
+        Protocol::Resp3 => 3,
+        _ => 2,
+    };
+    response_map(out, 6);
+    response_string(out, b"server");
+    response_string(out, b"crabcache");
+    response_string(out, b"version");
+    response_string(out, env!("CARGO_PKG_VERSION").as_bytes());
+    response_string(out, b"proto");
+    response_integer(out, proto);
+    response_string(out, b"mode");
+    response_string(out, b"standalone");
+    response_string(out, b"role");
+    response_string(out, b"master");
+    response_string(out, b"modules");
+    response_array(out, 0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Request> {
+        let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        Request::parse(&args)
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(parse(&[]).unwrap(), Request::default());
+        let request = parse(&["3", "auth", "default", "secret", "SETNAME", "worker"]).unwrap();
+        assert_eq!(request.version, Some(3));
+        assert_eq!(request.user.as_deref(), Some("default"));
+        assert_eq!(request.name.as_deref(), Some("worker"));
+        assert!(request.authenticate().is_ok());
+
+        let request = parse(&["2", "AUTH", "alice", "secret"]).unwrap();
+        let err = request.authenticate().unwrap_err();
+        assert_eq!(
+            err.downcast::<ProtocolError>().unwrap(),
+            ProtocolError::WrongPass
+        );
+
+        assert!(parse(&["3", "AUTH", "default"]).is_err());
+        assert!(parse(&["3", "SETNAME"]).is_err());
+        assert!(parse(&["3", "SETNAME", "two words"]).is_err());
+        assert!(parse(&["3", "FAST"]).is_err());
+        assert!(parse(&["three"]).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use super::{format_float, parse_float, parse_integer, ProtocolError};
+use crate::{
+    entry::{Data, Entry, Value},
+    serialization::{response_integer, response_string, Output},
+};
+use anyhow::Result;
+
+/// INCR, DECR, INCRBY and DECRBY: adds `increment` to the integer stored at
+/// `key`, starting from 0 if the key is missing, and replies with the
+/// result. The key keeps its TTL, and the result is stored as a number so
+/// the next increment needs no parsing.
+pub fn invoke(data: &mut Data, key: Vec<u8>, increment: i64, out: &mut Output) -> Result<()> {
+    let result = match data.lookup(&key) {
+        Some(Entry {
+            value: Some(value), ..
+        }) => {
+            let current = match value {
+                Value::Integer(current) => *current,
+                Value::String(string) => parse_integer(string)?,
+                Value::ZSet(_) => return Err(ProtocolError::WrongType.into()),
+            };
+            let result = current
+                .checked_add(increment)
+                .ok_or(ProtocolError::IncrementOverflow)?;
+            *value = Value::Integer(result);
+            result
+        }
+        _ => {
+            data.lookup_or_insert(&key).value = Some(Value::Integer(increment));
+            increment
+        }
+    };
+    response_integer(out, result);
+    Ok(())
+}
+
+/// INCRBYFLOAT: like INCRBY for floats. The result is stored, and sent, as
+/// a string, as in Redis.
+pub fn invoke_float(data: &mut Data, key: Vec<u8>, increment: f64, out: &mut Output) -> Result<()> {
+    let current = match data.lookup(&key).and_then(|entry| entry.value.as_ref()) {
+        None => 0.0,
+        Some(Value::Integer(current)) => *current as f64,
+        Some(Value::String(string)) => parse_float(string)?,
+        Some(Value::ZSet(_)) => return Err(ProtocolError::WrongType.into()),
+    };
+    let result = current + increment;
+    if !result.is_finite() {
+        return Err(ProtocolError::NotFinite.into());
+    }
+    let result = format_float(result);
+    response_string(out, result.as_bytes());
+    data.lookup_or_insert(&key).value = Some(Value::String(result.into_bytes()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{execute, table, Command, Request};
+
+    fn run(data: &mut Data, command: Command) -> Result<Vec<u8>> {
+        let name: &[u8] = match command {
+            Command::IncrBy(..) => b"incrby",
+            Command::IncrByFloat(..) => b"incrbyfloat",
+            _ => b"get",
+        };
+        let spec = table::lookup(name).unwrap();
+        let mut out = Output::default();
+        execute(data, Request { spec, command }, &mut out)?;
+        Ok(out.as_bytes().to_vec())
+    }
+
+    fn integer(value: i64) -> Vec<u8> {
+        let mut out = Output::default();
+        response_integer(&mut out, value);
+        out.as_bytes().to_vec()
+    }
+
+    fn error(err: anyhow::Error) -> ProtocolError {
+        err.downcast().unwrap()
+    }
+
+    #[test]
+    fn test_incr_by() {
+        let mut data = Data::new();
+        let incr = |increment| Command::IncrBy(b"n".to_vec(), increment);
+        assert_eq!(run(&mut data, incr(1)).unwrap(), integer(1));
+        assert_eq!(run(&mut data, incr(-11)).unwrap(), integer(-10));
+        assert!(matches!(
+            data.lookup(b"n").unwrap().value,
+            Some(Value::Integer(-10))
+        ));
+
+        // A string counter is parsed once and stored as a number from then on.
+        data.lookup_or_insert(b"n").value = Some(Value::String(b"41".to_vec()));
+        data.set_expiry(b"n", Some(u64::MAX));
+        assert_eq!(run(&mut data, incr(1)).unwrap(), integer(42));
+        assert!(matches!(
+            data.lookup(b"n").unwrap().value,
+            Some(Value::Integer(42))
+        ));
+        assert_eq!(data.lookup(b"n").unwrap().expire_at(), Some(u64::MAX));
+        assert_eq!(
+            run(&mut data, Command::Get(b"n".to_vec())).unwrap(),
+            [3, 2, 0, 0, 0, b'4', b'2']
+        );
+    }
+
+    #[test]
+    fn test_incr_by_errors() {
+        let mut data = Data::new();
+        data.lookup_or_insert(b"max").value = Some(Value::Integer(i64::MAX));
+        data.lookup_or_insert(b"text").value = Some(Value::String(b"4.5".to_vec()));
+        data.lookup_or_insert(b"z").value = Some(Value::ZSet(Box::default()));
+
+        let err = run(&mut data, Command::IncrBy(b"max".to_vec(), 1)).unwrap_err();
+        assert_eq!(error(err), ProtocolError::IncrementOverflow);
+        assert!(matches!(
+            data.lookup(b"max").unwrap().value,
+            Some(Value::Integer(i64::MAX))
+        ));
+        let err = run(&mut data, Command::IncrBy(b"text".to_vec(), 1)).unwrap_err();
+        assert_eq!(error(err), ProtocolError::NotAnInteger);
+        let err = run(&mut data, Command::IncrBy(b"z".to_vec(), 1)).unwrap_err();
+        assert_eq!(error(err), ProtocolError::WrongType);
+    }
+
+    #[test]
+    fn test_incr_by_float() {
+        let mut data = Data::new();
+        data.lookup_or_insert(b"n").value = Some(Value::Integer(10));
+        let incr = |increment| Command::IncrByFloat(b"n".to_vec(), increment);
+        assert_eq!(
+            run(&mut data, incr(0.5)).unwrap(),
+            [3, 4, 0, 0, 0, b'1', b'0', b'.', b'5']
+        );
+        assert_eq!(
+            run(&mut data, incr(-0.5)).unwrap(),
+            [3, 2, 0, 0, 0, b'1', b'0']
+        );
+        // The integral result is a string again, so INCR reparses it.
+        assert_eq!(
+            run(&mut data, Command::IncrBy(b"n".to_vec(), 1)).unwrap(),
+            integer(11)
+        );
+
+        let err = run(&mut data, incr(f64::INFINITY)).unwrap_err();
+        assert_eq!(error(err), ProtocolError::NotFinite);
+        data.lookup_or_insert(b"text").value = Some(Value::String(b"abc".to_vec()));
+        let err = run(&mut data, Command::IncrByFloat(b"text".to_vec(), 1.0)).unwrap_err();
+        assert_eq!(error(err), ProtocolError::NotAFloat);
+    }
+}
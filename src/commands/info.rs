@@ -0,0 +1,99 @@
+use crate::{
+    serialization::{response_string, Output},
+    stats::Stats,
+};
+use anyhow::Result;
+use std::fmt::Write;
+
+/// What the INFO sections report about the clients.
+pub struct Clients {
+    pub connected: usize,
+    pub max: usize,
+}
+
+/// INFO [section ...]: the named sections in Redis's `key:value` text
+/// format. No sections, `default`, `all` or `everything` means every one;
+/// unknown sections are left out.
+pub fn invoke(
+    sections: Vec<String>,
+    clients: Clients,
+    stats: &Stats,
+    out: &mut Output,
+) -> Result<()> {
+    let all = sections.is_empty()
+        || sections
+            .iter()
+            .any(|section| matches!(section.as_str(), "default" | "all" | "everything"));
+    let wanted = |name: &str| all || sections.iter().any(|section| section == name);
+    let mut report = Vec::new();
+    if wanted("clients") {
+        report.push(section(
+            "Clients",
+            &[
+                ("connected_clients", clients.connected as u64),
+                ("maxclients", clients.max as u64),
+            ],
+        ));
+    }
+    if wanted("stats") {
+        report.push(section(
+            "Stats",
+            &[
+                (
+                    "total_connections_received",
+                    stats.total_connections_received,
+                ),
+                ("total_commands_processed", stats.total_commands_processed),
+                ("rejected_connections", stats.rejected_connections),
+                (
+                    "client_output_buffer_limit_disconnections",
+                    stats.client_output_buffer_limit_disconnections,
+                ),
+            ],
+        ));
+    }
+    response_string(out, report.join("\r\n").as_bytes());
+    Ok(())
+}
+
+fn section(title: &str, fields: &[(&str, u64)]) -> String {
+    let mut text = format!("# {}\r\n", title);
+    for (name, value) in fields {
+        writeln!(text, "{}:{}\r", name, value).unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(sections: &[&str]) -> String {
+        let mut out = Output::default();
+        let sections = sections.iter().map(|section| section.to_string()).collect();
+        let clients = Clients {
+            connected: 2,
+            max: 10,
+        };
+        let stats = Stats {
+            rejected_connections: 3,
+            ..Stats::default()
+        };
+        invoke(sections, clients, &stats, &mut out).unwrap();
+        // Skip the native string header: type byte and length.
+        String::from_utf8(out.as_bytes()[5..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_sections() {
+        assert_eq!(
+            info(&["clients"]),
+            "# Clients\r\nconnected_clients:2\r\nmaxclients:10\r\n"
+        );
+        let all = info(&[]);
+        assert!(all.starts_with("# Clients\r\n"));
+        assert!(all.contains("\r\n\r\n# Stats\r\n"));
+        assert!(all.contains("rejected_connections:3\r\n"));
+        assert_eq!(info(&["keyspace"]), "");
+    }
+}
@@ -0,0 +1,10 @@
+use crate::{
+    rdb::Snapshots,
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(snapshots: &Snapshots, out: &mut Output) -> Result<()> {
+    response_integer(out, snapshots.last_save() as i64);
+    Ok(())
+}
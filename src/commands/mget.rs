@@ -0,0 +1,18 @@
+use crate::{
+    entry::{Data, Value},
+    serialization::{response_array, response_nil, response_string, Output},
+};
+use anyhow::Result;
+
+/// Keys that are missing or hold another type come back as nil, as in Redis.
+pub fn invoke(data: &mut Data, keys: Vec<Vec<u8>>, out: &mut Output) -> Result<()> {
+    response_array(out, keys.len() as u32);
+    for key in keys {
+        let value = data.lookup(&key).and_then(|entry| entry.value.as_ref());
+        match value.and_then(Value::string_bytes) {
+            Some(string) => response_string(out, &string),
+            None => response_nil(out),
+        }
+    }
+    Ok(())
+}
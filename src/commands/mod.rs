@@ -1,87 +1,460 @@
-use anyhow::Result;
+use crate::{
+    entry::{Data, Entry, Value},
+    serialization::{response_prefixed_err, ErrorCode, Output},
+    zset::ZSet,
+};
+use anyhow::{bail, Result};
 use byteorder::{ByteOrder, LittleEndian};
+use std::fmt::Display;
+use table::{CommandSpec, Handler};
 
+pub mod bgrewriteaof;
+pub mod bgsave;
+pub mod client;
+pub mod command;
+pub mod config;
 pub mod del;
+pub mod expire;
 pub mod get;
+pub mod hello;
+pub mod incr;
+pub mod info;
+pub mod lastsave;
+pub mod mget;
+pub mod mset;
+pub mod persist;
+pub mod ping;
+pub mod save;
 pub mod set;
+pub mod shutdown;
+pub mod table;
+pub mod ttl;
+pub mod zadd;
+pub mod zcard;
+pub mod zincrby;
+pub mod zrange;
+pub mod zrangebyscore;
+pub mod zrank;
+pub mod zrem;
+pub mod zscore;
 
-#[derive(Debug)]
+/// Matches Redis's default `proto-max-bulk-len`.
+pub const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Matches Redis's limit on the number of arguments in one request.
+pub const DEFAULT_MAX_ARGUMENTS: usize = 1024 * 1024;
+
+/// Bounds on a single request, enforced from its headers before the rest of
+/// it has arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Largest single argument, as `proto-max-bulk-len` sets it.
+    pub max_bulk_len: usize,
+    pub max_arguments: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            max_arguments: DEFAULT_MAX_ARGUMENTS,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// No limits, for input the server wrote itself such as its own log.
+    pub fn unlimited() -> Self {
+        Self {
+            max_bulk_len: usize::MAX,
+            max_arguments: usize::MAX,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    ArgumentTooLarge { limit: usize },
+    InvalidArgumentCount(u32),
+    TruncatedRequest,
+    UnknownCommand(String),
+    UnknownSubcommand { command: &'static str, name: String },
+    WrongArity(&'static str),
+    NotAnInteger,
+    IncrementOverflow,
+    NotFinite,
+    InvalidExpireTime(&'static str),
+    SyntaxError,
+    NotAFloat,
+    MinMaxNotAFloat,
+    ScoreIsNaN,
+    WrongType,
+    BackgroundSaveInProgress,
+    BackgroundRewriteInProgress,
+    AofWriteFailed(String),
+    Resp(&'static str),
+    UnsupportedProtocol,
+    ConfigSetFailed { name: String, reason: String },
+    NoConfigFile,
+    NoShutdownInProgress,
+    ShutdownInProgress,
+    ShuttingDown,
+    MaxClients,
+    NoSuchClient,
+    InvalidClientId,
+    InvalidClientName,
+    UnknownClientType(String),
+    NoSuchUser(String),
+    WrongPass,
+}
+
+impl ProtocolError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ProtocolError::ArgumentTooLarge { .. } => ErrorCode::TooBig,
+            ProtocolError::InvalidArgumentCount(_)
+            | ProtocolError::TruncatedRequest
+            | ProtocolError::Resp(_)
+            | ProtocolError::UnsupportedProtocol => ErrorCode::Protocol,
+            ProtocolError::UnknownCommand(_) | ProtocolError::UnknownSubcommand { .. } => {
+                ErrorCode::Unknown
+            }
+            ProtocolError::WrongArity(_)
+            | ProtocolError::NotAnInteger
+            | ProtocolError::IncrementOverflow
+            | ProtocolError::NotFinite
+            | ProtocolError::InvalidExpireTime(_)
+            | ProtocolError::SyntaxError
+            | ProtocolError::NotAFloat
+            | ProtocolError::MinMaxNotAFloat
+            | ProtocolError::ScoreIsNaN
+            | ProtocolError::ConfigSetFailed { .. }
+            | ProtocolError::NoConfigFile
+            | ProtocolError::NoShutdownInProgress
+            | ProtocolError::NoSuchClient
+            | ProtocolError::InvalidClientId
+            | ProtocolError::InvalidClientName
+            | ProtocolError::UnknownClientType(_)
+            | ProtocolError::NoSuchUser(_)
+            | ProtocolError::WrongPass => ErrorCode::Arg,
+            ProtocolError::ShutdownInProgress
+            | ProtocolError::ShuttingDown
+            | ProtocolError::MaxClients => ErrorCode::Busy,
+            ProtocolError::WrongType => ErrorCode::Type,
+            ProtocolError::AofWriteFailed(_) => ErrorCode::Internal,
+            ProtocolError::BackgroundSaveInProgress
+            | ProtocolError::BackgroundRewriteInProgress => ErrorCode::Busy,
+        }
+    }
+
+    /// The word RESP replies start with, which Redis clients match on.
+    /// Native replies have the numeric `code` instead.
+    pub fn resp_prefix(&self) -> &'static str {
+        match self {
+            ProtocolError::WrongType => "WRONGTYPE",
+            ProtocolError::UnsupportedProtocol => "NOPROTO",
+            ProtocolError::AofWriteFailed(_) => "MISCONF",
+            ProtocolError::WrongPass => "WRONGPASS",
+            _ => "ERR",
+        }
+    }
+
+    /// Writes the error as the reply in `out`.
+    pub fn reply(&self, out: &mut Output) {
+        response_prefixed_err(
+            out,
+            self.code().as_num(),
+            self.resp_prefix(),
+            &self.to_string(),
+        );
+    }
+
+    /// Whether the error leaves the connection unable to find the start of
+    /// the next request. Anything else is reported and the client carries on.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::ArgumentTooLarge { .. }
+                | ProtocolError::InvalidArgumentCount(_)
+                | ProtocolError::Resp(_)
+        )
+    }
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::ArgumentTooLarge { limit } => {
+                write!(f, "argument exceeds the maximum size of {} bytes", limit)
+            }
+            ProtocolError::InvalidArgumentCount(n) => {
+                write!(f, "invalid argument count {}", n)
+            }
+            ProtocolError::TruncatedRequest => write!(f, "truncated request"),
+            ProtocolError::UnknownCommand(name) => write!(f, "unknown command '{}'", name),
+            ProtocolError::UnknownSubcommand { command, name } => {
+                write!(f, "unknown subcommand '{}' for '{}'", name, command)
+            }
+            ProtocolError::WrongArity(name) => {
+                write!(f, "wrong number of arguments for '{}' command", name)
+            }
+            ProtocolError::NotAnInteger => write!(f, "value is not an integer or out of range"),
+            ProtocolError::IncrementOverflow => write!(f, "increment or decrement would overflow"),
+            ProtocolError::NotFinite => write!(f, "increment would produce NaN or Infinity"),
+            ProtocolError::InvalidExpireTime(name) => {
+                write!(f, "invalid expire time in '{}' command", name)
+            }
+            ProtocolError::SyntaxError => write!(f, "syntax error"),
+            ProtocolError::NotAFloat => write!(f, "value is not a valid float"),
+            ProtocolError::MinMaxNotAFloat => write!(f, "min or max is not a float"),
+            ProtocolError::ScoreIsNaN => write!(f, "resulting score is not a number (NaN)"),
+            ProtocolError::WrongType => {
+                write!(f, "Operation against a key holding the wrong kind of value")
+            }
+            ProtocolError::BackgroundSaveInProgress => {
+                write!(f, "background save already in progress")
+            }
+            ProtocolError::AofWriteFailed(reason) => {
+                write!(f, "Errors writing to the AOF file: {}", reason)
+            }
+            ProtocolError::Resp(reason) => write!(f, "Protocol error: {}", reason),
+            ProtocolError::UnsupportedProtocol => {
+                write!(f, "unsupported protocol version")
+            }
+            ProtocolError::ConfigSetFailed { name, reason } => write!(
+                f,
+                "CONFIG SET failed (possibly related to argument '{}') - {}",
+                name, reason
+            ),
+            ProtocolError::NoConfigFile => write!(f, "the server is running without a config file"),
+            ProtocolError::NoShutdownInProgress => write!(f, "no shutdown in progress"),
+            ProtocolError::ShutdownInProgress => write!(f, "shutdown already in progress"),
+            ProtocolError::ShuttingDown => {
+                write!(
+                    f,
+                    "the server is shutting down and no longer accepts writes"
+                )
+            }
+            ProtocolError::MaxClients => write!(f, "max number of clients reached"),
+            ProtocolError::NoSuchClient => write!(f, "No such client"),
+            ProtocolError::InvalidClientId => write!(f, "client-id should be greater than 0"),
+            ProtocolError::InvalidClientName => write!(
+                f,
+                "Client names cannot contain spaces, newlines or special characters."
+            ),
+            ProtocolError::UnknownClientType(name) => {
+                write!(f, "Unknown client type '{}'", name)
+            }
+            ProtocolError::NoSuchUser(name) => write!(f, "No such user '{}'", name),
+            ProtocolError::WrongPass => {
+                write!(f, "invalid username-password pair or user is disabled.")
+            }
+            ProtocolError::BackgroundRewriteInProgress => {
+                write!(
+                    f,
+                    "background append only file rewriting already in progress"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Debug, PartialEq)]
 pub enum Command {
     Get(Vec<u8>),
-    Set(Vec<u8>, Vec<u8>),
-    Del(Vec<u8>),
+    Set(Vec<u8>, Vec<u8>, set::Expiry),
+    Del(Vec<Vec<u8>>),
+    MGet(Vec<Vec<u8>>),
+    MSet(Vec<(Vec<u8>, Vec<u8>)>),
+    /// INCR, DECR, INCRBY and DECRBY, with DECR's amounts negated.
+    IncrBy(Vec<u8>, i64),
+    IncrByFloat(Vec<u8>, f64),
+    Expire(Vec<u8>, i64),
+    PExpire(Vec<u8>, i64),
+    ExpireAt(Vec<u8>, i64),
+    PExpireAt(Vec<u8>, i64),
+    Ttl(Vec<u8>),
+    PTtl(Vec<u8>),
+    Persist(Vec<u8>),
+    ZAdd(Vec<u8>, Vec<(f64, Vec<u8>)>),
+    ZRem(Vec<u8>, Vec<Vec<u8>>),
+    ZScore(Vec<u8>, Vec<u8>),
+    ZRange(Vec<u8>, i64, i64, bool),
+    ZRevRange(Vec<u8>, i64, i64, bool),
+    ZRangeByScore(Vec<u8>, zrangebyscore::ScoreRange),
+    ZRank(Vec<u8>, Vec<u8>),
+    ZCard(Vec<u8>),
+    ZIncrBy(Vec<u8>, f64, Vec<u8>),
+    Save,
+    BgSave,
+    LastSave,
+    BgRewriteAof,
+    /// INFO [section ...], with the section names lower-cased.
+    Info(Vec<String>),
+    Client(client::Subcommand),
+    Command(command::Subcommand),
+    Config(config::Subcommand),
+    Shutdown(shutdown::Request),
+    Ping(Option<Vec<u8>>),
+    Hello(hello::Request),
+    Quit,
 }
 
-impl PartialEq for Command {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Command::Get(a), Command::Get(b)) => a == b,
-            (Command::Set(a1, a2), Command::Set(b1, b2)) => a1 == b1 && a2 == b2,
-            (Command::Del(a), Command::Del(b)) => a == b,
-            _ => false,
-        }
+/// A parsed command, with the table entry that parsed it and will run it.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub spec: &'static CommandSpec,
+    pub command: Command,
+}
+
+impl Request {
+    /// Builds a request from its name and arguments, as decoded from either
+    /// protocol. Names are matched case-insensitively against the command
+    /// table, which also checks the argument count.
+    pub fn parse(args: &[Vec<u8>]) -> Result<Request> {
+        Request::command_spec(args)?.parse_request(args)
+    }
+
+    /// The table entry for the command named by the first of `args`.
+    pub fn command_spec(args: &[Vec<u8>]) -> Result<&'static CommandSpec> {
+        let name = args.first().ok_or(ProtocolError::TruncatedRequest)?;
+        Ok(table::lookup(name).ok_or_else(|| {
+            ProtocolError::UnknownCommand(String::from_utf8_lossy(name).into_owned())
+        })?)
+    }
+}
+
+/// Runs a command that only touches the keyspace, writing its reply to
+/// `out`. Commands that act on the server or the connection need the event
+/// loop and are refused.
+pub fn execute(data: &mut Data, request: Request, out: &mut Output) -> Result<()> {
+    match request.spec.handler {
+        Handler::Keyspace(handler) => handler(data, request.command, out),
+        Handler::Server(_) => bail!("{} does not operate on the keyspace", request.spec.name),
     }
 }
 
-impl Command {
-    pub fn parse_request(request: &[u8]) -> Result<Command> {
-        let request = resolve_command_payload(request)?;
-        let mut tokens = request.split(|b| *b == b' ');
-        let command = tokens
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Invalid request"))?;
-
-        match command {
-            b"GET" => {
-                let key = tokens
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Expected key for GET"))?
-                    .to_vec();
-                Ok(Command::Get(key))
-            }
-            b"SET" => {
-                let key = tokens
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Expected key for SET"))?
-                    .to_vec();
-                let value = tokens
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Expected value for SET"))?
-                    .to_vec();
-                Ok(Command::Set(key, value))
-            }
-            b"DEL" => {
-                let key = tokens
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Expected key for DEL"))?
-                    .to_vec();
-                Ok(Command::Del(key))
-            }
-            _ => Err(anyhow::anyhow!("Invalid command")),
+fn next_arg<'a>(
+    tokens: &mut impl Iterator<Item = &'a [u8]>,
+    name: &'static str,
+) -> Result<&'a [u8]> {
+    Ok(tokens.next().ok_or(ProtocolError::WrongArity(name))?)
+}
+
+fn parse_with_scores<'a>(tokens: &mut impl Iterator<Item = &'a [u8]>) -> Result<bool> {
+    match tokens.next() {
+        None => Ok(false),
+        Some(option) if option.eq_ignore_ascii_case(b"WITHSCORES") => match tokens.next() {
+            None => Ok(true),
+            Some(_) => Err(ProtocolError::SyntaxError.into()),
+        },
+        Some(_) => Err(ProtocolError::SyntaxError.into()),
+    }
+}
+
+/// Parses a score the way Redis does: any float, plus `inf`, `+inf` and
+/// `-inf`, but never NaN.
+pub fn parse_float(arg: &[u8]) -> Result<f64> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse::<f64>().ok())
+        .filter(|value| !value.is_nan())
+        .ok_or_else(|| ProtocolError::NotAFloat.into())
+}
+
+pub fn format_float(value: f64) -> String {
+    value.to_string()
+}
+
+/// Looks up the sorted set stored at `key`, failing if the key holds a value
+/// of another type.
+pub fn lookup_zset<'a>(data: &'a mut Data, key: &[u8]) -> Result<Option<&'a mut ZSet>> {
+    match data.lookup(key) {
+        None | Some(Entry { value: None, .. }) => Ok(None),
+        Some(Entry {
+            value: Some(Value::ZSet(zset)),
+            ..
+        }) => Ok(Some(zset)),
+        Some(_) => Err(ProtocolError::WrongType.into()),
+    }
+}
+
+/// Like `lookup_zset`, but creates an empty sorted set if `key` is missing.
+pub fn lookup_or_create_zset<'a>(data: &'a mut Data, key: &[u8]) -> Result<&'a mut ZSet> {
+    if lookup_zset(data, key)?.is_none() {
+        data.lookup_or_insert(key).value = Some(Value::ZSet(Box::default()));
+    }
+    Ok(lookup_zset(data, key)?.ok_or(ProtocolError::WrongType)?)
+}
+
+pub fn parse_integer(arg: &[u8]) -> Result<i64> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| ProtocolError::NotAnInteger.into())
+}
+
+/// Returns the total length of the request at the start of `buf` once it has
+/// been fully received, or `None` if more bytes are needed to complete it.
+/// Requests over either of the `limits` are rejected as soon as their
+/// headers show it, without waiting for the payload.
+pub fn request_length(buf: &[u8], limits: RequestLimits) -> Result<Option<usize>> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let length = LittleEndian::read_u32(&buf[..4]);
+    if length == 0 || length as usize > limits.max_arguments {
+        return Err(ProtocolError::InvalidArgumentCount(length).into());
+    }
+    let mut current_pos = 4;
+    for _ in 0..length {
+        if buf.len() < current_pos + 4 {
+            return Ok(None);
+        }
+        let item_length = LittleEndian::read_u32(&buf[current_pos..current_pos + 4]) as usize;
+        if item_length > limits.max_bulk_len {
+            return Err(ProtocolError::ArgumentTooLarge {
+                limit: limits.max_bulk_len,
+            }
+            .into());
         }
+        current_pos += 4 + item_length;
     }
+    if buf.len() < current_pos {
+        return Ok(None);
+    }
+    Ok(Some(current_pos))
 }
 
-fn resolve_command_payload(request: &[u8]) -> Result<Vec<u8>> {
-    let mut items = Vec::new();
+/// Splits a complete native request, as delimited by `request_length`, into
+/// its arguments.
+pub fn decode_request(request: &[u8]) -> Result<Vec<Vec<u8>>> {
     if request.len() < 4 {
-        return Err(anyhow::anyhow!("Invalid request"));
+        return Err(ProtocolError::TruncatedRequest.into());
     }
     let length = LittleEndian::read_u32(&request[..4]);
-    if length < 2 || length > 3 {
-        return Err(anyhow::anyhow!("Invalid length"));
+    if length == 0 {
+        return Err(ProtocolError::InvalidArgumentCount(length).into());
     }
+    // Every argument takes at least its 4-byte length, which bounds how many
+    // a request of this size can really hold.
+    let mut args = Vec::with_capacity((length as usize).min(request.len() / 4));
     let mut current_pos = 4;
     for _ in 0..length {
-        let item_length = LittleEndian::read_u32(&request[current_pos..current_pos + 4]);
+        let item_length = request
+            .get(current_pos..current_pos + 4)
+            .map(LittleEndian::read_u32)
+            .ok_or(ProtocolError::TruncatedRequest)?;
         current_pos += 4;
-        let item = &request[current_pos..current_pos + item_length as usize];
-        items.extend_from_slice(item);
-        items.push(b' ');
+        let item = request
+            .get(current_pos..current_pos + item_length as usize)
+            .ok_or(ProtocolError::TruncatedRequest)?;
+        args.push(item.to_vec());
         current_pos += item_length as usize;
     }
-    items.pop();
-    Ok(items)
+    Ok(args)
 }
 
 #[cfg(test)]
@@ -91,8 +464,7 @@ mod tests {
     use std::str::from_utf8;
 
     fn generate_command_payload(args: Vec<String>) -> Vec<u8> {
-        let mut request = Vec::new();
-        request.resize(4, 0);
+        let mut request = vec![0; 4];
         LittleEndian::write_u32(&mut request[..4], args.len() as u32);
         let mut current_pos = 4;
         for arg in args.iter() {
@@ -105,6 +477,10 @@ mod tests {
         request
     }
 
+    fn parse_payload(request: &[u8]) -> Result<Command> {
+        Ok(Request::parse(&decode_request(request)?)?.command)
+    }
+
     #[test]
     fn test_generate_command_payload() {
         let args = vec!["GET".to_string(), "key".to_string()];
@@ -114,76 +490,366 @@ mod tests {
     }
 
     #[test]
-    fn test_resolve_command_request() {
+    fn test_decode_request() {
         // Valid GET request
         let args = vec!["GET".to_string(), "key".to_string()];
         let request = generate_command_payload(args);
-        let response = resolve_command_payload(&request);
+        let response = decode_request(&request);
         assert!(response.is_ok());
-        let response = response.unwrap();
-        let mut tokens = response.split(|b| *b == b' ');
-        assert_eq!(tokens.next().unwrap(), b"GET");
-        assert_eq!(tokens.next().unwrap(), b"key");
+        assert_eq!(response.unwrap(), vec![b"GET".to_vec(), b"key".to_vec()]);
 
-        // Valid SET request
-        let args = vec!["SET".to_string(), "key".to_string(), "value".to_string()];
+        // Valid SET request, with arguments that contain spaces
+        let args = vec!["SET".to_string(), "my key".to_string(), "a b c".to_string()];
         let request = generate_command_payload(args);
-        let response = resolve_command_payload(&request);
+        let response = decode_request(&request);
         assert!(response.is_ok());
-        let response = response.unwrap();
-        let mut tokens = response.split(|b| *b == b' ');
-        assert_eq!(tokens.next().unwrap(), b"SET");
-        assert_eq!(tokens.next().unwrap(), b"key");
-        assert_eq!(tokens.next().unwrap(), b"value");
+        assert_eq!(
+            response.unwrap(),
+            vec![b"SET".to_vec(), b"my key".to_vec(), b"a b c".to_vec()]
+        );
 
-        // Invalid request (length < 4)
+        // Any number of arguments
+        let args: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let request = generate_command_payload(args);
+        assert_eq!(decode_request(&request).unwrap().len(), 100);
+
+        // Invalid request (shorter than the 4-byte argument count)
         let invalid_request = vec![0, 0, 0];
-        let response = resolve_command_payload(&invalid_request);
+        let response = decode_request(&invalid_request);
         assert!(response.is_err());
 
-        // Invalid request (length prefix < 2)
+        // Invalid request (the count reads as 1 << 24 little-endian, more
+        // arguments than the 3 bytes after it can hold)
         let mut invalid_request = vec![0, 0, 0, 1];
         invalid_request.extend_from_slice(b"GET");
-        let response = resolve_command_payload(&invalid_request);
+        let response = decode_request(&invalid_request);
         assert!(response.is_err());
 
-        // Invalid request (length prefix > 3)
+        // Invalid request (the count reads as 4 << 24, and the text after it
+        // is not length-prefixed at all)
         let mut invalid_request = vec![0, 0, 0, 4];
         invalid_request.extend_from_slice(b"GET key value extra");
-        let response = resolve_command_payload(&invalid_request);
+        let response = decode_request(&invalid_request);
         assert!(response.is_err());
 
-        // Invalid request (incorrect item length)
+        // Invalid request (count and lengths written big-endian, so they read
+        // as 2 << 24 arguments, the first of them 3 << 24 bytes long)
         let mut invalid_request = vec![0, 0, 0, 2];
-        invalid_request.extend_from_slice(&[0, 0, 0, 3]); // Length of "GET"
+        invalid_request.extend_from_slice(&[0, 0, 0, 3]);
+        invalid_request.extend_from_slice(b"GET");
+        invalid_request.extend_from_slice(&[0, 0, 0, 5]);
+        invalid_request.extend_from_slice(b"key");
+        let response = decode_request(&invalid_request);
+        assert!(response.is_err());
+
+        // Invalid request (item length runs past the end of the payload)
+        let mut invalid_request = vec![2, 0, 0, 0];
+        invalid_request.extend_from_slice(&[3, 0, 0, 0]);
         invalid_request.extend_from_slice(b"GET");
-        invalid_request.extend_from_slice(&[0, 0, 0, 5]); // Incorrect length
+        invalid_request.extend_from_slice(&[5, 0, 0, 0]);
         invalid_request.extend_from_slice(b"key");
-        let response = resolve_command_payload(&invalid_request);
+        let response = decode_request(&invalid_request);
         assert!(response.is_err());
     }
 
+    #[test]
+    fn test_request_length() {
+        let args = vec!["SET".to_string(), "key".to_string(), "value".to_string()];
+        let request = generate_command_payload(args);
+        for end in 0..request.len() {
+            assert_eq!(
+                request_length(&request[..end], RequestLimits::default()).unwrap(),
+                None
+            );
+        }
+        assert_eq!(
+            request_length(&request, RequestLimits::default()).unwrap(),
+            Some(request.len())
+        );
+
+        let mut pipelined = request.clone();
+        pipelined.extend(generate_command_payload(vec![
+            "GET".to_string(),
+            "key".to_string(),
+        ]));
+        assert_eq!(
+            request_length(&pipelined, RequestLimits::default()).unwrap(),
+            Some(request.len())
+        );
+
+        let invalid_request = vec![0, 0, 0, 4];
+        assert!(request_length(&invalid_request, RequestLimits::default()).is_err());
+
+        let limits = RequestLimits {
+            max_arguments: 2,
+            ..RequestLimits::default()
+        };
+        let err = request_length(&request, limits).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::InvalidArgumentCount(3))
+        );
+    }
+
+    #[test]
+    fn test_request_length_limit() {
+        let value = "x".repeat(64 * 1024);
+        let args = vec!["SET".to_string(), "key".to_string(), value.clone()];
+        let request = generate_command_payload(args);
+        assert_eq!(
+            request_length(&request, RequestLimits::default()).unwrap(),
+            Some(request.len())
+        );
+        assert_eq!(
+            parse_payload(&request).unwrap(),
+            Command::Set(b"key".to_vec(), value.into_bytes(), set::Expiry::Clear)
+        );
+
+        // Rejected from the headers alone, before the value has arrived.
+        let limits = RequestLimits {
+            max_bulk_len: 1024,
+            ..RequestLimits::default()
+        };
+        let err = request_length(&request[..22], limits).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::ArgumentTooLarge { limit: 1024 })
+        );
+
+        // The limit is per argument, not for the request as a whole.
+        let value = "x".repeat(1000);
+        let args = vec![
+            "MSET".to_string(),
+            "a".to_string(),
+            value.clone(),
+            "b".to_string(),
+            value,
+        ];
+        let request = generate_command_payload(args);
+        assert!(request.len() > limits.max_bulk_len);
+        assert_eq!(
+            request_length(&request, limits).unwrap(),
+            Some(request.len())
+        );
+    }
+
     #[test]
     fn test_parse_request() {
         let args = vec!["GET".to_string(), "name".to_string()];
         let request = generate_command_payload(args);
-        let command = Command::parse_request(&request);
+        let command = parse_payload(&request);
         assert!(command.is_ok());
         let command = command.unwrap();
         assert_eq!(command, Command::Get(b"name".to_vec()));
 
         let args = vec!["SET".to_string(), "age".to_string(), "32".to_string()];
         let request = generate_command_payload(args);
-        let command = Command::parse_request(&request);
+        let command = parse_payload(&request);
         assert!(command.is_ok());
         let command = command.unwrap();
-        assert_eq!(command, Command::Set(b"age".to_vec(), b"32".to_vec()));
+        assert_eq!(
+            command,
+            Command::Set(b"age".to_vec(), b"32".to_vec(), set::Expiry::Clear)
+        );
 
         let args = vec!["DEL".to_string(), "name".to_string()];
         let request = generate_command_payload(args);
-        let command = Command::parse_request(&request);
+        let command = parse_payload(&request);
         assert!(command.is_ok());
         let command = command.unwrap();
-        assert_eq!(command, Command::Del(b"name".to_vec()));
+        assert_eq!(command, Command::Del(vec![b"name".to_vec()]));
+
+        let command = parse_payload(&generate_command_payload(vec![
+            "del".to_string(),
+            "a".to_string(),
+            "b c".to_string(),
+        ]));
+        assert_eq!(
+            command.unwrap(),
+            Command::Del(vec![b"a".to_vec(), b"b c".to_vec()])
+        );
+
+        let command = parse_payload(&generate_command_payload(vec![
+            "MSET".to_string(),
+            "a".to_string(),
+            "1".to_string(),
+            "b".to_string(),
+            "2".to_string(),
+        ]));
+        assert_eq!(
+            command.unwrap(),
+            Command::MSet(vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec())
+            ])
+        );
+
+        let command = parse_payload(&generate_command_payload(vec![
+            "MGET".to_string(),
+            "a".to_string(),
+            "b".to_string(),
+        ]));
+        assert_eq!(
+            command.unwrap(),
+            Command::MGet(vec![b"a".to_vec(), b"b".to_vec()])
+        );
+
+        let args = vec!["QUIT".to_string()];
+        let request = generate_command_payload(args);
+        let command = parse_payload(&request);
+        assert!(command.is_ok());
+        assert_eq!(command.unwrap(), Command::Quit);
+
+        for (name, expected) in [
+            ("SAVE", Command::Save),
+            ("BGSAVE", Command::BgSave),
+            ("LASTSAVE", Command::LastSave),
+            ("BGREWRITEAOF", Command::BgRewriteAof),
+            ("command", Command::Command(command::Subcommand::List)),
+        ] {
+            let request = generate_command_payload(vec![name.to_string()]);
+            assert_eq!(parse_payload(&request).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_expiry_commands() {
+        let parse = |args: Vec<&str>| {
+            let request = generate_command_payload(args.into_iter().map(String::from).collect());
+            parse_payload(&request)
+        };
+        assert_eq!(
+            parse(vec!["SET", "k", "v", "EX", "10"]).unwrap(),
+            Command::Set(b"k".to_vec(), b"v".to_vec(), set::Expiry::After(10_000))
+        );
+        assert_eq!(
+            parse(vec!["SET", "k", "v", "px", "1500"]).unwrap(),
+            Command::Set(b"k".to_vec(), b"v".to_vec(), set::Expiry::After(1500))
+        );
+        assert_eq!(
+            parse(vec!["SET", "k", "v", "EXAT", "1700000000"]).unwrap(),
+            Command::Set(
+                b"k".to_vec(),
+                b"v".to_vec(),
+                set::Expiry::At(1_700_000_000_000)
+            )
+        );
+        assert_eq!(
+            parse(vec!["SET", "k", "v", "PXAT", "1700000000123"]).unwrap(),
+            Command::Set(
+                b"k".to_vec(),
+                b"v".to_vec(),
+                set::Expiry::At(1_700_000_000_123)
+            )
+        );
+        assert_eq!(
+            parse(vec!["SET", "k", "v", "KEEPTTL"]).unwrap(),
+            Command::Set(b"k".to_vec(), b"v".to_vec(), set::Expiry::KeepTtl)
+        );
+        assert_eq!(
+            parse(vec!["EXPIRE", "k", "-5"]).unwrap(),
+            Command::Expire(b"k".to_vec(), -5)
+        );
+        assert_eq!(
+            parse(vec!["PEXPIRE", "k", "250"]).unwrap(),
+            Command::PExpire(b"k".to_vec(), 250)
+        );
+        assert_eq!(
+            parse(vec!["PEXPIREAT", "k", "1700000000000"]).unwrap(),
+            Command::PExpireAt(b"k".to_vec(), 1_700_000_000_000)
+        );
+        assert_eq!(
+            parse(vec!["TTL", "k"]).unwrap(),
+            Command::Ttl(b"k".to_vec())
+        );
+        assert_eq!(
+            parse(vec!["PTTL", "k"]).unwrap(),
+            Command::PTtl(b"k".to_vec())
+        );
+        assert_eq!(
+            parse(vec!["PERSIST", "k"]).unwrap(),
+            Command::Persist(b"k".to_vec())
+        );
+
+        let error = |args: Vec<&str>| {
+            parse(args)
+                .unwrap_err()
+                .downcast::<ProtocolError>()
+                .unwrap()
+        };
+        assert_eq!(
+            error(vec!["SET", "k", "v", "EX"]),
+            ProtocolError::SyntaxError
+        );
+        assert_eq!(
+            error(vec!["SET", "k", "v", "EX", "ten"]),
+            ProtocolError::NotAnInteger
+        );
+        assert_eq!(
+            error(vec!["SET", "k", "v", "EX", "0"]),
+            ProtocolError::InvalidExpireTime("set")
+        );
+        // In milliseconds, past i64::MAX.
+        assert_eq!(
+            error(vec!["SET", "k", "v", "EX", "9223372036854776"]),
+            ProtocolError::InvalidExpireTime("set")
+        );
+        assert_eq!(
+            error(vec!["SET", "k", "v", "SOON", "1"]),
+            ProtocolError::SyntaxError
+        );
+        assert_eq!(
+            error(vec!["EXPIRE", "k", "1.5"]),
+            ProtocolError::NotAnInteger
+        );
+    }
+
+    #[test]
+    fn test_parse_request_errors() {
+        let parse_error = |args: Vec<&str>| {
+            let request = generate_command_payload(args.into_iter().map(String::from).collect());
+            parse_payload(&request)
+                .unwrap_err()
+                .downcast::<ProtocolError>()
+                .unwrap()
+        };
+
+        let err = parse_error(vec!["FLY", "key"]);
+        assert_eq!(err, ProtocolError::UnknownCommand("FLY".to_string()));
+        assert_eq!(err.code(), ErrorCode::Unknown);
+        assert!(!err.is_fatal());
+
+        let err = parse_error(vec!["GET"]);
+        assert_eq!(err, ProtocolError::WrongArity("get"));
+        assert_eq!(err.code(), ErrorCode::Arg);
+        assert!(!err.is_fatal());
+
+        let err = parse_error(vec!["SET", "key"]);
+        assert_eq!(err, ProtocolError::WrongArity("set"));
+
+        let err = parse_error(vec!["MSET", "a", "1", "b"]);
+        assert_eq!(err, ProtocolError::WrongArity("mset"));
+
+        let err = parse_error(vec!["ZADD", "z", "1"]);
+        assert_eq!(err, ProtocolError::WrongArity("zadd"));
+
+        let err = parse_error(vec!["COMMAND", "FLY"]);
+        assert_eq!(
+            err,
+            ProtocolError::UnknownSubcommand {
+                command: "COMMAND",
+                name: "FLY".to_string()
+            }
+        );
+
+        let err = parse_error(vec!["COMMAND", "COUNT", "extra"]);
+        assert_eq!(err, ProtocolError::WrongArity("command|count"));
+
+        let err = request_length(&[0, 0, 0, 0], RequestLimits::default())
+            .unwrap_err()
+            .downcast::<ProtocolError>()
+            .unwrap();
+        assert_eq!(err, ProtocolError::InvalidArgumentCount(0));
+        assert!(err.is_fatal());
     }
 }
@@ -0,0 +1,15 @@
+use crate::{
+    entry::{Data, Value},
+    serialization::{response_ok, Output},
+};
+use anyhow::Result;
+
+/// Sets every pair like a plain SET, discarding any previous TTL.
+pub fn invoke(data: &mut Data, pairs: Vec<(Vec<u8>, Vec<u8>)>, out: &mut Output) -> Result<()> {
+    for (key, value) in pairs {
+        data.lookup_or_insert(&key).value = Some(Value::String(value));
+        data.set_expiry(&key, None);
+    }
+    response_ok(out);
+    Ok(())
+}
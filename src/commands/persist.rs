@@ -0,0 +1,17 @@
+use crate::{
+    entry::Data,
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+/// PERSIST: 1 if a TTL was removed, 0 if the key is missing or has none.
+pub fn invoke(data: &mut Data, key: Vec<u8>, out: &mut Output) -> Result<()> {
+    let has_expiry = data
+        .lookup(&key)
+        .is_some_and(|entry| entry.expire_at().is_some());
+    if has_expiry {
+        data.set_expiry(&key, None);
+    }
+    response_integer(out, has_expiry as i64);
+    Ok(())
+}
@@ -0,0 +1,10 @@
+use crate::serialization::{response_status, response_string, Output};
+use anyhow::Result;
+
+pub fn invoke(message: Option<Vec<u8>>, out: &mut Output) -> Result<()> {
+    match message {
+        Some(message) => response_string(out, &message),
+        None => response_status(out, "PONG"),
+    }
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use super::ProtocolError;
+use crate::{
+    entry::Data,
+    rdb::Snapshots,
+    serialization::{response_status, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(snapshots: &mut Snapshots, data: &Data, out: &mut Output) -> Result<()> {
+    if snapshots.in_progress() {
+        return Err(ProtocolError::BackgroundSaveInProgress.into());
+    }
+    snapshots.save(data)?;
+    response_status(out, "OK");
+    Ok(())
+}
@@ -1,40 +1,117 @@
+use super::{parse_integer, ProtocolError};
 use crate::{
-    entry::Entry,
-    hashtable::{fnv1a_hash, HashNode},
-    scalablehashmap::ScalableHashMap,
-    serialization::response_nil,
+    entry::{now_ms, Data, Value},
+    serialization::{response_ok, Output},
 };
 use anyhow::Result;
-use container_of::container_of;
+
+/// What a SET does to the key's time to live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// No option given: any existing TTL is discarded.
+    Clear,
+    /// `KEEPTTL`: the existing TTL is retained.
+    KeepTtl,
+    /// `EX` / `PX`: milliseconds from now.
+    After(u64),
+    /// `EXAT` / `PXAT`: milliseconds since the Unix epoch.
+    At(u64),
+}
+
+/// The latest deadline a key can be given, in milliseconds since the Unix
+/// epoch. TTL and PTTL report times to live as signed integers.
+pub const MAX_DEADLINE: u64 = i64::MAX as u64;
+
+impl Expiry {
+    pub fn parse<'a>(tokens: &mut impl Iterator<Item = &'a [u8]>) -> Result<Expiry> {
+        let Some(option) = tokens.next() else {
+            return Ok(Expiry::Clear);
+        };
+        let option = option.to_ascii_uppercase();
+        if option == b"KEEPTTL" {
+            return match tokens.next() {
+                Some(_) => Err(ProtocolError::SyntaxError.into()),
+                None => Ok(Expiry::KeepTtl),
+            };
+        }
+        let scale = match option.as_slice() {
+            b"EX" | b"EXAT" => 1000,
+            b"PX" | b"PXAT" => 1,
+            _ => return Err(ProtocolError::SyntaxError.into()),
+        };
+        let time = tokens.next().ok_or(ProtocolError::SyntaxError)?;
+        let time = parse_integer(time)?;
+        if tokens.next().is_some() {
+            return Err(ProtocolError::SyntaxError.into());
+        }
+        let millis = u64::try_from(time)
+            .ok()
+            .filter(|time| *time > 0)
+            .and_then(|time| time.checked_mul(scale))
+            .filter(|millis| *millis <= MAX_DEADLINE)
+            .ok_or(ProtocolError::InvalidExpireTime("set"))?;
+        match option.as_slice() {
+            b"EX" | b"PX" => Ok(Expiry::After(millis)),
+            _ => Ok(Expiry::At(millis)),
+        }
+    }
+}
 
 pub fn invoke(
-    db: &mut ScalableHashMap,
+    data: &mut Data,
     key: Vec<u8>,
     value: Vec<u8>,
-    out: &mut Vec<u8>,
+    expiry: Expiry,
+    out: &mut Output,
 ) -> Result<()> {
-    let code = fnv1a_hash(&key);
-    let key_for_not_found = key.clone();
-    let node = HashNode::new(None, code);
-    let entry = Entry::new(node, key, Some(value.clone()));
-    let cmp = Entry::check_entry_equality;
-    let found = db.lookup_mut(&entry.node, cmp);
-    if found.is_none() {
-        let node = HashNode::new(None, code);
-        let mut entry = Entry::new(node, key_for_not_found, Some(value));
-        db.insert(&mut entry.node);
-        response_nil(out);
-        return Ok(());
+    // Resolved before the write, so a rejected SET leaves the key alone.
+    let deadline = match expiry {
+        Expiry::Clear | Expiry::KeepTtl => None,
+        Expiry::After(millis) => Some(
+            now_ms()
+                .checked_add(millis)
+                .filter(|deadline| *deadline <= MAX_DEADLINE)
+                .ok_or(ProtocolError::InvalidExpireTime("set"))?,
+        ),
+        Expiry::At(deadline) => Some(deadline),
+    };
+    data.lookup_or_insert(&key).value = Some(Value::String(value));
+    if expiry != Expiry::KeepTtl {
+        data.set_expiry(&key, deadline);
     }
-    let found = found.unwrap();
+    response_ok(out);
+    Ok(())
+}
 
-    let entry = unsafe {
-        let found = found as *mut HashNode;
-        let e = container_of!(found, Entry, node);
-        &mut *e
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::ttl;
 
-    entry.value = Some(value);
-    response_nil(out);
-    Ok(())
+    #[test]
+    fn test_deadline_fits_in_i64() {
+        let mut data = Data::new();
+        let mut set = |millis| {
+            let expiry = Expiry::After(millis);
+            invoke(
+                &mut data,
+                b"k".to_vec(),
+                b"v".to_vec(),
+                expiry,
+                &mut Output::default(),
+            )
+        };
+        let err = set(MAX_DEADLINE - now_ms() + 1000).unwrap_err();
+        assert_eq!(
+            err.downcast::<ProtocolError>().unwrap(),
+            ProtocolError::InvalidExpireTime("set")
+        );
+        set(MAX_DEADLINE - now_ms() - 1000).unwrap();
+
+        let mut out = Output::default();
+        ttl::invoke(&mut data, b"k".to_vec(), ttl::Unit::Milliseconds, &mut out).unwrap();
+        // An integer reply: the type byte, then the value.
+        let pttl = i64::from_le_bytes(out.as_bytes()[1..9].try_into().unwrap());
+        assert!(pttl > 0);
+    }
 }
@@ -0,0 +1,107 @@
+use super::ProtocolError;
+use anyhow::Result;
+
+/// Whether shutting down writes a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMode {
+    /// Save unless the append only file already has every write.
+    Default,
+    Save,
+    NoSave,
+}
+
+impl SaveMode {
+    pub fn parse(value: &str) -> Option<SaveMode> {
+        match value.to_ascii_lowercase().as_str() {
+            "default" => Some(SaveMode::Default),
+            "save" => Some(SaveMode::Save),
+            "nosave" => Some(SaveMode::NoSave),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SaveMode::Default => "default",
+            SaveMode::Save => "save",
+            SaveMode::NoSave => "nosave",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub save: SaveMode,
+    /// Exit without waiting for pending replies to be flushed.
+    pub now: bool,
+    /// Exit even if persisting the keyspace fails.
+    pub force: bool,
+}
+
+impl Options {
+    pub fn new(save: SaveMode) -> Options {
+        Options {
+            save,
+            now: false,
+            force: false,
+        }
+    }
+}
+
+/// SHUTDOWN [NOSAVE|SAVE] [NOW] [FORCE], or SHUTDOWN ABORT.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Start(Options),
+    Abort,
+}
+
+impl Request {
+    pub fn parse(args: &[Vec<u8>]) -> Result<Request> {
+        if let [abort] = args {
+            if abort.eq_ignore_ascii_case(b"ABORT") {
+                return Ok(Request::Abort);
+            }
+        }
+        let mut options = Options::new(SaveMode::Default);
+        for arg in args {
+            match arg.to_ascii_uppercase().as_slice() {
+                b"SAVE" if options.save == SaveMode::Default => options.save = SaveMode::Save,
+                b"NOSAVE" if options.save == SaveMode::Default => options.save = SaveMode::NoSave,
+                b"NOW" => options.now = true,
+                b"FORCE" => options.force = true,
+                _ => return Err(ProtocolError::SyntaxError.into()),
+            }
+        }
+        Ok(Request::Start(options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Request> {
+        let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        Request::parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(&[]).unwrap(),
+            Request::Start(Options::new(SaveMode::Default))
+        );
+        assert_eq!(
+            parse(&["nosave", "NOW", "force"]).unwrap(),
+            Request::Start(Options {
+                save: SaveMode::NoSave,
+                now: true,
+                force: true
+            })
+        );
+        assert_eq!(parse(&["abort"]).unwrap(), Request::Abort);
+        assert!(parse(&["SAVE", "NOSAVE"]).is_err());
+        assert!(parse(&["ABORT", "NOW"]).is_err());
+        assert!(parse(&["LATER"]).is_err());
+    }
+}
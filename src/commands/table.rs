@@ -0,0 +1,876 @@
+//! The command table: every command the server understands, with the
+//! metadata COMMAND reports, the parser that turns its arguments into a
+//! `Command` and the handler that runs it. Adding a command means adding an
+//! entry here.
+
+use super::{
+    client, command, config, del, expire, get, hello, incr, mget, mset, next_arg, parse_float,
+    parse_integer, parse_with_scores, persist, set, shutdown, ttl, zadd, zcard, zincrby, zrange,
+    zrangebyscore, zrank, zrem, zscore, Command, ProtocolError, Request,
+};
+use crate::{
+    entry::{now_ms, Data},
+    serialization::Output,
+    server,
+};
+use anyhow::Result;
+use std::{fmt, sync::OnceLock};
+
+/// The command may modify the keyspace.
+pub const WRITE: u8 = 1 << 0;
+/// The command only reads the keyspace.
+pub const READONLY: u8 = 1 << 1;
+/// The command manages the server rather than the data in it.
+pub const ADMIN: u8 = 1 << 2;
+/// The command runs in constant or logarithmic time.
+pub const FAST: u8 = 1 << 3;
+/// The command may block the client.
+pub const BLOCKING: u8 = 1 << 4;
+
+const FLAG_NAMES: [(u8, &str); 5] = [
+    (WRITE, "write"),
+    (READONLY, "readonly"),
+    (ADMIN, "admin"),
+    (FAST, "fast"),
+    (BLOCKING, "blocking"),
+];
+
+/// Runs a parsed command, writing its reply. Handlers are only given the
+/// `Command` variants their own entry's parser builds.
+#[derive(Clone, Copy)]
+pub(crate) enum Handler {
+    /// Touches nothing but the keyspace, so the append only file can be
+    /// replayed through it.
+    Keyspace(fn(&mut Data, Command, &mut Output) -> Result<()>),
+    /// Acts on the server or the client's connection; only the event loop
+    /// runs these.
+    Server(fn(&mut server::Context, Command) -> Result<()>),
+}
+
+pub struct CommandSpec {
+    /// Lowercase name, as COMMAND reports it.
+    pub name: &'static str,
+    /// Number of arguments including the name. Negative means at least that
+    /// many, as in Redis.
+    pub arity: i32,
+    pub flags: u8,
+    /// Position of the first key argument, 0 if the command takes no keys.
+    pub first_key: i32,
+    /// Position of the last key argument; negative counts from the end.
+    pub last_key: i32,
+    /// Distance between key arguments.
+    pub key_step: i32,
+    pub group: &'static str,
+    pub summary: &'static str,
+    /// Builds the command from arguments that already satisfy `arity`.
+    pub parse: fn(&[Vec<u8>]) -> Result<Command>,
+    pub(crate) handler: Handler,
+}
+
+impl fmt::Debug for CommandSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandSpec")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Every entry is a distinct command, so entries compare by identity.
+impl PartialEq for CommandSpec {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl CommandSpec {
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn check_arity(&self, args: usize) -> Result<()> {
+        let args = args as i32;
+        let valid = if self.arity >= 0 {
+            args == self.arity
+        } else {
+            args >= -self.arity
+        };
+        if !valid {
+            return Err(ProtocolError::WrongArity(self.name).into());
+        }
+        Ok(())
+    }
+
+    /// Checks the arity of `args`, which start with this command's name,
+    /// and parses them into a request this entry will run.
+    pub fn parse_request(&'static self, args: &[Vec<u8>]) -> Result<Request> {
+        self.check_arity(args.len())?;
+        let command = (self.parse)(args)?;
+        Ok(Request {
+            spec: self,
+            command,
+        })
+    }
+
+    pub fn flag_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.has_flag(*flag))
+            .map(|(_, name)| *name)
+    }
+
+    /// ACL categories, derived from the flags and the group.
+    pub fn acl_categories(&self) -> Vec<&'static str> {
+        let mut categories: Vec<&'static str> = Vec::new();
+        if self.has_flag(WRITE) {
+            categories.push("@write");
+        }
+        if self.has_flag(READONLY) {
+            categories.push("@read");
+        }
+        if self.has_flag(ADMIN) {
+            categories.extend(["@admin", "@dangerous"]);
+        }
+        categories.push(if self.has_flag(FAST) {
+            "@fast"
+        } else {
+            "@slow"
+        });
+        if self.has_flag(BLOCKING) {
+            categories.push("@blocking");
+        }
+        match self.group {
+            "generic" => categories.push("@keyspace"),
+            "string" => categories.push("@string"),
+            "sorted-set" => categories.push("@sortedset"),
+            "connection" => categories.push("@connection"),
+            _ => {}
+        }
+        categories
+    }
+}
+
+/// Finds the command called `name`, ignoring case.
+pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    static BY_NAME: OnceLock<Vec<&'static CommandSpec>> = OnceLock::new();
+    let by_name = BY_NAME.get_or_init(|| {
+        let mut by_name: Vec<_> = COMMANDS.iter().collect();
+        by_name.sort_by_key(|spec| spec.name);
+        by_name
+    });
+    let index = by_name
+        .binary_search_by(|spec| {
+            let name = name.iter().map(u8::to_ascii_lowercase);
+            spec.name.bytes().cmp(name)
+        })
+        .ok()?;
+    Some(by_name[index])
+}
+
+pub fn commands() -> &'static [CommandSpec] {
+    COMMANDS
+}
+
+static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "get",
+        arity: 2,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Returns the string value of a key.",
+        parse: |args| Ok(Command::Get(args[1].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::Get(key) = command else {
+                unreachable!()
+            };
+            get::invoke(data, key, out)
+        }),
+    },
+    CommandSpec {
+        name: "set",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Sets the string value of a key, optionally with an expiration.",
+        parse: |args| {
+            let expiry = set::Expiry::parse(&mut args[3..].iter().map(Vec::as_slice))?;
+            Ok(Command::Set(args[1].clone(), args[2].clone(), expiry))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::Set(key, value, expiry) = command else {
+                unreachable!()
+            };
+            set::invoke(data, key, value, expiry, out)
+        }),
+    },
+    CommandSpec {
+        name: "del",
+        arity: -2,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        key_step: 1,
+        group: "generic",
+        summary: "Deletes one or more keys.",
+        parse: |args| Ok(Command::Del(args[1..].to_vec())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::Del(keys) = command else {
+                unreachable!()
+            };
+            del::invoke(data, keys, out)
+        }),
+    },
+    CommandSpec {
+        name: "mget",
+        arity: -2,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: -1,
+        key_step: 1,
+        group: "string",
+        summary: "Returns the string values of one or more keys.",
+        parse: |args| Ok(Command::MGet(args[1..].to_vec())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::MGet(keys) = command else {
+                unreachable!()
+            };
+            mget::invoke(data, keys, out)
+        }),
+    },
+    CommandSpec {
+        name: "mset",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        key_step: 2,
+        group: "string",
+        summary: "Sets the string values of one or more keys.",
+        parse: |args| {
+            let mut tokens = args[1..].iter().map(Vec::as_slice);
+            let mut pairs = Vec::new();
+            while let Some(key) = tokens.next() {
+                let value = next_arg(&mut tokens, "mset")?;
+                pairs.push((key.to_vec(), value.to_vec()));
+            }
+            Ok(Command::MSet(pairs))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::MSet(pairs) = command else {
+                unreachable!()
+            };
+            mset::invoke(data, pairs, out)
+        }),
+    },
+    CommandSpec {
+        name: "incr",
+        arity: 2,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Increments the integer value of a key by one.",
+        parse: |args| Ok(Command::IncrBy(args[1].clone(), 1)),
+        handler: Handler::Keyspace(incr_by),
+    },
+    CommandSpec {
+        name: "decr",
+        arity: 2,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Decrements the integer value of a key by one.",
+        parse: |args| Ok(Command::IncrBy(args[1].clone(), -1)),
+        handler: Handler::Keyspace(incr_by),
+    },
+    CommandSpec {
+        name: "incrby",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Increments the integer value of a key by a number.",
+        parse: |args| Ok(Command::IncrBy(args[1].clone(), parse_integer(&args[2])?)),
+        handler: Handler::Keyspace(incr_by),
+    },
+    CommandSpec {
+        name: "decrby",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Decrements the integer value of a key by a number.",
+        parse: |args| {
+            let decrement = parse_integer(&args[2])?;
+            let increment = decrement
+                .checked_neg()
+                .ok_or(ProtocolError::IncrementOverflow)?;
+            Ok(Command::IncrBy(args[1].clone(), increment))
+        },
+        handler: Handler::Keyspace(incr_by),
+    },
+    CommandSpec {
+        name: "incrbyfloat",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "string",
+        summary: "Increments the floating point value of a key by a number.",
+        parse: |args| {
+            Ok(Command::IncrByFloat(
+                args[1].clone(),
+                parse_float(&args[2])?,
+            ))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::IncrByFloat(key, increment) = command else {
+                unreachable!()
+            };
+            incr::invoke_float(data, key, increment, out)
+        }),
+    },
+    CommandSpec {
+        name: "expire",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Sets the expiration time of a key in seconds.",
+        parse: |args| Ok(Command::Expire(args[1].clone(), parse_integer(&args[2])?)),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::Expire(key, seconds) = command else {
+                unreachable!()
+            };
+            let deadline = (now_ms() as i64).saturating_add(seconds.saturating_mul(1000));
+            expire::invoke(data, key, deadline, out)
+        }),
+    },
+    CommandSpec {
+        name: "pexpire",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Sets the expiration time of a key in milliseconds.",
+        parse: |args| Ok(Command::PExpire(args[1].clone(), parse_integer(&args[2])?)),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::PExpire(key, millis) = command else {
+                unreachable!()
+            };
+            expire::invoke(data, key, (now_ms() as i64).saturating_add(millis), out)
+        }),
+    },
+    CommandSpec {
+        name: "expireat",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+        parse: |args| Ok(Command::ExpireAt(args[1].clone(), parse_integer(&args[2])?)),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ExpireAt(key, seconds) = command else {
+                unreachable!()
+            };
+            expire::invoke(data, key, seconds.saturating_mul(1000), out)
+        }),
+    },
+    CommandSpec {
+        name: "pexpireat",
+        arity: 3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        parse: |args| {
+            Ok(Command::PExpireAt(
+                args[1].clone(),
+                parse_integer(&args[2])?,
+            ))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::PExpireAt(key, millis) = command else {
+                unreachable!()
+            };
+            expire::invoke(data, key, millis, out)
+        }),
+    },
+    CommandSpec {
+        name: "ttl",
+        arity: 2,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Returns the time to live of a key in seconds.",
+        parse: |args| Ok(Command::Ttl(args[1].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::Ttl(key) = command else {
+                unreachable!()
+            };
+            ttl::invoke(data, key, ttl::Unit::Seconds, out)
+        }),
+    },
+    CommandSpec {
+        name: "pttl",
+        arity: 2,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Returns the time to live of a key in milliseconds.",
+        parse: |args| Ok(Command::PTtl(args[1].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::PTtl(key) = command else {
+                unreachable!()
+            };
+            ttl::invoke(data, key, ttl::Unit::Milliseconds, out)
+        }),
+    },
+    CommandSpec {
+        name: "persist",
+        arity: 2,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "generic",
+        summary: "Removes the expiration time of a key.",
+        parse: |args| Ok(Command::Persist(args[1].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::Persist(key) = command else {
+                unreachable!()
+            };
+            persist::invoke(data, key, out)
+        }),
+    },
+    CommandSpec {
+        name: "zadd",
+        arity: -4,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Adds members to a sorted set, or updates their scores.",
+        parse: |args| {
+            let mut tokens = args[2..].iter().map(Vec::as_slice);
+            let mut pairs = Vec::new();
+            while let Some(score) = tokens.next() {
+                let member = next_arg(&mut tokens, "zadd")?.to_vec();
+                pairs.push((parse_float(score)?, member));
+            }
+            Ok(Command::ZAdd(args[1].clone(), pairs))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZAdd(key, pairs) = command else {
+                unreachable!()
+            };
+            zadd::invoke(data, key, pairs, out)
+        }),
+    },
+    CommandSpec {
+        name: "zrem",
+        arity: -3,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Removes members from a sorted set.",
+        parse: |args| Ok(Command::ZRem(args[1].clone(), args[2..].to_vec())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZRem(key, members) = command else {
+                unreachable!()
+            };
+            zrem::invoke(data, key, members, out)
+        }),
+    },
+    CommandSpec {
+        name: "zscore",
+        arity: 3,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Returns the score of a member in a sorted set.",
+        parse: |args| Ok(Command::ZScore(args[1].clone(), args[2].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZScore(key, member) = command else {
+                unreachable!()
+            };
+            zscore::invoke(data, key, member, out)
+        }),
+    },
+    CommandSpec {
+        name: "zrange",
+        arity: -4,
+        flags: READONLY,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Returns members of a sorted set by rank.",
+        parse: |args| {
+            let (key, start, stop, with_scores) = parse_rank_range(args)?;
+            Ok(Command::ZRange(key, start, stop, with_scores))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZRange(key, start, stop, with_scores) = command else {
+                unreachable!()
+            };
+            zrange::invoke(data, key, start, stop, with_scores, false, out)
+        }),
+    },
+    CommandSpec {
+        name: "zrevrange",
+        arity: -4,
+        flags: READONLY,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Returns members of a sorted set by rank, highest score first.",
+        parse: |args| {
+            let (key, start, stop, with_scores) = parse_rank_range(args)?;
+            Ok(Command::ZRevRange(key, start, stop, with_scores))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZRevRange(key, start, stop, with_scores) = command else {
+                unreachable!()
+            };
+            zrange::invoke(data, key, start, stop, with_scores, true, out)
+        }),
+    },
+    CommandSpec {
+        name: "zrangebyscore",
+        arity: -4,
+        flags: READONLY,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Returns members of a sorted set within a range of scores.",
+        parse: |args| {
+            let range = zrangebyscore::ScoreRange::parse(&mut args[2..].iter().map(Vec::as_slice))?;
+            Ok(Command::ZRangeByScore(args[1].clone(), range))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZRangeByScore(key, range) = command else {
+                unreachable!()
+            };
+            zrangebyscore::invoke(data, key, range, out)
+        }),
+    },
+    CommandSpec {
+        name: "zrank",
+        arity: 3,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Returns the rank of a member in a sorted set.",
+        parse: |args| Ok(Command::ZRank(args[1].clone(), args[2].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZRank(key, member) = command else {
+                unreachable!()
+            };
+            zrank::invoke(data, key, member, out)
+        }),
+    },
+    CommandSpec {
+        name: "zcard",
+        arity: 2,
+        flags: READONLY | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Returns the number of members in a sorted set.",
+        parse: |args| Ok(Command::ZCard(args[1].clone())),
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZCard(key) = command else {
+                unreachable!()
+            };
+            zcard::invoke(data, key, out)
+        }),
+    },
+    CommandSpec {
+        name: "zincrby",
+        arity: 4,
+        flags: WRITE | FAST,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        group: "sorted-set",
+        summary: "Increments the score of a member in a sorted set.",
+        parse: |args| {
+            let increment = parse_float(&args[2])?;
+            Ok(Command::ZIncrBy(
+                args[1].clone(),
+                increment,
+                args[3].clone(),
+            ))
+        },
+        handler: Handler::Keyspace(|data, command, out| {
+            let Command::ZIncrBy(key, increment, member) = command else {
+                unreachable!()
+            };
+            zincrby::invoke(data, key, increment, member, out)
+        }),
+    },
+    CommandSpec {
+        name: "save",
+        arity: 1,
+        flags: ADMIN,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Synchronously saves the keyspace to disk.",
+        parse: |_| Ok(Command::Save),
+        handler: Handler::Server(server::handlers::save),
+    },
+    CommandSpec {
+        name: "bgsave",
+        arity: 1,
+        flags: ADMIN,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Asynchronously saves the keyspace to disk.",
+        parse: |_| Ok(Command::BgSave),
+        handler: Handler::Server(server::handlers::bgsave),
+    },
+    CommandSpec {
+        name: "info",
+        arity: -1,
+        flags: 0,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Returns information and statistics about the server.",
+        parse: |args| {
+            let sections = args[1..]
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).to_ascii_lowercase())
+                .collect();
+            Ok(Command::Info(sections))
+        },
+        handler: Handler::Server(server::handlers::info),
+    },
+    CommandSpec {
+        name: "lastsave",
+        arity: 1,
+        flags: FAST,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Returns the Unix timestamp of the last successful save to disk.",
+        parse: |_| Ok(Command::LastSave),
+        handler: Handler::Server(server::handlers::lastsave),
+    },
+    CommandSpec {
+        name: "bgrewriteaof",
+        arity: 1,
+        flags: ADMIN,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Asynchronously rewrites the append-only file.",
+        parse: |_| Ok(Command::BgRewriteAof),
+        handler: Handler::Server(server::handlers::bgrewriteaof),
+    },
+    CommandSpec {
+        name: "client",
+        arity: -2,
+        flags: 0,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "connection",
+        summary: "Inspects and manages client connections.",
+        parse: |args| Ok(Command::Client(client::Subcommand::parse(&args[1..])?)),
+        handler: Handler::Server(server::handlers::client),
+    },
+    CommandSpec {
+        name: "command",
+        arity: -1,
+        flags: 0,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Returns detailed information about commands.",
+        parse: |args| Ok(Command::Command(command::Subcommand::parse(&args[1..])?)),
+        handler: Handler::Server(server::handlers::command),
+    },
+    CommandSpec {
+        name: "config",
+        arity: -2,
+        flags: ADMIN,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Reads, changes or persists the server's settings.",
+        parse: |args| Ok(Command::Config(config::Subcommand::parse(&args[1..])?)),
+        handler: Handler::Server(server::handlers::config),
+    },
+    CommandSpec {
+        name: "shutdown",
+        arity: -1,
+        flags: ADMIN,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "server",
+        summary: "Persists the keyspace if configured and stops the server.",
+        parse: |args| Ok(Command::Shutdown(shutdown::Request::parse(&args[1..])?)),
+        handler: Handler::Server(server::handlers::shutdown),
+    },
+    CommandSpec {
+        name: "ping",
+        arity: -1,
+        flags: FAST,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "connection",
+        summary: "Returns the server's liveliness response.",
+        parse: |args| match args {
+            [_] => Ok(Command::Ping(None)),
+            [_, message] => Ok(Command::Ping(Some(message.clone()))),
+            _ => Err(ProtocolError::WrongArity("ping").into()),
+        },
+        handler: Handler::Server(server::handlers::ping),
+    },
+    CommandSpec {
+        name: "hello",
+        arity: -1,
+        flags: FAST,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "connection",
+        summary: "Handshakes with the server, optionally switching protocol.",
+        parse: |args| Ok(Command::Hello(hello::Request::parse(&args[1..])?)),
+        handler: Handler::Server(server::handlers::hello),
+    },
+    CommandSpec {
+        name: "quit",
+        arity: -1,
+        flags: FAST,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        group: "connection",
+        summary: "Closes the connection.",
+        parse: |_| Ok(Command::Quit),
+        handler: Handler::Server(server::handlers::quit),
+    },
+];
+
+/// Shared by INCR, DECR, INCRBY and DECRBY.
+fn incr_by(data: &mut Data, command: Command, out: &mut Output) -> Result<()> {
+    let Command::IncrBy(key, increment) = command else {
+        unreachable!()
+    };
+    incr::invoke(data, key, increment, out)
+}
+
+/// `key start stop [WITHSCORES]`, shared by ZRANGE and ZREVRANGE.
+fn parse_rank_range(args: &[Vec<u8>]) -> Result<(Vec<u8>, i64, i64, bool)> {
+    let start = parse_integer(&args[2])?;
+    let stop = parse_integer(&args[3])?;
+    let with_scores = parse_with_scores(&mut args[4..].iter().map(Vec::as_slice))?;
+    Ok((args[1].clone(), start, stop, with_scores))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_ignores_case() {
+        assert_eq!(lookup(b"GeT").unwrap().name, "get");
+        assert!(lookup(b"fly").is_none());
+        for spec in commands() {
+            assert_eq!(spec.name, spec.name.to_ascii_lowercase());
+            assert!(std::ptr::eq(lookup(spec.name.as_bytes()).unwrap(), spec));
+            assert!(!(spec.has_flag(WRITE) && spec.has_flag(READONLY)));
+        }
+    }
+
+    #[test]
+    fn test_keyspace_commands_read_or_write() {
+        // The event loop decides from these flags whether to log a command.
+        for spec in commands() {
+            let keyspace = matches!(spec.handler, Handler::Keyspace(_));
+            assert_eq!(
+                keyspace,
+                spec.has_flag(WRITE) || spec.has_flag(READONLY),
+                "{}",
+                spec.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_arity() {
+        let get = lookup(b"get").unwrap();
+        assert!(get.check_arity(2).is_ok());
+        assert!(get.check_arity(1).is_err());
+        assert!(get.check_arity(3).is_err());
+
+        let del = lookup(b"del").unwrap();
+        assert!(del.check_arity(1).is_err());
+        assert!(del.check_arity(2).is_ok());
+        assert!(del.check_arity(10).is_ok());
+    }
+
+    #[test]
+    fn test_acl_categories() {
+        let set = lookup(b"set").unwrap();
+        assert_eq!(set.acl_categories(), vec!["@write", "@slow", "@string"]);
+        let bgsave = lookup(b"bgsave").unwrap();
+        assert_eq!(
+            bgsave.acl_categories(),
+            vec!["@admin", "@dangerous", "@slow"]
+        );
+        assert_eq!(
+            lookup(b"zscore").unwrap().flag_names().collect::<Vec<_>>(),
+            vec!["readonly", "fast"]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use crate::{
+    entry::{now_ms, Data},
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Seconds,
+    Milliseconds,
+}
+
+/// TTL / PTTL: -2 if the key does not exist, -1 if it has no expiry,
+/// otherwise the remaining time to live.
+pub fn invoke(data: &mut Data, key: Vec<u8>, unit: Unit, out: &mut Output) -> Result<()> {
+    let Some(entry) = data.lookup(&key) else {
+        response_integer(out, -2);
+        return Ok(());
+    };
+    let Some(deadline) = entry.expire_at() else {
+        response_integer(out, -1);
+        return Ok(());
+    };
+    let remaining = deadline.saturating_sub(now_ms()) as i64;
+    match unit {
+        Unit::Seconds => response_integer(out, (remaining + 500) / 1000),
+        Unit::Milliseconds => response_integer(out, remaining),
+    }
+    Ok(())
+}
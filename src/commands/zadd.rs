@@ -0,0 +1,23 @@
+use super::lookup_or_create_zset;
+use crate::{
+    entry::Data,
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+/// ZADD key score member [score member ...]: replies with the number of
+/// members that were newly added.
+pub fn invoke(
+    data: &mut Data,
+    key: Vec<u8>,
+    pairs: Vec<(f64, Vec<u8>)>,
+    out: &mut Output,
+) -> Result<()> {
+    let zset = lookup_or_create_zset(data, &key)?;
+    let added = pairs
+        .iter()
+        .filter(|(score, member)| zset.insert(member, *score))
+        .count();
+    response_integer(out, added as i64);
+    Ok(())
+}
@@ -0,0 +1,12 @@
+use super::lookup_zset;
+use crate::{
+    entry::Data,
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(data: &mut Data, key: Vec<u8>, out: &mut Output) -> Result<()> {
+    let len = lookup_zset(data, &key)?.map_or(0, |zset| zset.len());
+    response_integer(out, len as i64);
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use super::{lookup_or_create_zset, ProtocolError};
+use crate::{
+    entry::Data,
+    serialization::{response_double, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(
+    data: &mut Data,
+    key: Vec<u8>,
+    increment: f64,
+    member: Vec<u8>,
+    out: &mut Output,
+) -> Result<()> {
+    let zset = lookup_or_create_zset(data, &key)?;
+    let Some(score) = zset.incr(&member, increment) else {
+        if zset.is_empty() {
+            drop(data.remove(&key));
+        }
+        return Err(ProtocolError::ScoreIsNaN.into());
+    };
+    response_double(out, score);
+    Ok(())
+}
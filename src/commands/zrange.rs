@@ -0,0 +1,78 @@
+use super::lookup_zset;
+use crate::{
+    entry::Data,
+    serialization::{response_array, response_double, response_string, Output, Protocol},
+};
+use anyhow::Result;
+
+/// Resolves Redis-style inclusive `start`/`stop` indexes, where negative
+/// values count from the end, into a `skip`/`take` pair over `len` items.
+pub fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let stop = if stop < 0 { stop + len } else { stop }.min(len - 1);
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, (stop - start + 1) as usize))
+}
+
+/// Writes `(member, score)` pairs as a flat array, with the scores
+/// interleaved when `with_scores` is set. RESP3 clients get each member and
+/// its score as a nested pair instead, as Redis sends them.
+pub fn response_members(out: &mut Output, members: Vec<(&[u8], f64)>, with_scores: bool) {
+    let nested = with_scores && out.protocol() == Protocol::Resp3;
+    let per_member = if with_scores && !nested { 2 } else { 1 };
+    response_array(out, (members.len() * per_member) as u32);
+    for (member, score) in members {
+        if nested {
+            response_array(out, 2);
+        }
+        response_string(out, member);
+        if with_scores {
+            response_double(out, score);
+        }
+    }
+}
+
+/// ZRANGE / ZREVRANGE by rank.
+pub fn invoke(
+    data: &mut Data,
+    key: Vec<u8>,
+    start: i64,
+    stop: i64,
+    with_scores: bool,
+    reverse: bool,
+    out: &mut Output,
+) -> Result<()> {
+    let Some(zset) = lookup_zset(data, &key)? else {
+        response_array(out, 0);
+        return Ok(());
+    };
+    let Some((skip, take)) = resolve_range(start, stop, zset.len()) else {
+        response_array(out, 0);
+        return Ok(());
+    };
+    let len = zset.len();
+    let members: Vec<_> = (skip..skip + take)
+        .filter_map(|i| zset.select(if reverse { len - 1 - i } else { i }))
+        .collect();
+    response_members(out, members, with_scores);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_range() {
+        assert_eq!(resolve_range(0, -1, 5), Some((0, 5)));
+        assert_eq!(resolve_range(1, 2, 5), Some((1, 2)));
+        assert_eq!(resolve_range(-2, -1, 5), Some((3, 2)));
+        assert_eq!(resolve_range(-100, 100, 5), Some((0, 5)));
+        assert_eq!(resolve_range(3, 1, 5), None);
+        assert_eq!(resolve_range(5, 10, 5), None);
+        assert_eq!(resolve_range(0, -1, 0), None);
+    }
+}
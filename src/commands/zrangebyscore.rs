@@ -0,0 +1,143 @@
+use super::{lookup_zset, parse_integer, zrange::response_members, ProtocolError};
+use crate::{
+    entry::Data,
+    serialization::{response_array, Output},
+};
+use anyhow::Result;
+
+/// One end of a score interval: `1.5`, `(1.5` (exclusive), `-inf` or `+inf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    pub fn parse(arg: &[u8]) -> Result<ScoreBound> {
+        let (exclusive, value) = match arg.strip_prefix(b"(") {
+            Some(rest) => (true, rest),
+            None => (false, arg),
+        };
+        let value = std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|value| !value.is_nan())
+            .ok_or(ProtocolError::MinMaxNotAFloat)?;
+        Ok(ScoreBound { value, exclusive })
+    }
+
+    fn admits_from_below(&self, score: f64) -> bool {
+        if self.exclusive {
+            score > self.value
+        } else {
+            score >= self.value
+        }
+    }
+
+    fn admits_from_above(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.value
+        } else {
+            score <= self.value
+        }
+    }
+}
+
+/// Arguments of ZRANGEBYSCORE: `min max [WITHSCORES] [LIMIT offset count]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreRange {
+    pub min: ScoreBound,
+    pub max: ScoreBound,
+    pub with_scores: bool,
+    pub limit: Option<(i64, i64)>,
+}
+
+impl ScoreRange {
+    pub fn parse<'a>(tokens: &mut impl Iterator<Item = &'a [u8]>) -> Result<ScoreRange> {
+        let min = tokens
+            .next()
+            .ok_or(ProtocolError::WrongArity("zrangebyscore"))?;
+        let max = tokens
+            .next()
+            .ok_or(ProtocolError::WrongArity("zrangebyscore"))?;
+        let mut range = ScoreRange {
+            min: ScoreBound::parse(min)?,
+            max: ScoreBound::parse(max)?,
+            with_scores: false,
+            limit: None,
+        };
+        while let Some(option) = tokens.next() {
+            if option.eq_ignore_ascii_case(b"WITHSCORES") {
+                range.with_scores = true;
+            } else if option.eq_ignore_ascii_case(b"LIMIT") {
+                let offset = tokens.next().ok_or(ProtocolError::SyntaxError)?;
+                let count = tokens.next().ok_or(ProtocolError::SyntaxError)?;
+                range.limit = Some((parse_integer(offset)?, parse_integer(count)?));
+            } else {
+                return Err(ProtocolError::SyntaxError.into());
+            }
+        }
+        Ok(range)
+    }
+
+    pub fn contains(&self, score: f64) -> bool {
+        self.min.admits_from_below(score) && self.max.admits_from_above(score)
+    }
+}
+
+pub fn invoke(data: &mut Data, key: Vec<u8>, range: ScoreRange, out: &mut Output) -> Result<()> {
+    let Some(zset) = lookup_zset(data, &key)? else {
+        response_array(out, 0);
+        return Ok(());
+    };
+    // A negative offset yields nothing and a negative count means no limit.
+    let (offset, count) = match range.limit {
+        Some((offset, _)) if offset < 0 => {
+            response_array(out, 0);
+            return Ok(());
+        }
+        Some((offset, count)) if count >= 0 => (offset as usize, count as usize),
+        Some((offset, _)) => (offset as usize, usize::MAX),
+        None => (0, usize::MAX),
+    };
+    let members: Vec<_> = zset
+        .range_by_score(
+            |score| range.min.admits_from_below(score),
+            |score| range.max.admits_from_above(score),
+        )
+        .skip(offset)
+        .take(count)
+        .collect();
+    response_members(out, members, range.with_scores);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_score_range() {
+        let args: Vec<&[u8]> = vec![b"(1", b"+inf", b"WITHSCORES", b"LIMIT", b"2", b"10"];
+        let range = ScoreRange::parse(&mut args.into_iter()).unwrap();
+        assert_eq!(
+            range.min,
+            ScoreBound {
+                value: 1.0,
+                exclusive: true
+            }
+        );
+        assert_eq!(range.max.value, f64::INFINITY);
+        assert!(range.with_scores);
+        assert_eq!(range.limit, Some((2, 10)));
+        assert!(!range.contains(1.0));
+        assert!(range.contains(1.5));
+
+        let args: Vec<&[u8]> = vec![b"-inf", b"nope"];
+        let err = ScoreRange::parse(&mut args.into_iter()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::MinMaxNotAFloat)
+        );
+    }
+}
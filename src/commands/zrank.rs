@@ -0,0 +1,14 @@
+use super::lookup_zset;
+use crate::{
+    entry::Data,
+    serialization::{response_integer, response_nil, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(data: &mut Data, key: Vec<u8>, member: Vec<u8>, out: &mut Output) -> Result<()> {
+    match lookup_zset(data, &key)?.and_then(|zset| zset.rank(&member)) {
+        Some(rank) => response_integer(out, rank as i64),
+        None => response_nil(out),
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use super::lookup_zset;
+use crate::{
+    entry::Data,
+    serialization::{response_integer, Output},
+};
+use anyhow::Result;
+
+/// ZREM key member [member ...]: replies with the number of members removed.
+/// The key is deleted once its last member is gone.
+pub fn invoke(
+    data: &mut Data,
+    key: Vec<u8>,
+    members: Vec<Vec<u8>>,
+    out: &mut Output,
+) -> Result<()> {
+    let Some(zset) = lookup_zset(data, &key)? else {
+        response_integer(out, 0);
+        return Ok(());
+    };
+    let removed = members.iter().filter(|member| zset.remove(member)).count();
+    if zset.is_empty() {
+        drop(data.remove(&key));
+    }
+    response_integer(out, removed as i64);
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use super::lookup_zset;
+use crate::{
+    entry::Data,
+    serialization::{response_double, response_nil, Output},
+};
+use anyhow::Result;
+
+pub fn invoke(data: &mut Data, key: Vec<u8>, member: Vec<u8>, out: &mut Output) -> Result<()> {
+    match lookup_zset(data, &key)?.and_then(|zset| zset.score(&member)) {
+        Some(score) => response_double(out, score),
+        None => response_nil(out),
+    }
+    Ok(())
+}
@@ -0,0 +1,929 @@
+//! Server settings, read from a redis.conf-style file and the command line
+//! and partly adjustable at runtime with CONFIG SET.
+//!
+//! The file has one setting per line, a name followed by its value; blank
+//! lines and lines starting with `#` are ignored, and a value containing
+//! spaces can be quoted. On the command line the same settings are written
+//! `--name value`, after an optional path to the file:
+//!
+//! ```text
+//! crabcache /etc/crabcache.conf --port 6380 --bind 127.0.0.1 ::1
+//! ```
+
+use crate::{
+    aof::{self, FsyncPolicy},
+    commands::shutdown::SaveMode,
+    commands::{ProtocolError, RequestLimits, DEFAULT_MAX_ARGUMENTS, DEFAULT_MAX_BULK_LEN},
+    connection::OutputBufferLimit,
+    listener::ListenerProtocol,
+    rdb,
+    scalablehashmap::DEFAULT_LOAD_FACTOR,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+/// Output buffer limits for each class of client. Every client is a normal
+/// one for now; the other classes are kept so Redis configs carry over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    /// Redis's defaults.
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::UNLIMITED,
+            replica: OutputBufferLimit {
+                hard: 256 * 1024 * 1024,
+                soft: 64 * 1024 * 1024,
+                soft_duration: Duration::from_secs(60),
+            },
+            pubsub: OutputBufferLimit {
+                hard: 32 * 1024 * 1024,
+                soft: 8 * 1024 * 1024,
+                soft_duration: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+/// As in Redis.
+const MAX_IO_THREADS: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The file the settings were read from, which CONFIG REWRITE updates.
+    pub file: Option<PathBuf>,
+    pub bind: Vec<IpAddr>,
+    pub port: u16,
+    /// Path of a Unix domain socket to listen on as well, if any.
+    pub unixsocket: Option<PathBuf>,
+    /// Permission bits for the Unix socket; 0 leaves them to the umask.
+    pub unixsocketperm: u32,
+    /// The protocol spoken on the TCP listeners.
+    pub protocol: ListenerProtocol,
+    /// The protocol spoken on the Unix socket.
+    pub unixsocket_protocol: ListenerProtocol,
+    /// Directory the snapshot and the append only file live in.
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    /// Largest single argument accepted, in bytes.
+    pub proto_max_bulk_len: usize,
+    /// Average chain length at which the keyspace hash table grows.
+    pub hash_load_factor: usize,
+    /// How many readiness events one poll of the event loop can return.
+    pub events_per_poll: usize,
+    /// Threads reading requests and writing replies, counting the main one.
+    pub io_threads: usize,
+    /// Connections served at once; clients beyond this are turned away.
+    pub maxclients: usize,
+    /// How long a client may stay silent before it is disconnected; zero
+    /// never disconnects idle clients.
+    pub timeout: Duration,
+    /// Idle time before TCP keepalive probes start; zero turns them off.
+    pub tcp_keepalive: Duration,
+    pub client_output_buffer_limit: OutputBufferLimits,
+    /// File the server writes its process id to while it runs.
+    pub pidfile: Option<PathBuf>,
+    /// How long shutting down waits for pending replies to be flushed.
+    pub shutdown_timeout: Duration,
+    pub shutdown_on_sigint: SaveMode,
+    pub shutdown_on_sigterm: SaveMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            bind: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            port: 6379,
+            unixsocket: None,
+            unixsocketperm: 0,
+            protocol: ListenerProtocol::Auto,
+            unixsocket_protocol: ListenerProtocol::Auto,
+            dir: PathBuf::from("."),
+            dbfilename: rdb::DEFAULT_PATH.to_string(),
+            appendonly: false,
+            appendfilename: aof::DEFAULT_PATH.to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            proto_max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            hash_load_factor: DEFAULT_LOAD_FACTOR,
+            events_per_poll: 128,
+            io_threads: 1,
+            maxclients: 10000,
+            timeout: Duration::ZERO,
+            tcp_keepalive: Duration::from_secs(300),
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            pidfile: None,
+            shutdown_timeout: Duration::from_secs(10),
+            shutdown_on_sigint: SaveMode::Default,
+            shutdown_on_sigterm: SaveMode::Default,
+        }
+    }
+}
+
+/// One setting: how to read it back as text and how to change it from text.
+struct Param {
+    name: &'static str,
+    /// Whether CONFIG SET may change it while the server runs.
+    mutable: bool,
+    get: fn(&Config) -> String,
+    set: fn(&mut Config, &str) -> Result<()>,
+}
+
+static PARAMS: &[Param] = &[
+    Param {
+        name: "bind",
+        mutable: false,
+        get: |config| {
+            let addrs: Vec<String> = config.bind.iter().map(IpAddr::to_string).collect();
+            addrs.join(" ")
+        },
+        set: |config, value| {
+            let addrs = value
+                .split_whitespace()
+                .map(|addr| {
+                    addr.parse()
+                        .map_err(|_| anyhow!("invalid bind address '{}'", addr))
+                })
+                .collect::<Result<Vec<IpAddr>>>()?;
+            if addrs.is_empty() {
+                bail!("at least one bind address is required");
+            }
+            config.bind = addrs;
+            Ok(())
+        },
+    },
+    Param {
+        name: "port",
+        mutable: false,
+        get: |config| config.port.to_string(),
+        set: |config, value| {
+            config.port = value
+                .parse()
+                .map_err(|_| anyhow!("invalid port '{}'", value))?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "unixsocket",
+        mutable: false,
+        get: |config| {
+            config
+                .unixsocket
+                .as_ref()
+                .map_or(String::new(), |path| path.display().to_string())
+        },
+        set: |config, value| {
+            config.unixsocket = (!value.is_empty()).then(|| PathBuf::from(value));
+            Ok(())
+        },
+    },
+    Param {
+        name: "unixsocketperm",
+        mutable: false,
+        get: |config| format!("{:o}", config.unixsocketperm),
+        set: |config, value| {
+            config.unixsocketperm = u32::from_str_radix(value, 8)
+                .ok()
+                .filter(|perm| *perm <= 0o777)
+                .ok_or_else(|| anyhow!("argument must be octal permission bits"))?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "protocol",
+        mutable: true,
+        get: |config| config.protocol.as_str().to_string(),
+        set: |config, value| {
+            config.protocol = parse_listener_protocol(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "unixsocket-protocol",
+        mutable: true,
+        get: |config| config.unixsocket_protocol.as_str().to_string(),
+        set: |config, value| {
+            config.unixsocket_protocol = parse_listener_protocol(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "dir",
+        mutable: false,
+        get: |config| config.dir.display().to_string(),
+        set: |config, value| {
+            config.dir = PathBuf::from(value);
+            Ok(())
+        },
+    },
+    Param {
+        name: "dbfilename",
+        mutable: false,
+        get: |config| config.dbfilename.clone(),
+        set: |config, value| {
+            config.dbfilename = parse_file_name(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "appendonly",
+        mutable: false,
+        get: |config| format_bool(config.appendonly),
+        set: |config, value| {
+            config.appendonly = parse_bool(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "appendfilename",
+        mutable: false,
+        get: |config| config.appendfilename.clone(),
+        set: |config, value| {
+            config.appendfilename = parse_file_name(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "appendfsync",
+        mutable: true,
+        get: |config| config.appendfsync.as_str().to_string(),
+        set: |config, value| {
+            config.appendfsync = FsyncPolicy::parse(value)
+                .ok_or_else(|| anyhow!("argument must be one of always, everysec, no"))?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "proto-max-bulk-len",
+        mutable: true,
+        get: |config| config.proto_max_bulk_len.to_string(),
+        set: |config, value| {
+            let len = parse_memory(value)?;
+            if len < 1024 * 1024 {
+                bail!("argument must be at least 1mb");
+            }
+            config.proto_max_bulk_len = len;
+            Ok(())
+        },
+    },
+    Param {
+        name: "hash-load-factor",
+        mutable: false,
+        get: |config| config.hash_load_factor.to_string(),
+        set: |config, value| {
+            config.hash_load_factor = parse_positive(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "events-per-poll",
+        mutable: false,
+        get: |config| config.events_per_poll.to_string(),
+        set: |config, value| {
+            config.events_per_poll = parse_positive(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "io-threads",
+        mutable: false,
+        get: |config| config.io_threads.to_string(),
+        set: |config, value| {
+            let threads = parse_positive(value)?;
+            if threads > MAX_IO_THREADS {
+                bail!("argument must be between 1 and {}", MAX_IO_THREADS);
+            }
+            config.io_threads = threads;
+            Ok(())
+        },
+    },
+    Param {
+        name: "maxclients",
+        mutable: true,
+        get: |config| config.maxclients.to_string(),
+        set: |config, value| {
+            config.maxclients = parse_positive(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "timeout",
+        mutable: true,
+        get: |config| config.timeout.as_secs().to_string(),
+        set: |config, value| {
+            config.timeout = parse_seconds(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "tcp-keepalive",
+        mutable: true,
+        get: |config| config.tcp_keepalive.as_secs().to_string(),
+        set: |config, value| {
+            config.tcp_keepalive = parse_seconds(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "client-output-buffer-limit",
+        mutable: true,
+        get: |config| {
+            let limits = &config.client_output_buffer_limit;
+            let classes = [
+                ("normal", limits.normal),
+                ("slave", limits.replica),
+                ("pubsub", limits.pubsub),
+            ];
+            let classes: Vec<String> = classes
+                .iter()
+                .map(|(class, limit)| {
+                    format!(
+                        "{} {} {} {}",
+                        class,
+                        limit.hard,
+                        limit.soft,
+                        limit.soft_duration.as_secs()
+                    )
+                })
+                .collect();
+            classes.join(" ")
+        },
+        // Any number of `class hard soft seconds` groups; classes left out
+        // keep their limits.
+        set: |config, value| {
+            let words: Vec<&str> = value.split_whitespace().collect();
+            if words.is_empty() || !words.len().is_multiple_of(4) {
+                bail!("wrong number of arguments");
+            }
+            let mut limits = config.client_output_buffer_limit;
+            for group in words.chunks(4) {
+                let limit = match group[0].to_ascii_lowercase().as_str() {
+                    "normal" => &mut limits.normal,
+                    "replica" | "slave" => &mut limits.replica,
+                    "pubsub" => &mut limits.pubsub,
+                    _ => bail!("Invalid client class specified in buffer limit configuration."),
+                };
+                *limit = OutputBufferLimit {
+                    hard: parse_memory(group[1])?,
+                    soft: parse_memory(group[2])?,
+                    soft_duration: parse_seconds(group[3])?,
+                };
+            }
+            config.client_output_buffer_limit = limits;
+            Ok(())
+        },
+    },
+    Param {
+        name: "pidfile",
+        mutable: false,
+        get: |config| {
+            config
+                .pidfile
+                .as_ref()
+                .map_or(String::new(), |path| path.display().to_string())
+        },
+        set: |config, value| {
+            config.pidfile = (!value.is_empty()).then(|| PathBuf::from(value));
+            Ok(())
+        },
+    },
+    Param {
+        name: "shutdown-timeout",
+        mutable: true,
+        get: |config| config.shutdown_timeout.as_secs().to_string(),
+        set: |config, value| {
+            config.shutdown_timeout = parse_seconds(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "shutdown-on-sigint",
+        mutable: true,
+        get: |config| config.shutdown_on_sigint.as_str().to_string(),
+        set: |config, value| {
+            config.shutdown_on_sigint = parse_save_mode(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "shutdown-on-sigterm",
+        mutable: true,
+        get: |config| config.shutdown_on_sigterm.as_str().to_string(),
+        set: |config, value| {
+            config.shutdown_on_sigterm = parse_save_mode(value)?;
+            Ok(())
+        },
+    },
+];
+
+fn lookup(name: &str) -> Option<&'static Param> {
+    PARAMS
+        .iter()
+        .find(|param| param.name.eq_ignore_ascii_case(name))
+}
+
+impl Config {
+    /// Builds the configuration from the command line, without the program
+    /// name: an optional config file, then `--name value` overrides.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Config> {
+        let mut args = args.into_iter().peekable();
+        let mut config = Config::default();
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            config.load_file(Path::new(&path))?;
+        }
+        while let Some(flag) = args.next() {
+            let Some(name) = flag.strip_prefix("--") else {
+                bail!("unexpected argument '{}'", flag);
+            };
+            // A setting takes every value up to the next flag, as `--bind`
+            // may be given several addresses.
+            let mut values = Vec::new();
+            while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+                values.push(value);
+            }
+            if values.is_empty() {
+                bail!("missing value for --{}", name);
+            }
+            config
+                .set_startup(name, &values.join(" "))
+                .with_context(|| format!("invalid --{}", name))?;
+        }
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        for (number, line) in text.lines().enumerate() {
+            let context = || format!("{}:{}", path.display(), number + 1);
+            let Some((name, value)) = parse_line(line).with_context(context)? else {
+                continue;
+            };
+            self.set_startup(&name, &value).with_context(context)?;
+        }
+        self.file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn set_startup(&mut self, name: &str, value: &str) -> Result<()> {
+        let param = lookup(name).ok_or_else(|| anyhow!("unknown setting '{}'", name))?;
+        (param.set)(self, value)
+    }
+
+    /// Changes settings at runtime, all or none of them. Fails naming the
+    /// first setting that is unknown, immutable or given a bad value.
+    pub fn set(&mut self, pairs: &[(String, String)]) -> Result<()> {
+        let mut updated = self.clone();
+        for (name, value) in pairs {
+            let failed = |reason: String| ProtocolError::ConfigSetFailed {
+                name: name.clone(),
+                reason,
+            };
+            let param = match lookup(name) {
+                Some(param) if param.mutable => param,
+                Some(_) => return Err(failed("can't set immutable config".to_string()).into()),
+                None => return Err(failed("unknown option".to_string()).into()),
+            };
+            (param.set)(&mut updated, value).map_err(|err| failed(err.to_string()))?;
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    /// Every setting whose name matches one of the glob `patterns`, with its
+    /// current value.
+    pub fn get(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        PARAMS
+            .iter()
+            .filter(|param| {
+                patterns
+                    .iter()
+                    .any(|pattern| glob_match(pattern.as_bytes(), param.name.as_bytes()))
+            })
+            .map(|param| (param.name, (param.get)(self)))
+            .collect()
+    }
+
+    /// Writes the current settings back to the config file. Lines for known
+    /// settings are updated in place, comments and everything else are kept,
+    /// and settings that differ from their default but have no line yet are
+    /// appended.
+    pub fn rewrite(&self) -> Result<()> {
+        let path = self.file.as_ref().ok_or(ProtocolError::NoConfigFile)?;
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let defaults = Config::default();
+        let mut written = Vec::new();
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let param = match parse_line(line) {
+                Ok(Some((name, _))) => lookup(&name),
+                _ => None,
+            };
+            match param {
+                Some(param) if written.contains(&param.name) => {}
+                Some(param) => {
+                    lines.push(self.format_line(param));
+                    written.push(param.name);
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        let mut appended = PARAMS.iter().filter(|param| {
+            !written.contains(&param.name) && (param.get)(self) != (param.get)(&defaults)
+        });
+        if let Some(first) = appended.next() {
+            lines.push("# Generated by CONFIG REWRITE".to_string());
+            lines.push(self.format_line(first));
+            lines.extend(appended.map(|param| self.format_line(param)));
+        }
+
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".tmp-{}", process::id()));
+        let temp = path.with_file_name(name);
+        let result = (|| -> Result<()> {
+            fs::write(&temp, lines.join("\n") + "\n")?;
+            fs::File::open(&temp)?.sync_all()?;
+            fs::rename(&temp, path)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result.with_context(|| format!("failed to rewrite config file {}", path.display()))
+    }
+
+    fn format_line(&self, param: &Param) -> String {
+        let value = (param.get)(self);
+        // Bind addresses and buffer limits are separate words; anything else
+        // is one value.
+        let words = matches!(param.name, "bind" | "client-output-buffer-limit");
+        if words || is_bare_word(&value) {
+            format!("{} {}", param.name, value)
+        } else {
+            format!(
+                "{} \"{}\"",
+                param.name,
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        }
+    }
+
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.bind
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, self.port))
+            .collect()
+    }
+
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+
+    pub fn limits(&self) -> RequestLimits {
+        RequestLimits {
+            max_bulk_len: self.proto_max_bulk_len,
+            max_arguments: DEFAULT_MAX_ARGUMENTS,
+        }
+    }
+}
+
+/// Splits a config file line into the setting name and its value, or
+/// `None` for a blank line or a comment. Quoted words keep their spaces;
+/// several words are joined with single spaces.
+fn parse_line(line: &str) -> Result<Option<(String, String)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' || c == '\'' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    None => bail!("unbalanced quotes"),
+                    Some(q) if q == c => break,
+                    Some('\\') if c == '"' => {
+                        word.push(chars.next().ok_or_else(|| anyhow!("unbalanced quotes"))?)
+                    }
+                    Some(other) => word.push(other),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    let name = words.remove(0);
+    if words.is_empty() {
+        bail!("missing value for '{}'", name);
+    }
+    Ok(Some((name, words.join(" "))))
+}
+
+fn is_bare_word(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => bail!("argument must be 'yes' or 'no'"),
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn parse_save_mode(value: &str) -> Result<SaveMode> {
+    SaveMode::parse(value).ok_or_else(|| anyhow!("argument must be one of default, save, nosave"))
+}
+
+fn parse_listener_protocol(value: &str) -> Result<ListenerProtocol> {
+    ListenerProtocol::parse(value)
+        .ok_or_else(|| anyhow!("argument must be one of auto, native, resp"))
+}
+
+fn parse_positive(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .ok()
+        .filter(|value| *value > 0)
+        .ok_or_else(|| anyhow!("argument must be a positive integer"))
+}
+
+fn parse_seconds(value: &str) -> Result<Duration> {
+    value
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| anyhow!("argument must be a number of seconds"))
+}
+
+/// A byte count, optionally with a `k`, `kb`, `m`, `mb`, `g` or `gb` suffix.
+/// As in Redis, `k` is 1000 bytes and `kb` is 1024.
+fn parse_memory(value: &str) -> Result<usize> {
+    let lower = value.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit: usize = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => bail!("invalid memory unit in '{}'", value),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| anyhow!("argument must be a memory value"))
+}
+
+fn parse_file_name(value: &str) -> Result<String> {
+    if value.is_empty() || value.contains('/') {
+        bail!("argument must be a file name without a directory");
+    }
+    Ok(value.to_string())
+}
+
+/// Glob matching as in CONFIG GET and KEYS: `*` matches any run of
+/// characters and `?` any single one. Matching ignores ASCII case. Like
+/// Redis's `stringmatchlen`, a mismatch only ever goes back to the last `*`,
+/// so no pattern takes more than pattern-times-text steps.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The pattern just past the last `*`, and where in the text it was
+    // last tried from.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, t));
+            }
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                let Some((after_star, from)) = star else {
+                    return false;
+                };
+                // Let the `*` take one more character and try again.
+                p = after_star;
+                t = from + 1;
+                star = Some((after_star, t));
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_command_line() {
+        let config = Config::from_args(args(
+            "--port 6380 --bind 127.0.0.1 ::1 --appendonly yes --proto-max-bulk-len 2mb \
+             --unixsocket /tmp/crabcache.sock --unixsocketperm 770",
+        ))
+        .unwrap();
+        assert_eq!(
+            config.unixsocket.as_deref(),
+            Some(Path::new("/tmp/crabcache.sock"))
+        );
+        assert_eq!(config.unixsocketperm, 0o770);
+        assert_eq!(config.port, 6380);
+        assert_eq!(
+            config.listen_addrs(),
+            vec![
+                "127.0.0.1:6380".parse().unwrap(),
+                "[::1]:6380".parse().unwrap()
+            ]
+        );
+        assert!(config.appendonly);
+        assert_eq!(config.proto_max_bulk_len, 2 * 1024 * 1024);
+
+        assert!(Config::from_args(args("--port")).is_err());
+        assert!(Config::from_args(args("--port http")).is_err());
+        assert!(Config::from_args(args("--colour blue")).is_err());
+        assert!(Config::from_args(args("--appendfsync sometimes")).is_err());
+        assert!(Config::from_args(args("--unixsocketperm 999")).is_err());
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("  # comment").unwrap(), None);
+        assert_eq!(parse_line("").unwrap(), None);
+        assert_eq!(
+            parse_line("dbfilename \"my \\\"dump\\\".rdb\"").unwrap(),
+            Some(("dbfilename".to_string(), "my \"dump\".rdb".to_string()))
+        );
+        assert_eq!(
+            parse_line("bind 127.0.0.1   ::1").unwrap(),
+            Some(("bind".to_string(), "127.0.0.1 ::1".to_string()))
+        );
+        assert!(parse_line("dir 'open").is_err());
+        assert!(parse_line("port").is_err());
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let mut config = Config::default();
+        let names: Vec<_> = config
+            .get(&["APPEND*".to_string()])
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["appendonly", "appendfilename", "appendfsync"]);
+        assert_eq!(
+            config.get(&["port".to_string()]),
+            vec![("port", "6379".to_string())]
+        );
+
+        let set = |config: &mut Config, pairs: &[(&str, &str)]| {
+            let pairs: Vec<_> = pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            config.set(&pairs)
+        };
+        assert!(set(&mut config, &[("appendfsync", "always")]).is_ok());
+        assert_eq!(config.appendfsync, FsyncPolicy::Always);
+        assert_eq!(
+            set(&mut config, &[("port", "1")])
+                .unwrap_err()
+                .downcast::<ProtocolError>()
+                .unwrap(),
+            ProtocolError::ConfigSetFailed {
+                name: "port".to_string(),
+                reason: "can't set immutable config".to_string()
+            }
+        );
+        // A bad value anywhere leaves every setting untouched.
+        assert!(set(
+            &mut config,
+            &[("appendfsync", "no"), ("proto-max-bulk-len", "10")]
+        )
+        .is_err());
+        assert_eq!(config.appendfsync, FsyncPolicy::Always);
+
+        assert!(set(
+            &mut config,
+            &[("client-output-buffer-limit", "normal 1mb 512kb 10")]
+        )
+        .is_ok());
+        assert_eq!(
+            config.get(&["client-output-buffer-limit".to_string()]),
+            vec![(
+                "client-output-buffer-limit",
+                "normal 1048576 524288 10 slave 268435456 67108864 60 pubsub 33554432 8388608 60"
+                    .to_string()
+            )]
+        );
+        assert!(set(
+            &mut config,
+            &[("client-output-buffer-limit", "master 1mb 1mb 1")]
+        )
+        .is_err());
+
+        assert!(set(&mut config, &[("unixsocket-protocol", "Native")]).is_ok());
+        assert_eq!(config.unixsocket_protocol, ListenerProtocol::Native);
+        assert_eq!(config.protocol, ListenerProtocol::Auto);
+        assert!(set(&mut config, &[("protocol", "resp3")]).is_err());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let dir = std::env::temp_dir().join(format!("crabcache-config-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("crabcache.conf");
+        fs::write(
+            &path,
+            "# my settings\nport 7000\nappendfsync no\nport 7001\n",
+        )
+        .unwrap();
+
+        let mut config = Config::from_args(vec![path.display().to_string()]).unwrap();
+        assert_eq!(config.port, 7001);
+        config
+            .set(&[("appendfsync".to_string(), "always".to_string())])
+            .unwrap();
+        config.dbfilename = "my dump.rdb".to_string();
+        config.rewrite().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# my settings\nport 7001\nappendfsync always\n\
+             # Generated by CONFIG REWRITE\ndbfilename \"my dump.rdb\"\n"
+        );
+        let reloaded = Config::from_args(vec![path.display().to_string()]).unwrap();
+        assert_eq!(reloaded, config);
+
+        assert!(Config::default().rewrite().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b"port"));
+        assert!(glob_match(b"p?rt", b"PORT"));
+        assert!(glob_match(b"*fsync", b"appendfsync"));
+        assert!(!glob_match(b"append", b"appendonly"));
+        assert!(!glob_match(b"?", b""));
+        assert!(glob_match(b"**a*?", b"banana"));
+        assert!(glob_match(b"*an*a", b"banana"));
+        assert!(!glob_match(b"*an*b", b"banana"));
+        assert!(glob_match(b"", b""));
+        assert!(glob_match(b"***", b""));
+
+        // Trying every split of the text for every star would never finish.
+        let pattern = [&[b'*'; 30][..], b"b"].concat();
+        let started = Instant::now();
+        assert!(!glob_match(&pattern, &[b'a'; 200]));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,46 +1,138 @@
+use crate::commands::{self, ProtocolError, Request, RequestLimits};
+use crate::resp;
+use crate::serialization::{Output, Protocol};
+use anyhow::Result;
+use byteorder::{LittleEndian, WriteBytesExt};
 use mio::net::TcpStream;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
 
-const MAX_MESSAGE_SIZE: usize = 4096;
+const READ_CHUNK_SIZE: usize = 4096;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum ConnectionState {
     ReadyToRead,
     ReadyToWrite,
     Closing,
 }
 
-pub struct Connection {
-    pub stream: TcpStream,
+/// How much unsent output a client may have queued before it is
+/// disconnected. Zero turns a limit off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    /// Disconnect as soon as this many bytes are pending.
+    pub hard: usize,
+    /// Disconnect once this many bytes have stayed pending for longer than
+    /// `soft_duration`.
+    pub soft: usize,
+    pub soft_duration: Duration,
+}
+
+impl OutputBufferLimit {
+    pub const UNLIMITED: OutputBufferLimit = OutputBufferLimit {
+        hard: 0,
+        soft: 0,
+        soft_duration: Duration::ZERO,
+    };
+}
+
+/// What CLIENT LIST reports about a connection, besides its buffers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+    /// Unique for the life of the server, in the order clients connected.
+    pub id: u64,
+    /// The peer's address.
+    pub addr: String,
+    /// The address the peer connected to.
+    pub laddr: String,
+    /// Set with CLIENT SETNAME.
+    pub name: Option<String>,
+    pub created: Instant,
+    /// The last command the client sent that exists.
+    pub last_command: Option<&'static str>,
+}
+
+impl ClientInfo {
+    pub fn new(id: u64, addr: String, laddr: String) -> ClientInfo {
+        ClientInfo {
+            id,
+            addr,
+            laddr,
+            name: None,
+            created: Instant::now(),
+            last_command: None,
+        }
+    }
+}
+
+/// A client connection over a stream of type `S`, which is a TCP or Unix
+/// socket in the server and can be anything readable and writable in tests.
+pub struct Connection<S = TcpStream> {
+    pub stream: S,
     pub state: ConnectionState,
-    read_buffer_size: usize,
-    pub read_buffer: [u8; 4 + MAX_MESSAGE_SIZE],
+    pub info: ClientInfo,
+    read_buffer_start: usize,
+    pub read_buffer: Vec<u8>,
     write_buffer_size: usize,
-    pub write_buffer: [u8; 4 + MAX_MESSAGE_SIZE],
+    pub write_buffer: Vec<u8>,
     write_buffer_sent: usize,
+    close_after_reply: bool,
+    limits: RequestLimits,
+    /// Detected from the first byte the client sends.
+    protocol: Option<Protocol>,
+    /// Keeps a RESP request that has partly arrived between reads.
+    resp: resp::Parser,
+    /// When bytes last moved in either direction.
+    last_interaction: Instant,
+    /// Accepted past `maxclients`: answered with an error once the client
+    /// has spoken, and closed without executing anything.
+    rejected: bool,
+    /// Since when the pending output has been over the soft limit.
+    soft_limit_reached: Option<Instant>,
+    /// Requests parsed by `read_requests` and not yet executed.
+    requests: VecDeque<Result<Request>>,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream) -> Connection {
+impl<S: Read + Write> Connection<S> {
+    pub fn new(stream: S) -> Connection<S> {
+        Self::with_limits(stream, RequestLimits::default())
+    }
+
+    pub fn with_limits(stream: S, limits: RequestLimits) -> Connection<S> {
         Connection {
             stream,
             state: ConnectionState::ReadyToRead,
-            read_buffer_size: 0,
-            read_buffer: [0; 4 + MAX_MESSAGE_SIZE],
+            info: ClientInfo::new(0, String::new(), String::new()),
+            read_buffer_start: 0,
+            read_buffer: Vec::with_capacity(READ_CHUNK_SIZE),
             write_buffer_size: 0,
-            write_buffer: [0; 4 + MAX_MESSAGE_SIZE],
+            write_buffer: Vec::new(),
             write_buffer_sent: 0,
+            close_after_reply: false,
+            limits,
+            protocol: None,
+            resp: resp::Parser::default(),
+            last_interaction: Instant::now(),
+            rejected: false,
+            soft_limit_reached: None,
+            requests: VecDeque::new(),
         }
     }
 
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
     pub fn state(&self) -> &ConnectionState {
         &self.state
     }
 
-    pub fn stream(&self) -> &TcpStream {
+    pub fn stream(&self) -> &S {
         &self.stream
     }
 
-    pub fn stream_mut(&mut self) -> &mut TcpStream {
+    pub fn stream_mut(&mut self) -> &mut S {
         &mut self.stream
     }
 
@@ -48,18 +140,526 @@ impl Connection {
         self.state = state;
     }
 
+    /// Reads everything currently available on the stream into the read
+    /// buffer, growing it as needed. Returns `false` once the peer has
+    /// closed its side of the connection.
+    pub fn fill_read_buffer(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.read_buffer.extend_from_slice(&chunk[..n]);
+                    self.last_interaction = Instant::now();
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// How long since the client last sent anything or was last sent
+    /// anything.
+    pub fn idle_time(&self) -> Duration {
+        self.last_interaction.elapsed()
+    }
+
+    pub fn reject(&mut self) {
+        self.rejected = true;
+    }
+
+    pub fn is_rejected(&self) -> bool {
+        self.rejected
+    }
+
+    /// Reads what the client has sent and parses every complete request in
+    /// it, to be taken with `next_parsed`. Touches nothing but this
+    /// connection, so it can run on an I/O thread. Returns `false` once the
+    /// peer has closed its side.
+    pub fn read_requests(&mut self) -> io::Result<bool> {
+        let open = self.fill_read_buffer()?;
+        // A rejected client only ever gets an error, whatever it asked.
+        if !self.rejected {
+            self.parse_requests();
+        }
+        Ok(open)
+    }
+
+    fn parse_requests(&mut self) {
+        loop {
+            match self.next_request() {
+                Ok(Some(request)) => self.requests.push_back(Ok(request)),
+                Ok(None) => return,
+                Err(err) => {
+                    // Past an error that loses the frame boundary there is
+                    // nothing more to parse.
+                    let fatal = err
+                        .downcast_ref::<ProtocolError>()
+                        .is_none_or(ProtocolError::is_fatal);
+                    self.requests.push_back(Err(err));
+                    if fatal {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The oldest request `read_requests` parsed and nobody has taken yet,
+    /// or the error it failed to parse with.
+    pub fn next_parsed(&mut self) -> Option<Result<Request>> {
+        self.requests.pop_front()
+    }
+
+    /// The protocol replies are encoded in. Until the client has sent
+    /// anything this is the native one.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol.unwrap_or(Protocol::Binary)
+    }
+
+    /// Switches between RESP versions, as negotiated by HELLO.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = Some(protocol);
+    }
+
+    /// A reply buffer in this connection's protocol.
+    pub fn output(&self) -> Output {
+        Output::new(self.protocol())
+    }
+
+    /// Parses the next complete request out of the read buffer. Returns
+    /// `None` when only a partial frame (or nothing) is buffered; the partial
+    /// bytes are kept for the next readiness event.
+    pub fn next_request(&mut self) -> Result<Option<Request>> {
+        let Some(protocol) = self.detect_protocol() else {
+            return Ok(None);
+        };
+        if protocol.is_resp() {
+            return self.next_resp_request();
+        }
+        let buffered = &self.read_buffer[self.read_buffer_start..];
+        let Some(length) = commands::request_length(buffered, self.limits)? else {
+            self.compact_read_buffer();
+            return Ok(None);
+        };
+        // Consume the frame before parsing it so a malformed command does not
+        // desynchronize the requests that follow it.
+        self.read_buffer_start += length;
+        let args = commands::decode_request(&buffered[..length])?;
+        let command = self.parse(&args)?;
+        Ok(Some(command))
+    }
+
+    /// Settles the protocol from the first buffered bytes, unless it is
+    /// already known. `None` until the client has sent enough to tell.
+    pub fn detect_protocol(&mut self) -> Option<Protocol> {
+        if self.protocol.is_none() {
+            let buffered = &self.read_buffer[self.read_buffer_start..];
+            self.protocol = Some(if resp::is_resp(buffered, self.limits)? {
+                Protocol::Resp2
+            } else {
+                Protocol::Binary
+            });
+        }
+        self.protocol
+    }
+
+    fn next_resp_request(&mut self) -> Result<Option<Request>> {
+        loop {
+            let buffered = &self.read_buffer[self.read_buffer_start..];
+            let (args, length) = self.resp.parse(buffered, self.limits)?;
+            self.read_buffer_start += length;
+            let Some(args) = args else {
+                self.compact_read_buffer();
+                return Ok(None);
+            };
+            if args.is_empty() {
+                continue;
+            }
+            return self.parse(&args).map(Some);
+        }
+    }
+
+    /// Parses a decoded request, remembering its name for CLIENT LIST.
+    fn parse(&mut self, args: &[Vec<u8>]) -> Result<Request> {
+        let spec = Request::command_spec(args)?;
+        self.info.last_command = Some(spec.name);
+        spec.parse_request(args)
+    }
+
+    fn compact_read_buffer(&mut self) {
+        self.read_buffer.drain(..self.read_buffer_start);
+        self.read_buffer_start = 0;
+    }
+
+    /// Bytes received but not yet parsed into a request.
+    pub fn query_buffer_len(&self) -> usize {
+        self.read_buffer.len() - self.read_buffer_start
+    }
+
     pub fn get_write_buffer(&self) -> &[u8] {
-        &self.write_buffer
+        &self.write_buffer[..self.write_buffer_size]
+    }
+
+    /// Appends a serialized response after any responses already queued, so
+    /// pipelined requests are answered in the order they arrived. Native
+    /// responses are framed with their length; RESP ones frame themselves.
+    pub fn queue_response(&mut self, output: &Output) {
+        let output = output.as_bytes();
+        if !self.protocol().is_resp() {
+            let len = output.len() as u32;
+            self.write_buffer.write_u32::<LittleEndian>(len).unwrap();
+        }
+        self.write_buffer.extend_from_slice(output);
+        self.write_buffer_size = self.write_buffer.len();
+    }
+
+    /// The part of the queued responses that has not been written yet.
+    pub fn pending_response(&self) -> &[u8] {
+        &self.write_buffer[self.write_buffer_sent..self.write_buffer_size]
+    }
+
+    /// Writes the unsent part of the queued responses to the stream and
+    /// advances `write_buffer_sent` by the number of bytes accepted.
+    pub fn write_pending(&mut self) -> io::Result<usize> {
+        let n = self
+            .stream
+            .write(&self.write_buffer[self.write_buffer_sent..self.write_buffer_size])?;
+        self.write_buffer_sent += n;
+        if n > 0 {
+            self.last_interaction = Instant::now();
+        }
+        Ok(n)
+    }
+
+    /// Whether the pending output breaks `limit`, either at once or by
+    /// having stayed over the soft limit for too long. Meant to be called
+    /// whenever output is queued and periodically while it waits, as the
+    /// soft limit is only timed from the first check that sees it broken.
+    pub fn exceeds_output_limit(&mut self, limit: OutputBufferLimit) -> bool {
+        let pending = self.pending_response().len();
+        if limit.hard > 0 && pending >= limit.hard {
+            return true;
+        }
+        if limit.soft == 0 || pending < limit.soft {
+            self.soft_limit_reached = None;
+            return false;
+        }
+        let since = *self.soft_limit_reached.get_or_insert_with(Instant::now);
+        since.elapsed() > limit.soft_duration
+    }
+
+    /// Writes as much of the queued response as the socket accepts. A
+    /// partially written response stays in `ReadyToWrite` and resumes from
+    /// `write_buffer_sent` on the next writable event; a fully flushed one
+    /// returns the connection to `ReadyToRead` so it can serve the next
+    /// request, or to `Closing` if it was the last.
+    pub fn send_response(&mut self) -> io::Result<()> {
+        while !self.is_flushed() {
+            match self.write_pending() {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        self.reset_write_buffer();
+        if self.close_after_reply {
+            self.set_state(ConnectionState::Closing);
+        } else {
+            self.set_state(ConnectionState::ReadyToRead);
+        }
+        Ok(())
+    }
+
+    pub fn is_flushed(&self) -> bool {
+        self.write_buffer_sent == self.write_buffer_size
+    }
+
+    /// Marks the connection to be closed once the queued responses are flushed.
+    pub fn close_after_reply(&mut self) {
+        self.close_after_reply = true;
+    }
+
+    pub fn should_close_after_reply(&self) -> bool {
+        self.close_after_reply
     }
 
     pub fn reset_write_buffer(&mut self) {
+        self.write_buffer.clear();
         self.write_buffer_size = 0;
         self.write_buffer_sent = 0;
-        self.write_buffer = [0; 4 + MAX_MESSAGE_SIZE];
     }
 
     pub fn reset_read_buffer(&mut self) {
-        self.read_buffer_size = 0;
-        self.read_buffer = [0; 4 + MAX_MESSAGE_SIZE];
+        self.read_buffer.clear();
+        self.read_buffer_start = 0;
+        self.resp.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{set, Command};
+    use crate::serialization::response_string;
+    use std::net::TcpListener;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn request(args: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.write_u32::<LittleEndian>(args.len() as u32).unwrap();
+        for arg in args {
+            out.write_u32::<LittleEndian>(arg.len() as u32).unwrap();
+            out.extend_from_slice(arg);
+        }
+        out
+    }
+
+    fn connected_streams() -> (TcpStream, std::net::TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+        (TcpStream::from_std(server), client)
+    }
+
+    fn connected_pair() -> (Connection, std::net::TcpStream) {
+        let (server, client) = connected_streams();
+        (Connection::new(server), client)
+    }
+
+    fn fill<S: Read + Write>(connection: &mut Connection<S>, expected: usize) {
+        for _ in 0..100 {
+            connection.fill_read_buffer().unwrap();
+            if connection.read_buffer.len() - connection.read_buffer_start >= expected {
+                return;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        panic!("timed out waiting for {} bytes", expected);
+    }
+
+    fn next_command<S: Read + Write>(connection: &mut Connection<S>) -> Option<Command> {
+        let request = connection.next_request().unwrap();
+        request.map(|request| request.command)
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let (mut connection, mut client) = connected_pair();
+        let mut payload = request(&[b"SET", b"key", b"value"]);
+        payload.extend(request(&[b"GET", b"key"]));
+        let third = request(&[b"DEL", b"key"]);
+        payload.extend_from_slice(&third[..5]);
+        client.write_all(&payload).unwrap();
+
+        fill(&mut connection, payload.len());
+        assert_eq!(
+            next_command(&mut connection),
+            Some(Command::Set(
+                b"key".to_vec(),
+                b"value".to_vec(),
+                set::Expiry::Clear
+            ))
+        );
+        assert_eq!(
+            next_command(&mut connection),
+            Some(Command::Get(b"key".to_vec()))
+        );
+        assert_eq!(next_command(&mut connection), None);
+        assert_eq!(connection.read_buffer, third[..5]);
+
+        client.write_all(&third[5..]).unwrap();
+        fill(&mut connection, third.len());
+        assert_eq!(
+            next_command(&mut connection),
+            Some(Command::Del(vec![b"key".to_vec()]))
+        );
+        assert_eq!(next_command(&mut connection), None);
+        assert!(connection.read_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_argument_too_large() {
+        let (stream, mut client) = connected_streams();
+        let limits = RequestLimits {
+            max_bulk_len: 1024,
+            ..RequestLimits::default()
+        };
+        let mut connection = Connection::with_limits(stream, limits);
+        let value = vec![b'x'; 2048];
+        client
+            .write_all(&request(&[b"SET", b"key", &value])[..64])
+            .unwrap();
+        fill(&mut connection, 64);
+        assert!(connection.next_request().is_err());
+    }
+
+    #[test]
+    fn test_large_request() {
+        let (mut connection, mut client) = connected_pair();
+        let value = vec![b'x'; 1024 * 1024];
+        let payload = request(&[b"SET", b"key", &value]);
+        let writer = std::thread::spawn(move || client.write_all(&payload).unwrap());
+        let mut command = None;
+        for _ in 0..500 {
+            connection.fill_read_buffer().unwrap();
+            command = next_command(&mut connection);
+            if command.is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        writer.join().unwrap();
+        assert_eq!(
+            command,
+            Some(Command::Set(b"key".to_vec(), value, set::Expiry::Clear))
+        );
+    }
+
+    #[test]
+    fn test_responses_are_queued_in_order() {
+        let (mut connection, _client) = connected_pair();
+        let mut first = connection.output();
+        response_string(&mut first, b"first");
+        let mut second = connection.output();
+        response_string(&mut second, b"second");
+        connection.queue_response(&first);
+        connection.queue_response(&second);
+        assert_eq!(
+            connection.pending_response(),
+            b"\x0a\x00\x00\x00\x03\x05\x00\x00\x00first\x0b\x00\x00\x00\x03\x06\x00\x00\x00second"
+        );
+        assert!(!connection.is_flushed());
+        while !connection.is_flushed() {
+            connection.write_pending().unwrap();
+        }
+        connection.reset_write_buffer();
+        assert!(connection.pending_response().is_empty());
+    }
+
+    #[test]
+    fn test_resp_requests() {
+        let (mut connection, mut client) = connected_pair();
+        let payload = b"*2\r\n$3\r\nget\r\n$3\r\nkey\r\n\r\nPING\r\n";
+        client.write_all(payload).unwrap();
+        fill(&mut connection, payload.len());
+        assert_eq!(
+            next_command(&mut connection),
+            Some(Command::Get(b"key".to_vec()))
+        );
+        assert_eq!(next_command(&mut connection), Some(Command::Ping(None)));
+        assert_eq!(next_command(&mut connection), None);
+        assert_eq!(connection.protocol(), Protocol::Resp2);
+
+        let mut output = connection.output();
+        response_string(&mut output, b"value");
+        connection.queue_response(&output);
+        assert_eq!(connection.pending_response(), b"$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_pinned_protocol() {
+        // 42 arguments: the count's first byte is `*`, which would otherwise
+        // read as the start of a RESP request.
+        let keys: Vec<Vec<u8>> = (0..41).map(|i| format!("k{}", i).into_bytes()).collect();
+        let mut args: Vec<&[u8]> = vec![b"DEL"];
+        args.extend(keys.iter().map(|key| key.as_slice()));
+        let payload = request(&args);
+        assert_eq!(payload[0], b'*');
+
+        let (mut connection, mut client) = connected_pair();
+        connection.set_protocol(Protocol::Binary);
+        client.write_all(&payload).unwrap();
+        fill(&mut connection, payload.len());
+        assert_eq!(next_command(&mut connection), Some(Command::Del(keys)));
+        assert_eq!(connection.protocol(), Protocol::Binary);
+    }
+
+    #[test]
+    fn test_unix_stream() {
+        let (server, mut client) = mio::net::UnixStream::pair().unwrap();
+        let mut connection = Connection::new(server);
+        client.write_all(&request(&[b"GET", b"key"])).unwrap();
+        fill(&mut connection, 15);
+        assert_eq!(
+            next_command(&mut connection),
+            Some(Command::Get(b"key".to_vec()))
+        );
+
+        let mut output = connection.output();
+        response_string(&mut output, b"value");
+        connection.queue_response(&output);
+        assert_eq!(connection.write_pending().unwrap(), 14);
+        let mut reply = [0; 14];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply[4..], &[3, 5, 0, 0, 0, b'v', b'a', b'l', b'u', b'e']);
+    }
+
+    #[test]
+    fn test_idle_time() {
+        let (mut connection, mut client) = connected_pair();
+        assert_eq!(connection.detect_protocol(), None);
+        sleep(Duration::from_millis(50));
+        assert!(connection.idle_time() >= Duration::from_millis(50));
+
+        client.write_all(b"PING\r\n").unwrap();
+        fill(&mut connection, 6);
+        assert!(connection.idle_time() < Duration::from_millis(50));
+        assert_eq!(connection.detect_protocol(), Some(Protocol::Resp2));
+    }
+
+    #[test]
+    fn test_output_buffer_limit() {
+        let (mut connection, _client) = connected_pair();
+        let mut output = connection.output();
+        response_string(&mut output, &[b'x'; 100]);
+        connection.queue_response(&output);
+        let pending = connection.pending_response().len();
+
+        let hard = OutputBufferLimit {
+            hard: pending,
+            ..OutputBufferLimit::UNLIMITED
+        };
+        assert!(connection.exceeds_output_limit(hard));
+        assert!(!connection.exceeds_output_limit(OutputBufferLimit::UNLIMITED));
+
+        let soft = OutputBufferLimit {
+            hard: 0,
+            soft: pending,
+            soft_duration: Duration::from_millis(20),
+        };
+        assert!(!connection.exceeds_output_limit(soft));
+        sleep(Duration::from_millis(30));
+        assert!(connection.exceeds_output_limit(soft));
+
+        // Dropping back under the soft limit restarts the clock.
+        connection.write_pending().unwrap();
+        assert!(!connection.exceeds_output_limit(soft));
+        connection.queue_response(&output);
+        assert!(!connection.exceeds_output_limit(soft));
+    }
+
+    #[test]
+    fn test_read_requests() {
+        let (mut connection, mut client) = connected_pair();
+        client
+            .write_all(b"PING\r\nNOPE\r\n*1\r\n$x\r\nPING\r\n")
+            .unwrap();
+        sleep(Duration::from_millis(50));
+        assert!(connection.read_requests().unwrap());
+        assert_eq!(
+            connection.next_parsed().unwrap().unwrap().command,
+            Command::Ping(None)
+        );
+        // An unknown command is answered and the client carries on...
+        assert!(connection.next_parsed().unwrap().is_err());
+        // ...but nothing is parsed past a frame that cannot be delimited.
+        assert!(connection.next_parsed().unwrap().is_err());
+        assert!(connection.next_parsed().is_none());
     }
 }
@@ -1,18 +1,69 @@
-use crate::{hashtable::HashNode, scalablehashmap::ScalableHashMap};
+use crate::{
+    hashtable::{fnv1a_hash, HashNode},
+    heap::Heap,
+    scalablehashmap::{ScalableHashMap, DEFAULT_LOAD_FACTOR},
+    zset::ZSet,
+};
 use container_of::container_of;
+use std::{
+    borrow::Cow,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-#[repr(C)]
-#[derive(Debug)]
+/// Marks an entry that has no slot in the expiration heap.
+const NO_HEAP_INDEX: usize = usize::MAX;
+
+pub enum Value {
+    String(Vec<u8>),
+    /// A string that holds a counter, kept as a number so INCR and friends
+    /// don't reparse it on every hit. It reads back as its decimal digits.
+    Integer(i64),
+    ZSet(Box<ZSet>),
+}
+
+impl Value {
+    /// Type name as reported by the TYPE command and in errors.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) | Value::Integer(_) => "string",
+            Value::ZSet(_) => "zset",
+        }
+    }
+
+    /// The bytes of a string, however it is encoded, or `None` for other
+    /// types.
+    pub fn string_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Value::String(string) => Some(Cow::Borrowed(string)),
+            Value::Integer(value) => Some(Cow::Owned(value.to_string().into_bytes())),
+            Value::ZSet(_) => None,
+        }
+    }
+}
 
+#[repr(C)]
 pub struct Entry {
     pub node: HashNode,
     pub key: Vec<u8>,
-    pub value: Option<Vec<u8>>,
+    pub value: Option<Value>,
+    expire_at: Option<u64>,
+    heap_index: usize,
 }
 
 impl Entry {
-    pub fn new(node: HashNode, key: Vec<u8>, value: Option<Vec<u8>>) -> Self {
-        Self { node, key, value }
+    pub fn new(node: HashNode, key: Vec<u8>, value: Option<Value>) -> Self {
+        Self {
+            node,
+            key,
+            value,
+            expire_at: None,
+            heap_index: NO_HEAP_INDEX,
+        }
+    }
+
+    /// Absolute expiration deadline in milliseconds since the Unix epoch.
+    pub fn expire_at(&self) -> Option<u64> {
+        self.expire_at
     }
 
     pub fn check_entry_equality(left: &HashNode, right: &HashNode) -> bool {
@@ -28,17 +79,260 @@ impl Entry {
         let entry_right = unsafe { &*re };
         entry_left.key == entry_right.key
     }
+}
 
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
 }
 
-struct Data {
+/// The keyspace. Entries are heap allocated and owned by `Data`; the hash
+/// map and the expiration heap only link to them.
+pub struct Data {
     db: ScalableHashMap,
+    expirations: Heap,
+    loading: bool,
+    expired: Vec<Vec<u8>>,
+}
+
+// SAFETY: every pointer in the map and the heap leads to an entry this
+// `Data` owns, and none are handed out beyond borrows of it, so the whole
+// keyspace can move to another thread, as an embedded server does.
+unsafe impl Send for Data {}
+
+impl Default for Data {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Data {
     pub fn new() -> Self {
+        Self::with_load_factor(DEFAULT_LOAD_FACTOR)
+    }
+
+    pub fn with_load_factor(load_factor: usize) -> Self {
         Self {
-            db: ScalableHashMap::new(),
+            db: ScalableHashMap::with_load_factor(load_factor),
+            expirations: Heap::new(),
+            loading: false,
+            expired: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.db.size()
+    }
+
+    /// Every entry in the keyspace, in no particular order. Entries past their
+    /// deadline but not yet reclaimed are included.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> + '_ {
+        self.db
+            .nodes()
+            .map(|node| unsafe { &*container_of!(node as *const HashNode, Entry, node) })
+    }
+
+    /// Looks up a live entry. An entry whose deadline has passed is removed
+    /// on the spot and reported as missing, unless the keyspace is loading.
+    pub fn lookup(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        let probe = Entry::new(HashNode::new(fnv1a_hash(key)), key.to_vec(), None);
+        let node =
+            self.db
+                .lookup_mut(&probe.node, Entry::check_entry_equality)? as *mut HashNode;
+        let entry = unsafe { &mut *container_of!(node, Entry, node) };
+        if !self.loading && entry.expire_at.is_some_and(|deadline| deadline <= now_ms()) {
+            drop(self.remove(key));
+            self.expired.push(key.to_vec());
+            return None;
         }
+        Some(entry)
+    }
+
+    /// Marks the keyspace as being rebuilt from the append only file. While
+    /// it is, keys past their deadline are kept, so each logged write finds
+    /// the keys it found when it was made; the active sweep reclaims them
+    /// afterwards.
+    pub fn set_loading(&mut self, loading: bool) {
+        self.loading = loading;
+    }
+
+    pub fn loading(&self) -> bool {
+        self.loading
+    }
+
+    /// The keys removed for being past their deadline since the last call,
+    /// in the order they went, so they can be logged as deletions.
+    pub fn take_expired(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Returns the entry for `key`, creating an empty one if it is missing.
+    pub fn lookup_or_insert(&mut self, key: &[u8]) -> &mut Entry {
+        if self.lookup(key).is_none() {
+            let entry = Box::new(Entry::new(
+                HashNode::new(fnv1a_hash(key)),
+                key.to_vec(),
+                None,
+            ));
+            let entry = Box::leak(entry);
+            self.db.insert(&mut entry.node);
+        }
+        self.lookup(key).unwrap()
+    }
+
+    /// Unlinks the entry for `key` from the keyspace and hands ownership back
+    /// to the caller. Expired entries are removed too, but still returned.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Box<Entry>> {
+        let probe = Entry::new(HashNode::new(fnv1a_hash(key)), key.to_vec(), None);
+        let node = self.db.pop(&probe.node, Entry::check_entry_equality)?;
+        let mut entry = unsafe {
+            let node = node.as_ptr();
+            Box::from_raw(container_of!(node, Entry, node))
+        };
+        self.clear_expiry(&mut entry);
+        Some(entry)
+    }
+
+    /// Sets or clears the absolute deadline of `key`. Returns `false` if the
+    /// key does not exist.
+    pub fn set_expiry(&mut self, key: &[u8], deadline: Option<u64>) -> bool {
+        let Some(entry) = self.lookup(key) else {
+            return false;
+        };
+        let entry = entry as *mut Entry;
+        let entry = unsafe { &mut *entry };
+        match deadline {
+            Some(deadline) if entry.heap_index != NO_HEAP_INDEX => {
+                self.expirations.update(entry.heap_index, deadline);
+            }
+            Some(deadline) => self.expirations.push(deadline, &mut entry.heap_index),
+            None => self.clear_expiry(entry),
+        }
+        entry.expire_at = deadline;
+        true
+    }
+
+    fn clear_expiry(&mut self, entry: &mut Entry) {
+        if entry.heap_index != NO_HEAP_INDEX {
+            self.expirations.remove(entry.heap_index);
+            entry.heap_index = NO_HEAP_INDEX;
+        }
+        entry.expire_at = None;
+    }
+
+    /// The earliest deadline among all keys with an expiry.
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.expirations.peek().map(|item| item.value)
+    }
+
+    /// Removes up to `limit` keys whose deadline is at or before `now` and
+    /// returns how many were removed.
+    pub fn expire_keys(&mut self, now: u64, limit: usize) -> usize {
+        let mut expired = 0;
+        while expired < limit {
+            let Some(item) = self.expirations.peek() else {
+                break;
+            };
+            if item.value > now {
+                break;
+            }
+            let key = unsafe {
+                let index = item.index_ref().as_ptr() as *const usize;
+                (*container_of!(index, Entry, heap_index)).key.clone()
+            };
+            drop(self.remove(&key));
+            self.expired.push(key);
+            expired += 1;
+        }
+        expired
+    }
+}
+
+impl Drop for Data {
+    fn drop(&mut self) {
+        let entries: Vec<*const Entry> = self
+            .db
+            .nodes()
+            .map(|node| unsafe { container_of!(node as *const HashNode, Entry, node) })
+            .collect();
+        self.db.destroy();
+        for entry in entries {
+            drop(unsafe { Box::from_raw(entry as *mut Entry) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_lookup_remove() {
+        let mut data = Data::new();
+        for i in 0..100 {
+            let key = format!("key{}", i).into_bytes();
+            data.lookup_or_insert(&key).value = Some(Value::String(key.clone()));
+        }
+        assert_eq!(data.size(), 100);
+        for i in 0..100 {
+            let key = format!("key{}", i).into_bytes();
+            match &data.lookup(&key).unwrap().value {
+                Some(Value::String(value)) => assert_eq!(value, &key),
+                _ => panic!("expected a string value"),
+            }
+        }
+        let removed = data.remove(b"key42").unwrap();
+        assert_eq!(removed.key, b"key42");
+        assert!(data.lookup(b"key42").is_none());
+        assert!(data.remove(b"key42").is_none());
+        assert_eq!(data.size(), 99);
+    }
+
+    #[test]
+    fn test_lazy_expiry() {
+        let mut data = Data::new();
+        data.lookup_or_insert(b"gone").value = Some(Value::String(b"1".to_vec()));
+        data.lookup_or_insert(b"kept").value = Some(Value::String(b"2".to_vec()));
+        assert!(data.set_expiry(b"gone", Some(now_ms() - 1)));
+        assert!(data.set_expiry(b"kept", Some(now_ms() + 60_000)));
+        assert!(!data.set_expiry(b"missing", Some(now_ms())));
+        assert!(data.lookup(b"gone").is_none());
+        assert!(data.lookup(b"kept").is_some());
+        assert_eq!(data.size(), 1);
+        assert_eq!(data.take_expired(), vec![b"gone".to_vec()]);
+        assert!(data.take_expired().is_empty());
+
+        data.set_expiry(b"kept", Some(now_ms() - 1));
+        data.set_loading(true);
+        assert!(data.lookup(b"kept").is_some());
+        data.set_loading(false);
+        assert!(data.lookup(b"kept").is_none());
+    }
+
+    #[test]
+    fn test_active_expiry() {
+        let mut data = Data::new();
+        let now = now_ms();
+        for i in 0..10u64 {
+            let key = format!("key{}", i).into_bytes();
+            data.lookup_or_insert(&key).value = Some(Value::String(key.clone()));
+            data.set_expiry(&key, Some(now + 1000 * (10 - i)));
+        }
+        assert_eq!(data.next_expiry(), Some(now + 1000));
+        assert!(data.set_expiry(b"key9", None));
+        assert_eq!(data.next_expiry(), Some(now + 2000));
+
+        assert_eq!(data.expire_keys(now + 5000, 100), 4);
+        assert_eq!(data.size(), 6);
+        let expired: Vec<Vec<u8>> = (5..9).rev().map(|i| format!("key{}", i).into()).collect();
+        assert_eq!(data.take_expired(), expired);
+        assert!(data.lookup(b"key5").is_none());
+        assert!(data.lookup(b"key4").is_some());
+
+        assert_eq!(data.expire_keys(now + 60_000, 2), 2);
+        assert_eq!(data.expire_keys(now + 60_000, 100), 3);
+        assert_eq!(data.next_expiry(), None);
+        assert_eq!(data.size(), 1);
     }
 }
@@ -0,0 +1,60 @@
+//! Child processes that work on a copy-on-write view of the keyspace while
+//! the parent keeps serving requests.
+
+use anyhow::{Context, Result};
+use log::error;
+use std::io;
+
+pub struct Child {
+    pid: libc::pid_t,
+}
+
+/// Forks and runs `work` in the child, which exits with its outcome. The
+/// child never returns from this function.
+pub fn spawn(name: &str, work: impl FnOnce() -> Result<()>) -> Result<Child> {
+    match unsafe { libc::fork() } {
+        -1 => {
+            Err(io::Error::last_os_error()).with_context(|| format!("failed to fork for {}", name))
+        }
+        0 => {
+            let status = match work() {
+                Ok(()) => 0,
+                Err(err) => {
+                    error!("{} failed: {:#}", name, err);
+                    1
+                }
+            };
+            unsafe { libc::_exit(status) }
+        }
+        pid => Ok(Child { pid }),
+    }
+}
+
+impl Child {
+    /// Collects the child if it has exited, without blocking. Returns whether
+    /// it succeeded, or `None` while it is still running.
+    pub fn try_wait(&self) -> Option<bool> {
+        let mut status = 0;
+        match unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) } {
+            0 => None,
+            -1 => Some(false),
+            _ => Some(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0),
+        }
+    }
+
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Kills the child and waits for it to exit.
+    pub fn kill(self) {
+        // SAFETY: plain syscalls on our own child. Owners drop a `Child`
+        // once `try_wait` has reaped it, so it is not reaped yet and its pid
+        // cannot have been reused by an unrelated process. `waitpid` accepts
+        // a null status pointer.
+        unsafe {
+            libc::kill(self.pid, libc::SIGKILL);
+            libc::waitpid(self.pid, std::ptr::null_mut(), 0);
+        }
+    }
+}
@@ -1,10 +1,13 @@
-use std::{fmt::Display, ops::RangeBounds};
+use std::{fmt::Display, ptr::NonNull};
 
 use anyhow::Result;
 
+/// Intrusive hash table link. A `HashNode` is embedded in the value it
+/// indexes (see `Entry`), and the table only links nodes together; whoever
+/// inserts a node keeps it alive and in place until it has been popped.
 #[derive(Debug, Clone)]
 pub struct HashNode {
-    next: Option<Box<HashNode>>,
+    next: Option<NonNull<HashNode>>,
     code: u64,
 }
 
@@ -15,17 +18,13 @@ impl PartialEq for HashNode {
 }
 
 impl HashNode {
-    pub fn new(next: Option<HashNode>, code: u64) -> Self {
-        if let Some(n) = next {
-            Self {
-                next: Some(Box::new(n)),
-                code,
-            }
-        } else {
-            Self { next: None, code }
-        }
+    pub fn new(code: u64) -> Self {
+        Self { next: None, code }
+    }
+
+    pub fn code(&self) -> u64 {
+        self.code
     }
-   
 }
 
 pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
@@ -36,9 +35,11 @@ pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
     h as u64
 }
 
+type Link = Option<NonNull<HashNode>>;
+
 #[derive(Debug)]
 pub struct HashTable {
-    pub table: Vec<Option<Box<HashNode>>>,
+    pub table: Vec<Link>,
     size: usize,
     mask: usize,
 }
@@ -63,71 +64,98 @@ impl HashTable {
         Ok(Self { table, size, mask })
     }
 
+    /// Links `node` into its bucket. The node must stay alive and must not
+    /// move until it is detached again.
     pub fn insert(&mut self, node: &mut HashNode) {
         let pos = (node.code & (self.mask as u64)) as usize;
-        let next = self.table.get_mut(pos).and_then(Option::take);
-        if let Some(n) = next {
-            node.next = Some(n);
-        }
-        self.table[pos] = Some(Box::new(node.clone()));
+        node.next = self.table[pos].take();
+        self.table[pos] = Some(NonNull::from(node));
         self.size += 1;
     }
 
+    /// Returns the link that points at the node matching `key`, so the caller
+    /// can either read the node or unlink it.
+    fn lookup_link(
+        &mut self,
+        key: &HashNode,
+        cmp: fn(&HashNode, &HashNode) -> bool,
+    ) -> Option<*mut Link> {
+        if self.table.is_empty() {
+            return None;
+        }
+        let pos = (key.code & (self.mask as u64)) as usize;
+        let mut link: *mut Link = &mut self.table[pos];
+        unsafe {
+            while let Some(mut node) = *link {
+                if node.as_ref().code == key.code && cmp(node.as_ref(), key) {
+                    return Some(link);
+                }
+                link = &mut node.as_mut().next;
+            }
+        }
+        None
+    }
+
     pub fn lookup(
         &self,
-        node: &HashNode,
+        key: &HashNode,
         cmp: fn(&HashNode, &HashNode) -> bool,
     ) -> Option<&HashNode> {
         if self.table.is_empty() {
             return None;
         }
-        let pos = (node.code & (self.mask as u64)) as usize;
-        let mut from = self.table[pos].as_ref();
-        while let Some(n) = from {
-            if cmp(n, node) {
-                return Some(n);
+        let pos = (key.code & (self.mask as u64)) as usize;
+        let mut from = self.table[pos];
+        while let Some(node) = from {
+            let node = unsafe { node.as_ref() };
+            if node.code == key.code && cmp(node, key) {
+                return Some(node);
             }
-            from = n.next.as_ref();
+            from = node.next;
         }
         None
     }
+
     pub fn lookup_mut(
         &mut self,
-        node: &HashNode,
+        key: &HashNode,
         cmp: fn(&HashNode, &HashNode) -> bool,
     ) -> Option<&mut HashNode> {
-        if self.table.is_empty() {
-            return None;
-        }
-        let pos = (node.code & (self.mask as u64)) as usize;
-        let mut from = self.table[pos].as_mut();
-        while let Some(n) = from {
-            if cmp(n, node) {
-                return Some(n);
-            }
-            from = n.next.as_mut();
+        self.lookup_link(key, cmp)
+            .and_then(|link| unsafe { (*link).map(|mut node| node.as_mut()) })
+    }
+
+    /// Unlinks the node matching `key` and hands it back to the caller.
+    pub fn detach(
+        &mut self,
+        key: &HashNode,
+        cmp: fn(&HashNode, &HashNode) -> bool,
+    ) -> Option<NonNull<HashNode>> {
+        let link = self.lookup_link(key, cmp)?;
+        let mut node = unsafe { (*link)? };
+        unsafe {
+            *link = node.as_mut().next.take();
         }
-        None
+        self.size -= 1;
+        Some(node)
     }
 
-    pub fn detach(&mut self, from: &mut HashNode) -> Option<HashNode> {
-        let _ = std::mem::replace(&mut from.next, None);
+    /// Unlinks the first node of bucket `pos`, if any.
+    pub fn detach_head(&mut self, pos: usize) -> Option<NonNull<HashNode>> {
+        let mut node = self.table[pos]?;
+        self.table[pos] = unsafe { node.as_mut().next.take() };
         self.size -= 1;
-        Some(HashNode {
-            code: from.code,
-            next: None,
-        })
+        Some(node)
     }
 
-    pub fn drain<R>(&mut self, range: R) -> impl Iterator<Item = Box<HashNode>> + '_
-    where
-        R: RangeBounds<usize>,
-    {
-        self.table.drain(range).filter_map(|node| {
-            if node.is_some() {
-                self.size -= 1;
-            }
-            node
+    pub fn nodes(&self) -> impl Iterator<Item = &HashNode> + '_ {
+        self.table.iter().flat_map(|head| {
+            let mut from = *head;
+            std::iter::from_fn(move || {
+                let node = unsafe { from?.as_ref() };
+                from = node.next;
+                Some(node)
+            })
         })
     }
 
@@ -143,8 +171,8 @@ impl HashTable {
         self.mask
     }
 
-    pub fn resize(&mut self, new_size: usize) {
-        self.table.resize(new_size, None);
+    pub fn capacity(&self) -> usize {
+        self.mask + 1
     }
 }
 
@@ -153,11 +181,12 @@ mod tests {
 
     use super::*;
 
-    fn print_out_linked_list(head: &Option<Box<HashNode>>) {
-        let mut current = head;
+    fn print_out_linked_list(head: &Link) {
+        let mut current = *head;
         while let Some(node) = current {
+            let node = unsafe { node.as_ref() };
             print!("{}->", node.code);
-            current = &node.next;
+            current = node.next;
         }
     }
 
@@ -182,19 +211,6 @@ mod tests {
         nodes
     }
 
-    fn generate_linked_list(n: usize) -> Option<Box<HashNode>> {
-        let mut head = None;
-        let mut tail = &mut head;
-        for i in 0..n {
-            *tail = Some(Box::new(HashNode {
-                next: None,
-                code: i as u64,
-            }));
-            tail = &mut (*tail).as_mut().unwrap().next;
-        }
-        head
-    }
-
     #[test]
     fn test_new() {
         let ht = HashTable::new(1024);
@@ -215,6 +231,7 @@ mod tests {
             ht.insert(n);
         }
         assert_eq!(ht.size, 18);
+        assert_eq!(ht.nodes().count(), 18);
         print_out_hash_table(&ht)
     }
 
@@ -228,47 +245,32 @@ mod tests {
         }
         print_out_hash_table(&ht);
         println!("\n\n");
-        let node1 = Box::new(HashNode {
-            next: None,
-            code: 4,
-        });
+        let node1 = HashNode::new(4);
 
         let found = ht.lookup(&node1, |a, b| a == b);
         assert!(found.is_some());
         assert_eq!(found.unwrap().code, 4);
         println!("node1 found!");
 
-        let node2 = Box::new(HashNode {
-            next: None,
-            code: 13,
-        });
+        let node2 = HashNode::new(13);
 
         let found = ht.lookup(&node2, |a, b| a == b);
         assert!(found.is_some());
         assert_eq!(found.unwrap().code, 13);
         println!("node2 found!");
 
-        let node3 = Box::new(HashNode {
-            next: None,
-            code: 21,
-        });
+        let node3 = HashNode::new(21);
 
         let found = ht.lookup(&node3, |a, b| a == b);
         assert!(found.is_none());
         println!("node3 not found!");
 
-        let node4 = Box::new(HashNode {
-            next: None,
-            code: 45,
-        });
+        let node4 = HashNode::new(45);
         let found = ht.lookup(&node4, |a, b| a == b);
         assert!(found.is_none());
         println!("node4 not found!");
 
-        let node5 = Box::new(HashNode {
-            next: None,
-            code: 16,
-        });
+        let node5 = HashNode::new(16);
 
         let found = ht.lookup(&node5, |a, b| a == b);
         assert!(found.is_some());
@@ -280,18 +282,33 @@ mod tests {
     #[test]
     fn test_detach() {
         let mut ht = HashTable::new(8).unwrap();
-        ht.size = 5;
+        let mut nodes = generate_node_list(5);
+        for n in nodes.iter_mut() {
+            ht.insert(n);
+        }
+        print_out_hash_table(&ht);
 
-        let head = generate_linked_list(5);
-        print_out_linked_list(&head);
+        let detached = ht.detach(&HashNode::new(1), |a, b| a == b);
+        println!("Detached node: {:?}", detached);
+        assert_eq!(detached, Some(NonNull::from(&nodes[1])));
+        assert_eq!(ht.size(), 4);
+        assert!(ht.lookup(&HashNode::new(1), |a, b| a == b).is_none());
+        assert!(ht.detach(&HashNode::new(1), |a, b| a == b).is_none());
+        assert!(ht.lookup(&HashNode::new(3), |a, b| a == b).is_some());
+    }
 
-        if let Some(mut first_node) = head {
-            let node = first_node.next.as_mut();
-            if let Some(node) = node {
-                let detached = ht.detach(node);
-                println!("Detached node: {:?}", detached);
-                assert_eq!(detached.unwrap().code, 1);
-            }
+    #[test]
+    fn test_detach_from_chain() {
+        let mut ht = HashTable::new(1).unwrap();
+        let mut nodes = generate_node_list(5);
+        for n in nodes.iter_mut() {
+            ht.insert(n);
         }
+        assert!(ht.detach(&HashNode::new(2), |a, b| a == b).is_some());
+        let mut codes: Vec<u64> = ht.nodes().map(|n| n.code).collect();
+        codes.sort();
+        assert_eq!(codes, vec![0, 1, 3, 4]);
+        assert!(ht.detach_head(0).is_some());
+        assert_eq!(ht.size(), 3);
     }
 }
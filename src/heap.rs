@@ -0,0 +1,155 @@
+use std::ptr::NonNull;
+
+/// Heap element. `index_ref` points at the owner's copy of the item's
+/// position, which the heap rewrites every time the item moves so the
+/// owner can update or remove it in O(log n).
+#[derive(Debug)]
+pub struct HeapItem {
+    pub value: u64,
+    index_ref: NonNull<usize>,
+}
+
+impl HeapItem {
+    pub fn index_ref(&self) -> NonNull<usize> {
+        self.index_ref
+    }
+}
+
+/// Binary min-heap keyed by `u64`, used for key expiration deadlines.
+#[derive(Debug, Default)]
+pub struct Heap {
+    items: Vec<HeapItem>,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn peek(&self) -> Option<&HeapItem> {
+        self.items.first()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&HeapItem> {
+        self.items.get(index)
+    }
+
+    /// Adds an item. `index_ref` must stay valid until the item is removed.
+    pub fn push(&mut self, value: u64, index_ref: &mut usize) {
+        self.items.push(HeapItem {
+            value,
+            index_ref: NonNull::from(index_ref),
+        });
+        self.update_position(self.items.len() - 1);
+    }
+
+    pub fn update(&mut self, index: usize, value: u64) {
+        self.items[index].value = value;
+        self.update_position(index);
+    }
+
+    pub fn remove(&mut self, index: usize) -> HeapItem {
+        let item = self.items.swap_remove(index);
+        if index < self.items.len() {
+            self.update_position(index);
+        }
+        item
+    }
+
+    fn update_position(&mut self, index: usize) {
+        if index > 0 && self.items[(index - 1) / 2].value > self.items[index].value {
+            self.sift_up(index);
+        } else {
+            self.sift_down(index);
+        }
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.items[parent].value <= self.items[index].value {
+                break;
+            }
+            self.items.swap(parent, index);
+            self.write_index(index);
+            index = parent;
+        }
+        self.write_index(index);
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let left = index * 2 + 1;
+            let right = left + 1;
+            let mut smallest = index;
+            if left < self.items.len() && self.items[left].value < self.items[smallest].value {
+                smallest = left;
+            }
+            if right < self.items.len() && self.items[right].value < self.items[smallest].value {
+                smallest = right;
+            }
+            if smallest == index {
+                break;
+            }
+            self.items.swap(smallest, index);
+            self.write_index(index);
+            index = smallest;
+        }
+        self.write_index(index);
+    }
+
+    fn write_index(&mut self, index: usize) {
+        unsafe {
+            *self.items[index].index_ref.as_mut() = index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn pops_in_order(values: Vec<u64>) -> bool {
+        let mut indexes = vec![0usize; values.len()];
+        let mut heap = Heap::new();
+        for (value, index) in values.iter().zip(indexes.iter_mut()) {
+            heap.push(*value, index);
+        }
+        let mut sorted = values.clone();
+        sorted.sort();
+        let mut popped = Vec::new();
+        while !heap.is_empty() {
+            popped.push(heap.remove(0).value);
+        }
+        popped == sorted
+    }
+
+    #[quickcheck]
+    fn tracks_indexes(values: Vec<u64>, updates: Vec<(usize, u64)>) -> bool {
+        let mut indexes = vec![0usize; values.len()];
+        let mut heap = Heap::new();
+        for (value, index) in values.iter().zip(indexes.iter_mut()) {
+            heap.push(*value, index);
+        }
+        for (owner, value) in updates {
+            if values.is_empty() {
+                break;
+            }
+            let owner = owner % values.len();
+            heap.update(indexes[owner], value);
+        }
+        indexes.iter().enumerate().all(|(owner, &index)| {
+            heap.get(index).map(|item| item.index_ref) == Some(NonNull::from(&indexes[owner]))
+        })
+    }
+}
@@ -0,0 +1,254 @@
+//! Threads that read and parse requests, and write replies, for batches of
+//! connections.
+//!
+//! Replies are encoded by the commands as they run on the main thread; the
+//! workers only write the encoded bytes out. What they take off the main
+//! thread is the socket I/O and request parsing, so extra threads only pay
+//! off with spare cores and many busy connections; on a single core the
+//! handoffs make them a little slower than serving everything in place.
+//!
+//! The event loop hands each batch out and waits for all of it to come back,
+//! so connections are only ever touched by one thread at a time and commands
+//! still run one after another on the main thread, between the batches. A
+//! connection's requests are parsed in order on one thread and executed in
+//! that order, so pipelined replies keep their order too.
+
+use crate::{connection::Connection, listener::Stream};
+use mio::Token;
+use std::{
+    collections::HashMap,
+    io,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// Below this many connections per thread a batch is handled on the main
+/// thread alone, as handing it out would cost more than it saves.
+const MIN_CONNECTIONS_PER_THREAD: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    /// `Connection::read_requests`, returning whether the peer is still
+    /// connected.
+    Read,
+    /// `Connection::send_response`.
+    Write,
+}
+
+impl Task {
+    fn perform(self, connection: &mut Connection<Stream>) -> io::Result<bool> {
+        match self {
+            Task::Read => connection.read_requests(),
+            Task::Write => connection.send_response().map(|()| true),
+        }
+    }
+}
+
+struct Job {
+    token: Token,
+    connection: Connection<Stream>,
+    result: io::Result<bool>,
+}
+
+struct Batch {
+    task: Task,
+    jobs: Vec<Job>,
+}
+
+struct Worker {
+    batches: Option<Sender<Batch>>,
+    done: Receiver<Batch>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub struct IoThreads {
+    workers: Vec<Worker>,
+}
+
+impl IoThreads {
+    /// Starts `count - 1` worker threads. The main thread takes a share of
+    /// every batch, so a count of one does all the I/O on the main thread.
+    pub fn new(count: usize) -> io::Result<IoThreads> {
+        let mut workers = Vec::new();
+        for index in 1..count {
+            let (batches, inbox) = mpsc::channel::<Batch>();
+            let (outbox, done) = mpsc::channel();
+            let thread = thread::Builder::new()
+                .name(format!("io-thread-{}", index))
+                .spawn(move || {
+                    for mut batch in inbox {
+                        for job in &mut batch.jobs {
+                            job.result = batch.task.perform(&mut job.connection);
+                        }
+                        if outbox.send(batch).is_err() {
+                            return;
+                        }
+                    }
+                })?;
+            workers.push(Worker {
+                batches: Some(batches),
+                done,
+                thread: Some(thread),
+            });
+        }
+        Ok(IoThreads { workers })
+    }
+
+    /// Performs `task` on the connections at `tokens`, spread across the
+    /// threads, and returns each one's result once all are done. Tokens
+    /// missing from `connections` are skipped. Fails if a worker thread has
+    /// stopped, which only a panic on it can cause; the connections it held
+    /// are lost.
+    pub fn run(
+        &self,
+        task: Task,
+        connections: &mut HashMap<Token, Connection<Stream>>,
+        tokens: &[Token],
+    ) -> io::Result<Vec<(Token, io::Result<bool>)>> {
+        let threads = self.workers.len() + 1;
+        if tokens.len() < threads * MIN_CONNECTIONS_PER_THREAD {
+            return Ok(tokens
+                .iter()
+                .filter_map(|token| {
+                    let connection = connections.get_mut(token)?;
+                    Some((*token, task.perform(connection)))
+                })
+                .collect());
+        }
+
+        let mut batches: Vec<Vec<Job>> = (0..threads).map(|_| Vec::new()).collect();
+        let jobs = tokens.iter().filter_map(|token| {
+            let connection = connections.remove(token)?;
+            Some(Job {
+                token: *token,
+                connection,
+                result: Ok(true),
+            })
+        });
+        for (index, job) in jobs.enumerate() {
+            batches[index % threads].push(job);
+        }
+        let mut own = batches.remove(0);
+        let mut busy = Vec::with_capacity(self.workers.len());
+        let mut stopped = false;
+        for (worker, jobs) in self.workers.iter().zip(batches) {
+            let batch = Batch { task, jobs };
+            let unsent = match &worker.batches {
+                Some(batches) => batches.send(batch).err().map(|err| err.0),
+                None => Some(batch),
+            };
+            match unsent {
+                None => busy.push(worker),
+                // Done here instead, so the connections are not lost too.
+                Some(batch) => {
+                    stopped = true;
+                    own.extend(batch.jobs);
+                }
+            }
+        }
+        for job in &mut own {
+            job.result = task.perform(&mut job.connection);
+        }
+        let mut returned = Vec::new();
+        for worker in busy {
+            match worker.done.recv() {
+                Ok(batch) => returned.extend(batch.jobs),
+                Err(_) => stopped = true,
+            }
+        }
+        let mut results = Vec::with_capacity(tokens.len());
+        for job in own.into_iter().chain(returned) {
+            connections.insert(job.token, job.connection);
+            results.push((job.token, job.result));
+        }
+        if stopped {
+            return Err(io::Error::other("an I/O thread stopped"));
+        }
+        Ok(results)
+    }
+}
+
+impl Drop for IoThreads {
+    fn drop(&mut self) {
+        // Closing the channels ends each worker's loop.
+        for worker in &mut self.workers {
+            worker.batches.take();
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{Command, Request},
+        serialization::response_status,
+    };
+    use mio::net::UnixStream;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_batches_are_shared_out() {
+        let threads = IoThreads::new(3).unwrap();
+        let mut connections = HashMap::new();
+        let mut peers = Vec::new();
+        for index in 0..8 {
+            let (server, mut peer) = UnixStream::pair().unwrap();
+            peer.write_all(b"PING\r\n").unwrap();
+            connections.insert(Token(index), Connection::new(Stream::Unix(server)));
+            peers.push(peer);
+        }
+        let tokens: Vec<Token> = (0..10).map(Token).collect();
+
+        let results = threads.run(Task::Read, &mut connections, &tokens).unwrap();
+        // The two unknown tokens are skipped.
+        assert_eq!(results.len(), 8);
+        assert!(results.iter().all(|(_, result)| matches!(result, Ok(true))));
+        assert_eq!(connections.len(), 8);
+        for connection in connections.values_mut() {
+            assert!(matches!(
+                connection.next_parsed(),
+                Some(Ok(Request {
+                    command: Command::Ping(None),
+                    ..
+                }))
+            ));
+            let mut output = connection.output();
+            response_status(&mut output, "PONG");
+            connection.queue_response(&output);
+        }
+
+        threads.run(Task::Write, &mut connections, &tokens).unwrap();
+        assert!(connections.values().all(Connection::is_flushed));
+        for peer in &mut peers {
+            let mut reply = [0; 7];
+            peer.read_exact(&mut reply).unwrap();
+            assert_eq!(&reply, b"+PONG\r\n");
+        }
+    }
+
+    #[test]
+    fn test_stopped_thread_is_an_error() {
+        let mut threads = IoThreads::new(2).unwrap();
+        // Swapping in a channel nobody receives from stops the worker, and
+        // sending to it fails as if the worker had panicked.
+        let (batches, _) = mpsc::channel();
+        threads.workers[0].batches = Some(batches);
+        let mut connections = HashMap::new();
+        let mut peers = Vec::new();
+        for index in 0..4 {
+            let (server, peer) = UnixStream::pair().unwrap();
+            connections.insert(Token(index), Connection::new(Stream::Unix(server)));
+            peers.push(peer);
+        }
+        let tokens: Vec<Token> = (0..4).map(Token).collect();
+
+        assert!(threads.run(Task::Read, &mut connections, &tokens).is_err());
+        assert_eq!(connections.len(), 4);
+    }
+}
@@ -0,0 +1,29 @@
+//! An in-memory key-value store speaking its own binary protocol and RESP.
+//! `Server` runs one, either in-process or as the `crabcache` binary.
+//!
+//! The server reports what it does through the `log` crate, so it stays quiet
+//! in-process until the host installs a logger; the binary prints the log to
+//! stdout.
+
+pub mod aof;
+pub mod avl_tree;
+pub mod commands;
+pub mod config;
+pub mod connection;
+pub mod entry;
+pub mod fork;
+pub mod hashtable;
+pub mod heap;
+pub mod io_threads;
+pub mod listener;
+pub mod rdb;
+pub mod resp;
+pub mod scalablehashmap;
+pub mod serialization;
+pub mod server;
+pub mod signals;
+pub mod stats;
+pub mod zset;
+
+pub use config::Config;
+pub use server::{Builder, Server, ServerHandle, ShutdownHandle};
@@ -0,0 +1,372 @@
+//! Listening sockets for the addresses in the configuration, and the
+//! streams they accept.
+
+use crate::serialization::Protocol;
+use mio::{
+    event::Source,
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    Interest, Registry, Token,
+};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    mem,
+    net::{self, SocketAddr},
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::fs::PermissionsExt,
+    },
+    path::Path,
+    time::Duration,
+};
+
+/// Which protocol a listener's clients speak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenerProtocol {
+    /// Guessed from the first bytes each client sends.
+    Auto,
+    /// The native length-prefixed protocol only.
+    Native,
+    /// RESP only, starting at RESP2 until HELLO says otherwise.
+    Resp,
+}
+
+impl ListenerProtocol {
+    pub fn parse(value: &str) -> Option<ListenerProtocol> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Some(ListenerProtocol::Auto),
+            "native" => Some(ListenerProtocol::Native),
+            "resp" => Some(ListenerProtocol::Resp),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListenerProtocol::Auto => "auto",
+            ListenerProtocol::Native => "native",
+            ListenerProtocol::Resp => "resp",
+        }
+    }
+
+    /// The protocol new connections start in, or `None` to detect it.
+    pub fn pinned(&self) -> Option<Protocol> {
+        match self {
+            ListenerProtocol::Auto => None,
+            ListenerProtocol::Native => Some(Protocol::Binary),
+            ListenerProtocol::Resp => Some(Protocol::Resp2),
+        }
+    }
+}
+
+/// Connections a listener queues before they are accepted.
+const BACKLOG: libc::c_int = 1024;
+
+/// Binds a non-blocking TCP listener to `addr`. IPv6 listeners accept IPv6
+/// only, so `0.0.0.0` and `::` can both be bound on the same port.
+pub fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let SocketAddr::V6(v6) = addr else {
+        return TcpListener::bind(addr);
+    };
+    // SAFETY: the descriptor is fresh and immediately owned by `listener`,
+    // which closes it on every early return below.
+    let listener = unsafe {
+        let fd = libc::socket(
+            libc::AF_INET6,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        net::TcpListener::from_raw_fd(fd)
+    };
+    let fd = listener.as_raw_fd();
+    set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+    set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 1)?;
+    let sockaddr = libc::sockaddr_in6 {
+        sin6_family: libc::AF_INET6 as libc::sa_family_t,
+        sin6_port: v6.port().to_be(),
+        sin6_flowinfo: v6.flowinfo(),
+        sin6_addr: libc::in6_addr {
+            s6_addr: v6.ip().octets(),
+        },
+        sin6_scope_id: v6.scope_id(),
+    };
+    // SAFETY: `sockaddr` is a valid `sockaddr_in6` of the length passed.
+    let bound = unsafe {
+        libc::bind(
+            fd,
+            &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+        )
+    };
+    if bound < 0 || unsafe { libc::listen(fd, BACKLOG) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(TcpListener::from_std(listener))
+}
+
+/// Binds a Unix domain socket listener at `path`, replacing a socket left
+/// behind by an earlier run. A non-zero `perm` sets the file's permission
+/// bits; otherwise they follow the umask.
+pub fn bind_unix(path: &Path, perm: u32) -> io::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let listener = UnixListener::bind(path)?;
+    if perm != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(perm))?;
+    }
+    Ok(listener)
+}
+
+/// Turns on TCP keepalive for `stream`, probing a peer that has been silent
+/// for `interval`. As in Redis, the peer is given up on after three
+/// unanswered probes sent a third of `interval` apart.
+pub fn set_keepalive(stream: &TcpStream, interval: Duration) -> io::Result<()> {
+    let fd = stream.as_raw_fd();
+    let idle = interval.as_secs().clamp(1, libc::c_int::MAX as u64) as libc::c_int;
+    set_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle)?;
+    set_option(
+        fd,
+        libc::IPPROTO_TCP,
+        libc::TCP_KEEPINTVL,
+        (idle / 3).max(1),
+    )?;
+    set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, 3)
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Accepts a pending connection, along with a description of the peer
+    /// for the log.
+    pub fn accept(&self) -> io::Result<(Stream, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept()?;
+                Ok((Stream::Tcp(stream), addr.to_string()))
+            }
+            Listener::Unix(listener) => {
+                let stream = Stream::Unix(listener.accept()?.0);
+                // Clients rarely bind their end, so name the socket they
+                // came in through instead. The name is only for the log, so
+                // failing to look it up does not lose the client.
+                let path = stream
+                    .local_addr()
+                    .unwrap_or_else(|_| "unix socket".to_string());
+                Ok((stream, path))
+            }
+        }
+    }
+}
+
+impl Source for Listener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.register(registry, token, interests),
+            Listener::Unix(listener) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.reregister(registry, token, interests),
+            Listener::Unix(listener) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.deregister(registry),
+            Listener::Unix(listener) => listener.deregister(registry),
+        }
+    }
+}
+
+/// A client connection over either kind of socket.
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// The address the client connected to: the listening address for TCP,
+    /// and the socket's path for a Unix socket.
+    pub fn local_addr(&self) -> io::Result<String> {
+        match self {
+            Stream::Tcp(stream) => Ok(stream.local_addr()?.to_string()),
+            Stream::Unix(stream) => {
+                let addr = stream.local_addr()?;
+                let path = addr.as_pathname().unwrap_or(Path::new("unix socket"));
+                Ok(path.display().to_string())
+            }
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.register(registry, token, interests),
+            Stream::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.reregister(registry, token, interests),
+            Stream::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.deregister(registry),
+            Stream::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+/// Sets the integer socket option `name`; boolean options take 1 for on.
+fn set_option(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    // SAFETY: `value` outlives the call and its size is passed along with it.
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_both_families_on_one_port() {
+        let v4 = bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        // Loopback IPv6 may be unavailable in minimal containers.
+        if let Ok(v6) = bind_tcp(SocketAddr::new("::1".parse().unwrap(), port)) {
+            assert_eq!(v6.local_addr().unwrap().port(), port);
+            assert!(bind_tcp(v6.local_addr().unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_bind_unix_replaces_stale_socket() {
+        let path = std::env::temp_dir().join(format!("crabcache-{}.sock", std::process::id()));
+        drop(bind_unix(&path, 0).unwrap());
+        let listener = bind_unix(&path, 0o700).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let _client = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let (_, peer) = Listener::Unix(listener).accept().unwrap();
+        assert_eq!(peer, path.display().to_string());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_set_keepalive() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let stream = TcpStream::from_std(stream);
+        set_keepalive(&stream, Duration::from_secs(60)).unwrap();
+
+        let get = |level, name| {
+            let mut value: libc::c_int = 0;
+            let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let result = unsafe {
+                libc::getsockopt(
+                    stream.as_raw_fd(),
+                    level,
+                    name,
+                    &mut value as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, 0);
+            value
+        };
+        assert_ne!(get(libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+        assert_eq!(get(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE), 60);
+        assert_eq!(get(libc::IPPROTO_TCP, libc::TCP_KEEPINTVL), 20);
+        assert_eq!(get(libc::IPPROTO_TCP, libc::TCP_KEEPCNT), 3);
+    }
+}
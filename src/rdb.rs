@@ -0,0 +1,367 @@
+//! Point-in-time snapshots of the keyspace.
+//!
+//! A snapshot file is laid out as follows, all integers little-endian:
+//!
+//! ```text
+//! "CRABRDB" u16 version
+//! { [OP_EXPIRE u64 deadline_ms] type:u8 key value }*
+//! OP_EOF u32 crc32
+//! ```
+//!
+//! Keys and strings are a u32 length followed by the bytes. A sorted set is a
+//! u32 member count followed by `f64 score, member` pairs in ascending order.
+//! The checksum covers every byte before it.
+
+use crate::{
+    entry::{now_ms, Data, Value},
+    fork::{self, Child},
+    zset::ZSet,
+};
+use anyhow::{bail, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+const MAGIC: &[u8; 7] = b"CRABRDB";
+const VERSION: u16 = 1;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_ZSET: u8 = 1;
+const OP_EXPIRE: u8 = 0xfc;
+const OP_EOF: u8 = 0xff;
+
+pub const DEFAULT_PATH: &str = "dump.rdb";
+
+/// Feeds everything written through it into a running CRC32.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Serializes every live key in `data` into `writer`.
+pub fn write_snapshot<W: Write>(data: &Data, writer: W) -> io::Result<()> {
+    let mut out = ChecksumWriter {
+        inner: writer,
+        hasher: crc32fast::Hasher::new(),
+    };
+    out.write_all(MAGIC)?;
+    out.write_u16::<LittleEndian>(VERSION)?;
+    let now = now_ms();
+    for entry in data.entries() {
+        let Some(value) = &entry.value else {
+            continue;
+        };
+        if let Some(deadline) = entry.expire_at() {
+            if deadline <= now {
+                continue;
+            }
+            out.write_u8(OP_EXPIRE)?;
+            out.write_u64::<LittleEndian>(deadline)?;
+        }
+        match value {
+            // Counters are saved as their digits, so the format is unchanged
+            // and they load back as plain strings.
+            Value::String(_) | Value::Integer(_) => {
+                out.write_u8(TYPE_STRING)?;
+                write_bytes(&mut out, &entry.key)?;
+                write_bytes(&mut out, &value.string_bytes().unwrap())?;
+            }
+            Value::ZSet(zset) => {
+                out.write_u8(TYPE_ZSET)?;
+                write_bytes(&mut out, &entry.key)?;
+                out.write_u32::<LittleEndian>(zset.len() as u32)?;
+                for (member, score) in zset.iter() {
+                    out.write_f64::<LittleEndian>(score)?;
+                    write_bytes(&mut out, member)?;
+                }
+            }
+        }
+    }
+    out.write_u8(OP_EOF)?;
+    let checksum = out.hasher.clone().finalize();
+    out.inner.write_u32::<LittleEndian>(checksum)?;
+    out.flush()
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    out.write_all(bytes)
+}
+
+/// Restores the keys in `snapshot` into `data` and returns how many were
+/// loaded. Keys whose deadline passed while the server was down are skipped.
+pub fn read_snapshot(snapshot: &[u8], data: &mut Data) -> Result<usize> {
+    let Some(body_len) = snapshot.len().checked_sub(4) else {
+        bail!("snapshot is truncated");
+    };
+    let (body, mut checksum) = snapshot.split_at(body_len);
+    if crc32fast::hash(body) != checksum.read_u32::<LittleEndian>()? {
+        bail!("snapshot checksum mismatch");
+    }
+
+    let mut body = body;
+    let mut magic = [0; MAGIC.len()];
+    body.read_exact(&mut magic)
+        .context("snapshot is truncated")?;
+    if &magic != MAGIC {
+        bail!("not a snapshot file");
+    }
+    let version = body.read_u16::<LittleEndian>()?;
+    if version != VERSION {
+        bail!("unsupported snapshot version {}", version);
+    }
+
+    let now = now_ms();
+    let mut loaded = 0;
+    loop {
+        let mut opcode = body.read_u8().context("snapshot is truncated")?;
+        let mut deadline = None;
+        if opcode == OP_EXPIRE {
+            deadline = Some(body.read_u64::<LittleEndian>()?);
+            opcode = body.read_u8()?;
+        }
+        let (key, value) = match opcode {
+            OP_EOF => break,
+            TYPE_STRING => (
+                read_bytes(&mut body)?,
+                Value::String(read_bytes(&mut body)?),
+            ),
+            TYPE_ZSET => {
+                let key = read_bytes(&mut body)?;
+                let mut zset = ZSet::new();
+                for _ in 0..body.read_u32::<LittleEndian>()? {
+                    let score = body.read_f64::<LittleEndian>()?;
+                    zset.insert(&read_bytes(&mut body)?, score);
+                }
+                (key, Value::ZSet(Box::new(zset)))
+            }
+            opcode => bail!("unknown snapshot opcode {:#x}", opcode),
+        };
+        if deadline.is_some_and(|deadline| deadline <= now) {
+            continue;
+        }
+        data.lookup_or_insert(&key).value = Some(value);
+        data.set_expiry(&key, deadline);
+        loaded += 1;
+    }
+    if !body.is_empty() {
+        bail!("trailing bytes after the end of the snapshot");
+    }
+    Ok(loaded)
+}
+
+fn read_bytes(body: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = body.read_u32::<LittleEndian>()? as usize;
+    if body.len() < len {
+        bail!("snapshot is truncated");
+    }
+    let (bytes, rest) = body.split_at(len);
+    *body = rest;
+    Ok(bytes.to_vec())
+}
+
+/// Writes a snapshot of `data` to `path`. The snapshot goes to a temporary
+/// file that is synced and then renamed over `path`, so a crash mid-save
+/// leaves the previous snapshot intact.
+pub fn save(data: &Data, path: &Path) -> Result<()> {
+    let temp = temp_path(path, process::id());
+    let result = (|| -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        write_snapshot(data, &mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("failed to save snapshot to {}", path.display()))
+}
+
+/// The file the process `pid` writes a snapshot of `path` to.
+fn temp_path(path: &Path, pid: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".tmp-{}", pid));
+    path.with_file_name(name)
+}
+
+/// Loads the snapshot at `path` into `data`. A missing file is an empty
+/// keyspace; a corrupt one is an error.
+pub fn load(path: &Path, data: &mut Data) -> Result<usize> {
+    let snapshot = match fs::read(path) {
+        Ok(snapshot) => snapshot,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    read_snapshot(&snapshot, data)
+        .with_context(|| format!("failed to load snapshot from {}", path.display()))
+}
+
+/// Snapshot bookkeeping for SAVE, BGSAVE and LASTSAVE.
+pub struct Snapshots {
+    path: PathBuf,
+    last_save: u64,
+    child: Option<Child>,
+    /// Started once the AOF rewrite that is running finishes.
+    scheduled: bool,
+}
+
+impl Snapshots {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            last_save: now_ms() / 1000,
+            child: None,
+            scheduled: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Unix time in seconds of the last successful save, or of startup if
+    /// nothing has been saved yet.
+    pub fn last_save(&self) -> u64 {
+        self.last_save
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.child.is_some()
+    }
+
+    /// Asks for a background save once the AOF rewrite that is running has
+    /// finished, as the two are not run at the same time.
+    pub fn schedule(&mut self) {
+        self.scheduled = true;
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.scheduled
+    }
+
+    /// Saves in the foreground, blocking every client until it is done.
+    pub fn save(&mut self, data: &Data) -> Result<()> {
+        save(data, &self.path)?;
+        self.last_save = now_ms() / 1000;
+        Ok(())
+    }
+
+    /// Writes the snapshot from a forked child. Call `reap` from the event
+    /// loop to learn when it finishes.
+    pub fn background_save(&mut self, data: &Data) -> Result<()> {
+        let path = &self.path;
+        self.scheduled = false;
+        self.child = Some(fork::spawn("Background save", || save(data, path))?);
+        Ok(())
+    }
+
+    /// Kills a running background save and removes its partial file.
+    pub fn abort_background_save(&mut self) {
+        self.scheduled = false;
+        if let Some(child) = self.child.take() {
+            let temp = temp_path(&self.path, child.pid() as u32);
+            child.kill();
+            let _ = fs::remove_file(temp);
+        }
+    }
+
+    /// Collects a finished background save without blocking. Returns
+    /// whether it succeeded, or `None` if none has finished.
+    pub fn reap(&mut self) -> Option<bool> {
+        let success = self.child.as_ref()?.try_wait()?;
+        self.child = None;
+        if success {
+            self.last_save = now_ms() / 1000;
+        }
+        Some(success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_data() -> Data {
+        let mut data = Data::new();
+        data.lookup_or_insert(b"plain").value = Some(Value::String(b"value".to_vec()));
+        data.lookup_or_insert(b"ttl").value = Some(Value::String(Vec::new()));
+        data.set_expiry(b"ttl", Some(now_ms() + 60_000));
+        data.lookup_or_insert(b"stale").value = Some(Value::String(b"x".to_vec()));
+        data.set_expiry(b"stale", Some(now_ms() - 1));
+        data.lookup_or_insert(b"counter").value = Some(Value::Integer(-12));
+        let mut zset = ZSet::new();
+        zset.insert(b"a", 1.5);
+        zset.insert(b"b", f64::NEG_INFINITY);
+        data.lookup_or_insert(b"zset").value = Some(Value::ZSet(Box::new(zset)));
+        data
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut snapshot = Vec::new();
+        write_snapshot(&sample_data(), &mut snapshot).unwrap();
+
+        let mut data = Data::new();
+        assert_eq!(read_snapshot(&snapshot, &mut data).unwrap(), 4);
+        assert!(data.lookup(b"stale").is_none());
+        match &data.lookup(b"counter").unwrap().value {
+            Some(Value::String(string)) => assert_eq!(string, b"-12"),
+            _ => panic!("expected a string"),
+        }
+        assert!(data.lookup(b"plain").unwrap().expire_at().is_none());
+        assert!(data.lookup(b"ttl").unwrap().expire_at().is_some());
+        match &data.lookup(b"zset").unwrap().value {
+            Some(Value::ZSet(zset)) => {
+                let members: Vec<_> = zset.iter().collect();
+                assert_eq!(
+                    members,
+                    vec![(&b"b"[..], f64::NEG_INFINITY), (&b"a"[..], 1.5)]
+                );
+            }
+            _ => panic!("expected a sorted set"),
+        }
+    }
+
+    #[test]
+    fn test_corrupt_snapshot_is_rejected() {
+        let mut snapshot = Vec::new();
+        write_snapshot(&sample_data(), &mut snapshot).unwrap();
+
+        let mut flipped = snapshot.clone();
+        flipped[12] ^= 1;
+        assert!(read_snapshot(&flipped, &mut Data::new()).is_err());
+        assert!(read_snapshot(&snapshot[..snapshot.len() - 1], &mut Data::new()).is_err());
+        assert!(read_snapshot(&[], &mut Data::new()).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load_file() {
+        let dir = std::env::temp_dir().join(format!("crabcache-rdb-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+
+        let mut data = Data::new();
+        assert_eq!(load(&path, &mut data).unwrap(), 0);
+        save(&sample_data(), &path).unwrap();
+        assert_eq!(load(&path, &mut data).unwrap(), 4);
+        assert!(!temp_path(&path, process::id()).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
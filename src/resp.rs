@@ -0,0 +1,298 @@
+//! Request side of RESP, the protocol spoken by redis-cli and the Redis
+//! client libraries. Replies are encoded by `serialization::Output`.
+
+use crate::commands::{ProtocolError, RequestLimits};
+use anyhow::Result;
+
+/// Longest inline command line accepted before its line break arrives.
+const MAX_INLINE_LENGTH: usize = 64 * 1024;
+
+/// Whether a connection that sent `buf` first speaks RESP, or `None` if more
+/// bytes are needed to tell. Multibulk requests start with `*` and inline
+/// ones with a command name, but a native request's argument count can begin
+/// with the same byte (42 arguments is `*`). So an ambiguous start is only
+/// taken as native if its header is plausible: an argument count and a first
+/// argument length within `limits`. Read as a count, RESP text is always
+/// beyond them, since its fourth byte is printable or a line break.
+pub fn is_resp(buf: &[u8], limits: RequestLimits) -> Option<bool> {
+    let first = *buf.first()?;
+    if first != b'*' && !first.is_ascii_alphabetic() {
+        return Some(false);
+    }
+    let Some(count) = buf.get(..4) else {
+        // Only a RESP line is over before a native count is.
+        return buf.contains(&b'\n').then_some(true);
+    };
+    let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+    if count > limits.max_arguments {
+        return Some(true);
+    }
+    let length = u32::from_le_bytes(buf.get(4..8)?.try_into().unwrap()) as usize;
+    Some(length == 0 || length > limits.max_bulk_len)
+}
+
+/// Parses RESP requests out of a connection's read buffer. A multibulk
+/// request that has only partly arrived is kept between reads with the
+/// arguments parsed so far, so a large one is scanned and copied once
+/// however many reads it takes.
+#[derive(Debug, Default)]
+pub struct Parser {
+    pending: Option<Multibulk>,
+}
+
+#[derive(Debug)]
+struct Multibulk {
+    args: Vec<Vec<u8>>,
+    remaining: usize,
+}
+
+impl Parser {
+    /// Parses the request at the start of `buf` into its arguments, or as
+    /// far into it as has arrived. Returns the arguments once the request is
+    /// complete, and the number of bytes used either way; those bytes are
+    /// not to be passed in again. An empty request such as a blank inline
+    /// line yields no arguments and should be skipped.
+    pub fn parse(
+        &mut self,
+        buf: &[u8],
+        limits: RequestLimits,
+    ) -> Result<(Option<Vec<Vec<u8>>>, usize)> {
+        let result = self.parse_request(buf, limits);
+        if result.is_err() {
+            self.pending = None;
+        }
+        result
+    }
+
+    /// Forgets the request in progress, for when the bytes after it are
+    /// thrown away.
+    pub fn reset(&mut self) {
+        self.pending = None;
+    }
+
+    fn parse_request(
+        &mut self,
+        buf: &[u8],
+        limits: RequestLimits,
+    ) -> Result<(Option<Vec<Vec<u8>>>, usize)> {
+        let mut pos = 0;
+        if self.pending.is_none() {
+            match buf.first() {
+                None => return Ok((None, 0)),
+                Some(b'*') => {}
+                Some(_) => {
+                    return Ok(match parse_inline(buf)? {
+                        Some((args, used)) => (Some(args), used),
+                        None => (None, 0),
+                    })
+                }
+            }
+            let Some((count, next)) = read_line(buf, 0)? else {
+                return Ok((None, 0));
+            };
+            let count = parse_length(&count[1..], "invalid multibulk length")?;
+            if count > limits.max_arguments as i64 {
+                return Err(ProtocolError::Resp("invalid multibulk length").into());
+            }
+            let count = count.max(0) as usize;
+            self.pending = Some(Multibulk {
+                // Each argument takes at least a 4-byte header, so what is
+                // buffered bounds how many can be expected soon.
+                args: Vec::with_capacity(count.min(buf.len() / 4)),
+                remaining: count,
+            });
+            pos = next;
+        }
+        let multibulk = self.pending.as_mut().unwrap();
+        while multibulk.remaining > 0 {
+            let Some((header, next)) = read_line(buf, pos)? else {
+                return Ok((None, pos));
+            };
+            if header.first() != Some(&b'$') {
+                return Err(ProtocolError::Resp("expected '$'").into());
+            }
+            let len = parse_length(&header[1..], "invalid bulk length")?;
+            if len < 0 {
+                return Err(ProtocolError::Resp("invalid bulk length").into());
+            }
+            if len as usize > limits.max_bulk_len {
+                return Err(ProtocolError::ArgumentTooLarge {
+                    limit: limits.max_bulk_len,
+                }
+                .into());
+            }
+            let end = next + len as usize;
+            if buf.len() < end + 2 {
+                return Ok((None, pos));
+            }
+            if &buf[end..end + 2] != b"\r\n" {
+                return Err(ProtocolError::Resp("expected CRLF after bulk string").into());
+            }
+            multibulk.args.push(buf[next..end].to_vec());
+            multibulk.remaining -= 1;
+            pos = end + 2;
+        }
+        let args = self.pending.take().unwrap().args;
+        Ok((Some(args), pos))
+    }
+}
+
+fn parse_inline(buf: &[u8]) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+    let Some(newline) = buf.iter().position(|b| *b == b'\n') else {
+        if buf.len() > MAX_INLINE_LENGTH {
+            return Err(ProtocolError::Resp("too big inline request").into());
+        }
+        return Ok(None);
+    };
+    let args = buf[..newline]
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect();
+    Ok(Some((args, newline + 1)))
+}
+
+/// The line starting at `pos` without its CRLF, and the position after it.
+fn read_line(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>> {
+    let rest = &buf[pos..];
+    match rest.windows(2).position(|pair| pair == b"\r\n") {
+        Some(end) => Ok(Some((&rest[..end], pos + end + 2))),
+        None if rest.len() > MAX_INLINE_LENGTH => {
+            Err(ProtocolError::Resp("too big length line").into())
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_length(digits: &[u8], error: &'static str) -> Result<i64> {
+    Ok(std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or(ProtocolError::Resp(error))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_bulk_len: usize) -> RequestLimits {
+        RequestLimits {
+            max_bulk_len,
+            max_arguments: 2,
+        }
+    }
+
+    /// Parses a request that has to be complete in `buf`.
+    fn parse_request(buf: &[u8], limits: RequestLimits) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+        let (args, used) = Parser::default().parse(buf, limits)?;
+        Ok(args.map(|args| (args, used)))
+    }
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_is_resp() {
+        let limits = RequestLimits::default();
+        assert_eq!(is_resp(b"", limits), None);
+        assert_eq!(is_resp(b"\x02\x00", limits), Some(false));
+        assert_eq!(is_resp(b"*1", limits), None);
+        assert_eq!(is_resp(b"*1\r\n", limits), Some(true));
+        assert_eq!(is_resp(b"a\n", limits), Some(true));
+        assert_eq!(is_resp(b"PING", limits), Some(true));
+        // 42 and 65 native arguments start with `*` and `A`.
+        for count in [42u32, 65] {
+            let mut header = count.to_le_bytes().to_vec();
+            assert_eq!(is_resp(&header, limits), None);
+            header.extend_from_slice(&3u32.to_le_bytes());
+            assert_eq!(is_resp(&header, limits), Some(false));
+        }
+        assert_eq!(
+            is_resp(b"*\x00\x00\x00\x00\x00\x00\x00", limits),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_parse_multibulk() {
+        let request = b"*2\r\n$3\r\nGET\r\n$5\r\na b\r\n\r\n*1";
+        let (parsed, used) = parse_request(request, limits(1024)).unwrap().unwrap();
+        assert_eq!(parsed, args(&["GET", "a b\r\n"]));
+        assert_eq!(&request[used..], b"*1");
+        for end in 0..used {
+            assert_eq!(parse_request(&request[..end], limits(1024)).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_multibulk_across_reads() {
+        let request = b"*2\r\n$3\r\nGET\r\n$10\r\n0123456789\r\n";
+        let mut parser = Parser::default();
+        let mut buf = Vec::new();
+        let mut parsed = None;
+        for (index, byte) in request.iter().enumerate() {
+            buf.push(*byte);
+            let (complete, used) = parser.parse(&buf, limits(1024)).unwrap();
+            // Arguments are taken out of the buffer as soon as they are
+            // complete, so none is scanned twice.
+            buf.drain(..used);
+            if complete.is_some() {
+                assert_eq!(index, request.len() - 1);
+                parsed = complete;
+            }
+        }
+        assert_eq!(parsed, Some(args(&["GET", "0123456789"])));
+        assert!(buf.is_empty());
+
+        // A request that fails to parse is not resumed.
+        let partial = parser.parse(b"*2\r\n$3\r\nGET\r\n", limits(1024));
+        assert_eq!(partial.unwrap(), (None, 13));
+        assert!(parser.parse(b":1\r\n", limits(1024)).is_err());
+        assert_eq!(
+            parser.parse(b"PING\r\n", limits(1024)).unwrap(),
+            (Some(args(&["PING"])), 6)
+        );
+    }
+
+    #[test]
+    fn test_parse_inline() {
+        let (parsed, used) = parse_request(b"get  key\r\nPING", limits(1024))
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed, args(&["get", "key"]));
+        assert_eq!(used, 10);
+        assert_eq!(parse_request(b"PING", limits(1024)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |request: &[u8]| {
+            parse_request(request, limits(16))
+                .unwrap_err()
+                .downcast::<ProtocolError>()
+                .unwrap()
+        };
+        assert_eq!(
+            error(b"*x\r\n"),
+            ProtocolError::Resp("invalid multibulk length")
+        );
+        assert_eq!(
+            error(b"*3\r\n"),
+            ProtocolError::Resp("invalid multibulk length")
+        );
+        assert_eq!(error(b"*1\r\n:1\r\n"), ProtocolError::Resp("expected '$'"));
+        assert_eq!(
+            error(b"*1\r\n$3\r\nabcd\r\n"),
+            ProtocolError::Resp("expected CRLF after bulk string")
+        );
+        assert_eq!(
+            error(b"*1\r\n$100\r\n"),
+            ProtocolError::ArgumentTooLarge { limit: 16 }
+        );
+        // Arguments that fit are fine however long the request gets.
+        let request = b"*2\r\n$10\r\n0123456789\r\n$10\r\n0123456789\r\n";
+        let (parsed, _) = parse_request(request, limits(16)).unwrap().unwrap();
+        assert_eq!(parsed.len(), 2);
+    }
+}
@@ -1,49 +1,74 @@
-use std::fmt::Display;
 use crate::hashtable::{HashNode, HashTable};
+use std::{fmt::Display, ptr::NonNull};
 
-const LOAD_FACTOR: usize = 8;
+/// Average chain length at which the table doubles.
+pub const DEFAULT_LOAD_FACTOR: usize = 8;
+const RESIZING_WORK: usize = 128;
 
+/// Hash map that grows by migrating nodes from the old table to the new one
+/// a few at a time on each operation instead of rehashing all at once.
 pub struct ScalableHashMap {
     table1: Option<HashTable>,
     table2: Option<HashTable>,
+    resizing_pos: usize,
+    load_factor: usize,
 }
 
 impl Display for ScalableHashMap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ScalableHashMap {{\n")?;
-        write!(f, " table1: {:#?}\n", &self.table1)?;
-        write!(f, " table2: {:#?}\n", &self.table2)?;
-        write!(f, " }}\n")
+        writeln!(f, "ScalableHashMap {{")?;
+        writeln!(f, " table1: {:#?}", &self.table1)?;
+        writeln!(f, " table2: {:#?}", &self.table2)?;
+        writeln!(f, " }}")
+    }
+}
+
+impl Default for ScalableHashMap {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl ScalableHashMap {
     pub fn new() -> ScalableHashMap {
+        Self::with_load_factor(DEFAULT_LOAD_FACTOR)
+    }
+
+    /// A map that grows once its chains average `load_factor` nodes.
+    pub fn with_load_factor(load_factor: usize) -> ScalableHashMap {
         ScalableHashMap {
             table1: Some(HashTable::new(4).unwrap()),
             table2: None,
+            resizing_pos: 0,
+            load_factor: load_factor.max(1),
         }
     }
 
     pub fn start_resizing(&mut self) {
         assert!(self.table2.is_none());
-        let capacity = self.table1.as_ref().map_or(4, |t| (t.mask() + 1) * 2);
+        let capacity = self.table1.as_ref().map_or(4, |t| t.capacity() * 2);
         self.table2 = self.table1.take();
         self.table1 = Some(HashTable::new(capacity).unwrap());
-        self.table1.as_mut().unwrap().resize(capacity);
+        self.resizing_pos = 0;
     }
 
     fn help_resizing(&mut self) {
-        if self.table2.is_none() {
+        let (Some(noble), Some(substitute)) = (&mut self.table2, &mut self.table1) else {
             return;
+        };
+        let mut work = 0;
+        while work < RESIZING_WORK && !noble.is_empty() {
+            match noble.detach_head(self.resizing_pos) {
+                Some(mut node) => {
+                    substitute.insert(unsafe { node.as_mut() });
+                    work += 1;
+                }
+                None => self.resizing_pos += 1,
+            }
         }
-        if let (Some(noble), Some(substitute)) = (&mut self.table2, &mut self.table1) {
-            noble.drain(..noble.size()).for_each(|mut item| {
-                substitute.insert(&mut item);
-            });
-        }
-        if self.table2.as_ref().map_or(0, |t| t.size()) == 0 {
+        if noble.is_empty() {
             self.table2 = None;
+            self.resizing_pos = 0;
         }
     }
 
@@ -58,6 +83,7 @@ impl ScalableHashMap {
             .and_then(|t| t.lookup(key, cmp))
             .or_else(|| self.table2.as_ref().and_then(|t| t.lookup(key, cmp)))
     }
+
     pub fn lookup_mut(
         &mut self,
         key: &HashNode,
@@ -70,53 +96,41 @@ impl ScalableHashMap {
             .or_else(|| self.table2.as_mut().and_then(|t| t.lookup_mut(key, cmp)))
     }
 
+    /// Links `node` into the map. The node must stay alive and must not move
+    /// until it has been popped again.
     pub fn insert(&mut self, node: &mut HashNode) {
-        if let Some(table) = self.table1.as_mut() {
-            table.insert(node)
-        } else {
-            self.table1 = Some(HashTable::new(4).unwrap());
-        }
+        self.table1
+            .get_or_insert_with(|| HashTable::new(4).unwrap())
+            .insert(node);
         if self.table2.is_none() {
-            let length = self.table1.as_ref().unwrap().size();
-            let mask = self.table1.as_ref().unwrap().mask() + 1;
-            let loaded = length / mask;
-            if loaded >= LOAD_FACTOR {
+            let table = self.table1.as_ref().unwrap();
+            let loaded = table.size() / table.capacity();
+            if loaded >= self.load_factor {
                 self.start_resizing();
             }
         }
         self.help_resizing()
     }
 
-    fn pop_from_table(
-        table: &mut Option<HashTable>,
-        node: &mut HashNode,
-        cmp: fn(&HashNode, &HashNode) -> bool,
-    ) -> Option<HashNode> {
-        table.as_mut().and_then(|t| {
-            if t.lookup(node, cmp).is_some() {
-                t.detach(node)
-            } else {
-                None
-            }
-        })
-    }
-
     pub fn pop(
         &mut self,
-        node: &mut HashNode,
+        key: &HashNode,
         cmp: fn(&HashNode, &HashNode) -> bool,
-    ) -> Option<HashNode> {
+    ) -> Option<NonNull<HashNode>> {
         self.help_resizing();
-        let mut table1 = self.table1.take();
-        let found = Self::pop_from_table(&mut table1, node, cmp);
-        self.table1 = table1;
-        if found.is_none() {
-            let mut table2 = self.table2.take();
-            let found = Self::pop_from_table(&mut table2, node, cmp);
-            self.table2 = table2;
-            return found;
-        }
-        return found;
+        self.table1
+            .as_mut()
+            .and_then(|t| t.detach(key, cmp))
+            .or_else(|| self.table2.as_mut().and_then(|t| t.detach(key, cmp)))
+    }
+
+    /// Iterates over every node in the map, including those that have not
+    /// been migrated to the new table yet.
+    pub fn nodes(&self) -> impl Iterator<Item = &HashNode> + '_ {
+        self.table1
+            .iter()
+            .chain(self.table2.iter())
+            .flat_map(|t| t.nodes())
     }
 
     pub fn size(&self) -> usize {
@@ -128,6 +142,7 @@ impl ScalableHashMap {
     pub fn destroy(&mut self) {
         self.table1 = None;
         self.table2 = None;
+        self.resizing_pos = 0;
     }
 }
 
@@ -138,7 +153,7 @@ mod tests {
     fn generate_node_list(n: usize) -> Vec<HashNode> {
         let mut list = Vec::new();
         for i in 0..n {
-            list.push(HashNode::new(None, i.try_into().unwrap()));
+            list.push(HashNode::new(i.try_into().unwrap()));
         }
         list
     }
@@ -175,8 +190,8 @@ mod tests {
         for node in &nodes_for_search {
             assert_eq!(map.lookup(node, |a, b| a == b), Some(node));
         }
-        assert_eq!(map.lookup(&HashNode::new(None, 123), |a, b| a == b), None);
-        assert_eq!(map.lookup(&HashNode::new(None, 101), |a, b| a == b), None);
+        assert_eq!(map.lookup(&HashNode::new(123), |a, b| a == b), None);
+        assert_eq!(map.lookup(&HashNode::new(101), |a, b| a == b), None);
     }
 
     #[test]
@@ -189,11 +204,16 @@ mod tests {
         for node in nodes.iter_mut() {
             map.insert(node);
         }
-        for mut node in nodes_for_search {
-            assert_eq!(map.pop(&mut node, |a, b| a == b), Some(node));
+        for node in nodes_for_search {
+            let popped = map.pop(&node, |a, b| a == b);
+            assert_eq!(
+                popped.map(|n| unsafe { n.as_ref() }.code()),
+                Some(node.code())
+            );
         }
-        assert_eq!(map.pop(&mut HashNode::new(None, 123), |a, b| a == b), None);
-        assert_eq!(map.pop(&mut HashNode::new(None, 101), |a, b| a == b), None);
+        assert_eq!(map.size(), 0);
+        assert_eq!(map.pop(&HashNode::new(123), |a, b| a == b), None);
+        assert_eq!(map.pop(&HashNode::new(101), |a, b| a == b), None);
     }
 
     #[test]
@@ -206,7 +226,7 @@ mod tests {
         }
         map.start_resizing();
         assert_eq!(map.size(), 3);
-        let node = HashNode::new(None, 2);
+        let node = HashNode::new(2);
         assert_eq!(map.lookup(&node, |a, b| a == b), Some(&node));
     }
 
@@ -220,10 +240,26 @@ mod tests {
         }
         map.start_resizing();
         assert_eq!(map.size(), 3);
-        let node = HashNode::new(None, 2);
+        let node = HashNode::new(2);
         assert_eq!(map.lookup(&node, |a, b| a == b), Some(&node));
         map.help_resizing();
         assert_eq!(map.size(), 3);
         assert!(map.table2.is_none());
     }
+
+    #[test]
+    fn test_incremental_resizing() {
+        let mut map = ScalableHashMap::new();
+        let node_number = 10_000;
+        let mut nodes = generate_node_list(node_number);
+        for node in nodes.iter_mut() {
+            map.insert(node);
+        }
+        assert_eq!(map.size(), node_number);
+        assert_eq!(map.nodes().count(), node_number);
+        for i in 0..node_number {
+            let node = HashNode::new(i as u64);
+            assert_eq!(map.lookup(&node, |a, b| a == b), Some(&node));
+        }
+    }
 }
@@ -1,5 +1,4 @@
-use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
-use std::io::Write;
+use byteorder::{LittleEndian, WriteBytesExt};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SerializationType {
@@ -32,33 +31,204 @@ impl SerializationType {
     }
 }
 
-pub fn response_nil(out: &mut Vec<u8>) {
-    out.push(SerializationType::Null.as_num());
+/// Error codes sent with `response_err`. The numbers are part of the wire
+/// protocol and must not change once assigned. They take the place of the
+/// word (`ERR`, `WRONGTYPE`, ...) RESP errors start with, so native error
+/// messages are plain text without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Unknown,
+    TooBig,
+    Type,
+    Arg,
+    Protocol,
+    Internal,
+    Busy,
+}
+
+impl ErrorCode {
+    pub fn as_num(&self) -> u32 {
+        match self {
+            ErrorCode::Unknown => 1,
+            ErrorCode::TooBig => 2,
+            ErrorCode::Type => 3,
+            ErrorCode::Arg => 4,
+            ErrorCode::Protocol => 5,
+            ErrorCode::Internal => 6,
+            ErrorCode::Busy => 7,
+        }
+    }
+}
+
+/// The wire protocol a connection speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The native little-endian, length-prefixed format.
+    Binary,
+    Resp2,
+    Resp3,
 }
 
-pub fn response_err(out: &mut Vec<u8>, code: u32, message: &str) {
-    out.push(SerializationType::Err.as_num());
-    out.write_u32::<LittleEndian>(code).unwrap();
+impl Protocol {
+    pub fn is_resp(&self) -> bool {
+        *self != Protocol::Binary
+    }
+}
+
+/// A reply under construction, encoded for the protocol of the client it
+/// is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    protocol: Protocol,
+    buf: Vec<u8>,
+}
+
+impl Output {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    fn resp_line(&mut self, prefix: u8, line: &[u8]) {
+        self.buf.push(prefix);
+        self.buf.extend_from_slice(line);
+        self.buf.extend_from_slice(b"\r\n");
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new(Protocol::Binary)
+    }
+}
+
+pub fn response_nil(out: &mut Output) {
+    match out.protocol {
+        Protocol::Binary => out.buf.push(SerializationType::Null.as_num()),
+        Protocol::Resp2 => out.resp_line(b'$', b"-1"),
+        Protocol::Resp3 => out.resp_line(b'_', b""),
+    }
+}
+
+/// An error reply: `code` and `message` natively, `-ERR message` in RESP.
+pub fn response_err(out: &mut Output, code: u32, message: &str) {
+    response_prefixed_err(out, code, "ERR", message);
+}
+
+/// An error reply whose RESP form starts with `prefix`, an upper-case word
+/// such as `WRONGTYPE` that Redis clients match on, instead of `ERR`. Native
+/// replies carry the numeric `code` for that and get the message alone.
+pub fn response_prefixed_err(out: &mut Output, code: u32, prefix: &str, message: &str) {
+    if out.protocol.is_resp() {
+        // Errors are a single line; keep stray line breaks from ending them early.
+        let line = format!("{} {}", prefix, message).replace(['\r', '\n'], " ");
+        out.resp_line(b'-', line.as_bytes());
+        return;
+    }
+    out.buf.push(SerializationType::Err.as_num());
+    out.buf.write_u32::<LittleEndian>(code).unwrap();
     let len = message.len() as u32;
-    out.write_u32::<LittleEndian>(len).unwrap();
-    out.extend_from_slice(message.as_bytes());
+    out.buf.write_u32::<LittleEndian>(len).unwrap();
+    out.buf.extend_from_slice(message.as_bytes());
 }
 
-pub fn response_integer(out: &mut Vec<u8>, value: i64) {
-    out.push(SerializationType::Integer.as_num());
-    out.write_i64::<LittleEndian>(value).unwrap();
+pub fn response_integer(out: &mut Output, value: i64) {
+    if out.protocol.is_resp() {
+        out.resp_line(b':', value.to_string().as_bytes());
+        return;
+    }
+    out.buf.push(SerializationType::Integer.as_num());
+    out.buf.write_i64::<LittleEndian>(value).unwrap();
 }
 
-pub fn response_string(out: &mut Vec<u8>, value: &[u8]) {
-    out.push(SerializationType::String.as_num());
+pub fn response_string(out: &mut Output, value: &[u8]) {
+    if out.protocol.is_resp() {
+        out.resp_line(b'$', value.len().to_string().as_bytes());
+        out.buf.extend_from_slice(value);
+        out.buf.extend_from_slice(b"\r\n");
+        return;
+    }
+    out.buf.push(SerializationType::String.as_num());
     let len = value.len() as u32;
-    out.write_u32::<LittleEndian>(len).unwrap();
-    out.extend_from_slice(value);
+    out.buf.write_u32::<LittleEndian>(len).unwrap();
+    out.buf.extend_from_slice(value);
+}
+
+pub fn response_array(out: &mut Output, n: u32) {
+    if out.protocol.is_resp() {
+        out.resp_line(b'*', n.to_string().as_bytes());
+        return;
+    }
+    out.buf.push(SerializationType::Array.as_num());
+    out.buf.write_u32::<LittleEndian>(n).unwrap();
+}
+
+/// A short status such as `OK`: a simple string in RESP, a string otherwise.
+pub fn response_status(out: &mut Output, status: &str) {
+    if out.protocol.is_resp() {
+        out.resp_line(b'+', status.as_bytes());
+    } else {
+        response_string(out, status.as_bytes());
+    }
+}
+
+/// Acknowledges a write. The binary protocol has always answered writes
+/// with a nil, so it keeps doing so; RESP clients expect `+OK`.
+pub fn response_ok(out: &mut Output) {
+    match out.protocol {
+        Protocol::Binary => response_nil(out),
+        _ => response_status(out, "OK"),
+    }
+}
+
+/// Reports a missing key. The binary protocol has always sent the string
+/// `not found` for this; RESP clients get a nil.
+pub fn response_not_found(out: &mut Output) {
+    match out.protocol {
+        Protocol::Binary => response_string(out, b"not found"),
+        _ => response_nil(out),
+    }
 }
 
-pub fn response_array(out: &mut Vec<u8>, n: u32) {
-    out.push(SerializationType::Array.as_num());
-    out.write_u32::<LittleEndian>(n).unwrap();
+/// A floating point value: a native double in RESP3, a string otherwise.
+pub fn response_double(out: &mut Output, value: f64) {
+    let formatted = crate::commands::format_float(value);
+    match out.protocol {
+        Protocol::Resp3 => out.resp_line(b',', formatted.as_bytes()),
+        _ => response_string(out, formatted.as_bytes()),
+    }
+}
+
+/// The header of a map with `n` key/value pairs. Protocols without maps get
+/// a flat array of `2 * n` items.
+pub fn response_map(out: &mut Output, n: u32) {
+    match out.protocol {
+        Protocol::Resp3 => out.resp_line(b'%', n.to_string().as_bytes()),
+        _ => response_array(out, n * 2),
+    }
+}
+
+/// The header of a set with `n` members: a native set in RESP3, an array
+/// otherwise.
+pub fn response_set(out: &mut Output, n: u32) {
+    match out.protocol {
+        Protocol::Resp3 => out.resp_line(b'~', n.to_string().as_bytes()),
+        _ => response_array(out, n),
+    }
 }
 
 #[cfg(test)]
@@ -67,16 +237,16 @@ mod tests {
 
     #[test]
     fn test_response_nil() {
-        let mut out = Vec::new();
+        let mut out = Output::default();
         response_nil(&mut out);
-        assert_eq!(out, vec![0]);
+        assert_eq!(out.as_bytes(), vec![0]);
     }
 
     #[test]
     fn test_response_err() {
-        let mut out = Vec::new();
+        let mut out = Output::default();
         response_err(&mut out, 123, "Error message");
-        assert_eq!(out.len(), 22);
+        assert_eq!(out.as_bytes().len(), 22);
 
         let expected = vec![
             SerializationType::Err.as_num(),
@@ -102,14 +272,19 @@ mod tests {
             b'g',
             b'e',
         ];
-        assert_eq!(out, expected);
+        assert_eq!(out.as_bytes(), expected);
+
+        // The RESP prefix is not part of the native message.
+        let mut out = Output::default();
+        response_prefixed_err(&mut out, 3, "WRONGTYPE", "wrong kind");
+        assert_eq!(&out.as_bytes()[9..], b"wrong kind");
     }
 
     #[test]
     fn test_response_integer() {
-        let mut out = Vec::new();
+        let mut out = Output::default();
         response_integer(&mut out, 123456789);
-        assert_eq!(out.len(), 9);
+        assert_eq!(out.as_bytes().len(), 9);
 
         let expected = vec![
             SerializationType::Integer.as_num(),
@@ -122,6 +297,44 @@ mod tests {
             0x00,
             0x00,
         ];
-        assert_eq!(out, expected);
+        assert_eq!(out.as_bytes(), expected);
+    }
+
+    #[test]
+    fn test_resp2_encoding() {
+        let mut out = Output::new(Protocol::Resp2);
+        response_array(&mut out, 5);
+        response_string(&mut out, b"a\r\nb");
+        response_integer(&mut out, -7);
+        response_nil(&mut out);
+        response_double(&mut out, 1.5);
+        response_ok(&mut out);
+        response_err(&mut out, ErrorCode::Arg.as_num(), "syntax error");
+        response_prefixed_err(
+            &mut out,
+            ErrorCode::Type.as_num(),
+            "WRONGTYPE",
+            "wrong kind",
+        );
+        assert_eq!(
+            out.as_bytes(),
+            &b"*5\r\n$4\r\na\r\nb\r\n:-7\r\n$-1\r\n$3\r\n1.5\r\n+OK\r\n-ERR syntax error\r\n-WRONGTYPE wrong kind\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_resp3_encoding() {
+        let mut out = Output::new(Protocol::Resp3);
+        response_map(&mut out, 1);
+        response_string(&mut out, b"proto");
+        response_integer(&mut out, 3);
+        response_nil(&mut out);
+        response_double(&mut out, f64::NEG_INFINITY);
+        response_set(&mut out, 1);
+        response_status(&mut out, "write");
+        assert_eq!(
+            out.as_bytes(),
+            &b"%1\r\n$5\r\nproto\r\n:3\r\n_\r\n,-inf\r\n~1\r\n+write\r\n"[..]
+        );
     }
 }
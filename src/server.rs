@@ -0,0 +1,1042 @@
+//! The server itself: listeners, the event loop and command dispatch. The
+//! `crabcache` binary is a thin command line over this, and other programs
+//! can run a server in-process the same way:
+//!
+//! ```no_run
+//! let server = crabcache::Server::builder().port(0).build()?.spawn()?;
+//! println!("listening on {}", server.addr());
+//! // ...
+//! server.stop()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::aof::{self, Aof};
+use crate::commands::shutdown::{Options, SaveMode};
+use crate::commands::table::{self, Handler};
+use crate::commands::{Command, ProtocolError, Request};
+use crate::config::Config;
+use crate::connection::{ClientInfo, Connection, ConnectionState::*};
+use crate::entry::{now_ms, Data};
+use crate::io_threads::{IoThreads, Task};
+use crate::listener::{self, Listener, Stream};
+use crate::rdb::{self, Snapshots};
+use crate::serialization::{response_err, ErrorCode, Output};
+use crate::signals::{Signal, Signals};
+use crate::stats::Stats;
+use anyhow::{anyhow, Context as _, Result};
+use log::{error, info, warn};
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+pub(crate) mod handlers;
+
+/// Upper bound on keys reclaimed per event loop iteration, so a burst of
+/// simultaneous deadlines cannot stall request handling.
+const MAX_EXPIRED_PER_TICK: usize = 2000;
+/// How often a running background save is checked for completion.
+const BACKGROUND_SAVE_POLL: Duration = Duration::from_millis(100);
+/// How often a pending shutdown checks whether replies have drained.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(10);
+/// How often connections are checked against the idle timeout.
+const CLIENT_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a client turned away by `maxclients` has to send something
+/// before it is closed without being told why.
+const REJECTED_CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to stop accepting after accept fails, as it does when the
+/// process is out of file descriptors, before trying again.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// How many clients turned away by `maxclients` can wait at once to be told
+/// why; any more are closed straight away.
+const MAX_REJECTED_CLIENTS: usize = 64;
+/// Readiness of the signal pipe; listeners and connections count up from 0.
+const SIGNALS: Token = Token(usize::MAX);
+/// Wakes the event loop when a `ShutdownHandle` is used.
+const WAKER: Token = Token(usize::MAX - 1);
+
+/// Open connections by token. The one being served is taken out while its
+/// event is handled, so commands can reach the rest.
+type Connections = HashMap<Token, Connection<Stream>>;
+
+/// State shared by every connection.
+struct State {
+    config: Config,
+    db: Data,
+    snapshots: Snapshots,
+    aof: Option<Aof>,
+    shutdown: Option<Shutdown>,
+    stats: Stats,
+}
+
+/// A shutdown waiting for pending replies to be flushed.
+struct Shutdown {
+    options: Options,
+    deadline: Instant,
+    /// The client that sent SHUTDOWN, which is told if it does not happen.
+    client: Option<Token>,
+    aborted: bool,
+}
+
+impl Shutdown {
+    fn new(options: Options, timeout: Duration, client: Option<Token>) -> Shutdown {
+        Shutdown {
+            options,
+            deadline: Instant::now() + timeout,
+            client,
+            aborted: false,
+        }
+    }
+}
+
+/// Settings for a `Server`, starting from the defaults of `Config`.
+pub struct Builder {
+    config: Config,
+    handle_signals: bool,
+}
+
+impl Builder {
+    /// Replaces every setting with those in `config`.
+    pub fn config(mut self, config: Config) -> Builder {
+        self.config = config;
+        self
+    }
+
+    pub fn bind(mut self, addrs: impl IntoIterator<Item = IpAddr>) -> Builder {
+        self.config.bind = addrs.into_iter().collect();
+        self
+    }
+
+    /// The TCP port to listen on; 0 picks a free one, which
+    /// `Server::local_addrs` then reports.
+    pub fn port(mut self, port: u16) -> Builder {
+        self.config.port = port;
+        self
+    }
+
+    pub fn unixsocket(mut self, path: impl Into<PathBuf>) -> Builder {
+        self.config.unixsocket = Some(path.into());
+        self
+    }
+
+    /// The directory the snapshot and the append only file live in.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Builder {
+        self.config.dir = dir.into();
+        self
+    }
+
+    pub fn appendonly(mut self, appendonly: bool) -> Builder {
+        self.config.appendonly = appendonly;
+        self
+    }
+
+    pub fn io_threads(mut self, threads: usize) -> Builder {
+        self.config.io_threads = threads;
+        self
+    }
+
+    pub fn maxclients(mut self, maxclients: usize) -> Builder {
+        self.config.maxclients = maxclients;
+        self
+    }
+
+    /// Shut down on SIGINT and SIGTERM, as the binary does. Off by default,
+    /// as the handlers are process-wide and only one server can have them.
+    pub fn handle_signals(mut self, handle_signals: bool) -> Builder {
+        self.handle_signals = handle_signals;
+        self
+    }
+
+    /// Binds the listeners and loads the keyspace from disk, ready to `run`.
+    pub fn build(self) -> Result<Server> {
+        let config = self.config;
+        let poll = Poll::new()?;
+        let mut files = RunFiles::default();
+        // Listeners take the first tokens; connections are numbered after them.
+        let mut listeners = Vec::new();
+        let mut local_addrs = Vec::new();
+        for addr in config.listen_addrs() {
+            let listener = listener::bind_tcp(addr)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            let addr = listener.local_addr()?;
+            info!("Server started on {}", addr);
+            local_addrs.push(addr);
+            listeners.push(Listener::Tcp(listener));
+        }
+        if let Some(path) = &config.unixsocket {
+            let listener = listener::bind_unix(path, config.unixsocketperm)
+                .with_context(|| format!("failed to listen on {}", path.display()))?;
+            info!("Server started on {}", path.display());
+            files.0.push(path.clone());
+            listeners.push(Listener::Unix(listener));
+        }
+        for (index, listener) in listeners.iter_mut().enumerate() {
+            poll.registry()
+                .register(listener, Token(index), Interest::READABLE)?;
+        }
+        let signals = match self.handle_signals {
+            true => {
+                let mut signals = Signals::install()?;
+                poll.registry()
+                    .register(&mut signals, SIGNALS, Interest::READABLE)?;
+                Some(signals)
+            }
+            false => None,
+        };
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let io_threads = IoThreads::new(config.io_threads)?;
+        let aof = match config.appendonly {
+            true => Some(Aof::open(config.aof_path(), config.appendfsync)?),
+            false => None,
+        };
+        let mut state = State {
+            db: Data::with_load_factor(config.hash_load_factor),
+            snapshots: Snapshots::new(config.rdb_path()),
+            aof,
+            shutdown: None,
+            stats: Stats::default(),
+            config,
+        };
+        if let Some(path) = &state.config.pidfile {
+            fs::write(path, format!("{}\n", process::id()))
+                .with_context(|| format!("failed to write pidfile {}", path.display()))?;
+            files.0.push(path.clone());
+        }
+        // The log is at least as recent as the last snapshot, so it wins.
+        if let Some(aof) = &state.aof {
+            let replayed = aof::load(aof.path(), &mut state.db)?;
+            info!("Replayed {} writes from {}", replayed, aof.path().display());
+        } else {
+            let loaded = rdb::load(state.snapshots.path(), &mut state.db)?;
+            info!(
+                "Loaded {} keys from {}",
+                loaded,
+                state.snapshots.path().display()
+            );
+        }
+        Ok(Server {
+            next_token: Token(listeners.len()),
+            state,
+            poll,
+            listeners,
+            local_addrs,
+            signals,
+            waker,
+            shutdown_requested: Arc::new(AtomicBool::new(false)),
+            io_threads,
+            files,
+        })
+    }
+}
+
+/// A bound server, ready to serve clients with `run`.
+pub struct Server {
+    state: State,
+    poll: Poll,
+    listeners: Vec<Listener>,
+    local_addrs: Vec<SocketAddr>,
+    signals: Option<Signals>,
+    waker: Arc<Waker>,
+    shutdown_requested: Arc<AtomicBool>,
+    io_threads: IoThreads,
+    next_token: Token,
+    files: RunFiles,
+}
+
+/// Files that exist for as long as the server does: the Unix socket and
+/// the pidfile. They are removed on drop, so whichever way the server stops,
+/// by a shutdown, an error or being dropped unrun, they do not outlive it.
+#[derive(Default)]
+struct RunFiles(Vec<PathBuf>);
+
+impl Drop for RunFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Asks a running server to shut down, from any thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    waker: Arc<Waker>,
+    requested: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Shuts the server down the way SIGTERM would: once pending replies
+    /// are flushed, persisting the keyspace as configured, after which
+    /// `Server::run` returns.
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        // Fails only if the server has already gone.
+        let _ = self.waker.wake();
+    }
+}
+
+/// A server running on a thread of its own, from `Server::spawn`.
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    shutdown: ShutdownHandle,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    /// The first TCP address the server listens on. Panics if it listens
+    /// on a Unix socket only.
+    pub fn addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shuts the server down and waits for it to exit, failing if
+    /// `Server::run` did.
+    pub fn stop(self) -> Result<()> {
+        self.shutdown.shutdown();
+        self.join()
+    }
+
+    /// Waits for the server to exit on its own, as it does after SHUTDOWN.
+    pub fn join(self) -> Result<()> {
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("the server thread panicked"))?
+    }
+}
+
+impl Server {
+    pub fn builder() -> Builder {
+        Builder {
+            config: Config::default(),
+            handle_signals: false,
+        }
+    }
+
+    /// The TCP addresses the server listens on, with the ports it was given
+    /// if it asked for port 0.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.state.config
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            waker: self.waker.clone(),
+            requested: self.shutdown_requested.clone(),
+        }
+    }
+
+    /// Runs the server on a new thread, for hosts and tests that carry on
+    /// while it serves.
+    pub fn spawn(self) -> Result<ServerHandle> {
+        let local_addrs = self.local_addrs.clone();
+        let shutdown = self.shutdown_handle();
+        let thread = thread::Builder::new()
+            .name("crabcache".to_string())
+            .spawn(move || self.run())
+            .context("failed to start the server thread")?;
+        Ok(ServerHandle {
+            local_addrs,
+            shutdown,
+            thread,
+        })
+    }
+
+    /// Serves clients until the server is shut down, by SHUTDOWN, a
+    /// `ShutdownHandle` or a signal. Fails if the event loop does, or if a
+    /// forced shutdown could not persist the keyspace.
+    pub fn run(self) -> Result<()> {
+        let Server {
+            mut state,
+            mut poll,
+            mut listeners,
+            mut signals,
+            shutdown_requested,
+            io_threads,
+            mut next_token,
+            files,
+            ..
+        } = self;
+        let mut events = Events::with_capacity(state.config.events_per_poll);
+        let mut connections = HashMap::new();
+        let mut next_client_id = 1;
+        let mut listening = true;
+        let mut last_sweep = Instant::now();
+        // Clients turned away by `maxclients` as of the last sweep, plus any
+        // since. It may overcount until the next sweep, never undercount.
+        let mut rejected = 0;
+        // Set while accepting is paused after it failed.
+        let mut accept_retry: Option<Instant> = None;
+        let result = loop {
+            if let Some(result) = advance_shutdown(&mut state, &mut connections, &poll) {
+                break result;
+            }
+            // Stop accepting while a shutdown is pending, and resume if it is
+            // called off.
+            if state.shutdown.is_some() == listening {
+                listening = !listening;
+                for (index, listener) in listeners.iter_mut().enumerate() {
+                    if listening {
+                        poll.registry()
+                            .register(listener, Token(index), Interest::READABLE)?;
+                    } else {
+                        poll.registry().deregister(listener)?;
+                    }
+                }
+            }
+
+            let mut timeout = state
+                .db
+                .next_expiry()
+                .map(|deadline| Duration::from_millis(deadline.saturating_sub(now_ms())));
+            let rewriting = state.aof.as_ref().is_some_and(Aof::rewrite_in_progress);
+            if state.snapshots.in_progress() || rewriting {
+                timeout = Some(timeout.map_or(BACKGROUND_SAVE_POLL, |timeout| {
+                    timeout.min(BACKGROUND_SAVE_POLL)
+                }));
+            }
+            if let Some(next_fsync) = state.aof.as_ref().and_then(Aof::next_tick) {
+                timeout = Some(timeout.map_or(next_fsync, |timeout| timeout.min(next_fsync)));
+            }
+            if state.shutdown.is_some() {
+                timeout = Some(timeout.map_or(SHUTDOWN_POLL, |timeout| timeout.min(SHUTDOWN_POLL)));
+            }
+            if let Some(retry) = accept_retry {
+                let wait = retry.saturating_duration_since(Instant::now());
+                timeout = Some(timeout.map_or(wait, |timeout| timeout.min(wait)));
+            }
+            if needs_sweep(&state, rejected) && !connections.is_empty() {
+                let next_sweep = CLIENT_SWEEP_INTERVAL.saturating_sub(last_sweep.elapsed());
+                timeout = Some(timeout.map_or(next_sweep, |timeout| timeout.min(next_sweep)));
+            }
+            if let Err(err) = poll.poll(&mut events, timeout) {
+                if interrupted(&err) {
+                    continue;
+                }
+                return Err(err.into());
+            }
+            state.db.expire_keys(now_ms(), MAX_EXPIRED_PER_TICK);
+            log_expired(&mut state);
+            match state.snapshots.reap() {
+                Some(true) => info!("Background save finished"),
+                Some(false) => warn!("Background save failed"),
+                None => {}
+            }
+            if let Some(aof) = &mut state.aof {
+                match aof.reap() {
+                    Some(Ok(())) => info!("Background AOF rewrite finished"),
+                    Some(Err(err)) => warn!("Background AOF rewrite failed: {:#}", err),
+                    None => {}
+                }
+                aof.tick();
+            }
+            start_scheduled(&mut state);
+            if needs_sweep(&state, rejected) && last_sweep.elapsed() >= CLIENT_SWEEP_INTERVAL {
+                rejected = sweep_clients(&mut state, &mut connections, &poll);
+                last_sweep = Instant::now();
+            }
+
+            let mut reads = Vec::new();
+            let mut writes = Vec::new();
+            // Listeners to accept from. Readiness is edge-triggered, so once
+            // a pause is over every listener is tried again.
+            let mut accepts = Vec::new();
+            if accept_retry.is_some_and(|retry| retry <= Instant::now()) {
+                accept_retry = None;
+                if listening {
+                    accepts.extend(0..listeners.len());
+                }
+            }
+            for event in events.iter() {
+                match event.token() {
+                    SIGNALS => {
+                        let signals = signals.as_mut().expect("signals are not handled");
+                        for signal in signals.drain()? {
+                            handle_signal(&mut state, signal);
+                        }
+                    }
+                    WAKER => {
+                        if shutdown_requested.swap(false, Ordering::SeqCst) {
+                            request_shutdown(&mut state);
+                        }
+                    }
+                    token if token.0 < listeners.len() => {
+                        if accept_retry.is_none() && !accepts.contains(&token.0) {
+                            accepts.push(token.0);
+                        }
+                    }
+                    token => match connections.get(&token).map(Connection::state) {
+                        Some(ReadyToRead) if event.is_readable() => reads.push(token),
+                        Some(ReadyToWrite) => writes.push(token),
+                        _ => {}
+                    },
+                }
+            }
+            for index in accepts {
+                loop {
+                    let (mut stream, address) = match listeners[index].accept() {
+                        Ok((stream, address)) => (stream, address),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            break;
+                        }
+                        Err(ref e) if interrupted(e) || aborted(e) => continue,
+                        Err(e) => {
+                            // Clients wait in the backlog until the retry.
+                            if out_of_resources(&e) {
+                                warn!("Out of resources to accept connections: {}", e);
+                            } else {
+                                warn!("Failed to accept a connection: {}", e);
+                            }
+                            accept_retry = Some(Instant::now() + ACCEPT_RETRY_DELAY);
+                            break;
+                        }
+                    };
+                    info!("Accepted connection from: {}", address);
+                    let keepalive = state.config.tcp_keepalive;
+                    if let Stream::Tcp(stream) = &stream {
+                        if !keepalive.is_zero() {
+                            if let Err(err) = listener::set_keepalive(stream, keepalive) {
+                                warn!("Failed to enable keepalive for {}: {}", address, err);
+                            }
+                        }
+                    }
+                    let token = next(&mut next_token);
+                    if let Err(err) =
+                        poll.registry()
+                            .register(&mut stream, token, Interest::READABLE)
+                    {
+                        warn!("Failed to register connection {}: {}", address, err);
+                        continue;
+                    }
+                    let local = stream.local_addr().unwrap_or_default();
+                    let protocol = match &stream {
+                        Stream::Tcp(_) => state.config.protocol,
+                        Stream::Unix(_) => state.config.unixsocket_protocol,
+                    };
+                    let mut connection = Connection::new(stream);
+                    if let Some(protocol) = protocol.pinned() {
+                        connection.set_protocol(protocol);
+                    }
+                    connection.info = ClientInfo::new(next_client_id, address.clone(), local);
+                    next_client_id += 1;
+                    let maxclients = state.config.maxclients;
+                    // Clients waiting to be turned away do not count.
+                    let full = connections.len() >= maxclients && {
+                        rejected = connections.values().filter(|c| c.is_rejected()).count();
+                        connections.len() - rejected >= maxclients
+                    };
+                    if full {
+                        warn!("Rejecting {}: max number of clients reached", address);
+                        state.stats.rejected_connections += 1;
+                        if rejected >= MAX_REJECTED_CLIENTS {
+                            close_connection(&poll, &mut connection);
+                            continue;
+                        }
+                        connection.reject();
+                        rejected += 1;
+                    } else {
+                        state.stats.total_connections_received += 1;
+                    }
+                    connections.insert(token, connection);
+                }
+            }
+            if let Err(err) = serve(
+                &mut state,
+                &io_threads,
+                &mut connections,
+                &poll,
+                reads,
+                writes,
+            ) {
+                break Err(err);
+            }
+        };
+
+        drop(files);
+        info!("Server exited");
+        result
+    }
+}
+
+/// Starts shutting down on SIGINT or SIGTERM. A second signal while a
+/// shutdown is pending stops waiting for replies to drain.
+fn handle_signal(state: &mut State, signal: Signal) {
+    if let Some(shutdown) = &mut state.shutdown {
+        warn!(
+            "Received {} while shutting down, exiting now",
+            signal.name()
+        );
+        shutdown.options.now = true;
+        return;
+    }
+    info!("Received {}, shutting down", signal.name());
+    let save = match signal {
+        Signal::Interrupt => state.config.shutdown_on_sigint,
+        Signal::Terminate => state.config.shutdown_on_sigterm,
+    };
+    state.shutdown = Some(Shutdown::new(
+        Options::new(save),
+        state.config.shutdown_timeout,
+        None,
+    ));
+}
+
+/// Starts shutting down at a `ShutdownHandle`'s request, saving as SIGTERM
+/// would.
+fn request_shutdown(state: &mut State) {
+    if state.shutdown.is_some() {
+        return;
+    }
+    info!("Shutdown requested");
+    state.shutdown = Some(Shutdown::new(
+        Options::new(state.config.shutdown_on_sigterm),
+        state.config.shutdown_timeout,
+        None,
+    ));
+}
+
+/// Moves a pending shutdown along. Once every reply is flushed, or the
+/// deadline passes, the keyspace is persisted and the outcome returned.
+/// A shutdown that is aborted, or fails to persist without FORCE, is called
+/// off and the client that asked for it gets an error.
+fn advance_shutdown(
+    state: &mut State,
+    connections: &mut Connections,
+    poll: &Poll,
+) -> Option<Result<()>> {
+    let shutdown = state.shutdown.as_ref()?;
+    let drained = connections.values().all(Connection::is_flushed);
+    if !shutdown.aborted && !shutdown.options.now && !drained && Instant::now() < shutdown.deadline
+    {
+        return None;
+    }
+    let shutdown = state.shutdown.take()?;
+    if shutdown.aborted {
+        info!("Shutdown aborted");
+        notify_error(connections, poll, shutdown.client, "shutdown was aborted");
+        return None;
+    }
+    match persist(state, shutdown.options) {
+        Ok(()) => Some(Ok(())),
+        Err(err) if shutdown.options.force => {
+            Some(Err(err.context("failed to persist, shut down anyway")))
+        }
+        Err(err) => {
+            error!("Errors trying to shut down: {:#}", err);
+            notify_error(
+                connections,
+                poll,
+                shutdown.client,
+                "Errors trying to SHUTDOWN. Check logs.",
+            );
+            None
+        }
+    }
+}
+
+/// Starts a BGSAVE or BGREWRITEAOF that was queued behind the other once
+/// that one has finished.
+fn start_scheduled(state: &mut State) {
+    let rewriting = state.aof.as_ref().is_some_and(Aof::rewrite_in_progress);
+    if state.snapshots.is_scheduled() && !rewriting {
+        match state.snapshots.background_save(&state.db) {
+            Ok(()) => info!("Scheduled background save started"),
+            Err(err) => warn!("Failed to start the scheduled background save: {:#}", err),
+        }
+    }
+    let Some(aof) = &mut state.aof else {
+        return;
+    };
+    if aof.rewrite_scheduled() && !state.snapshots.in_progress() {
+        match aof.background_rewrite(&state.db) {
+            Ok(()) => info!("Scheduled background AOF rewrite started"),
+            Err(err) => warn!("Failed to start the scheduled AOF rewrite: {:#}", err),
+        }
+    }
+}
+
+/// Saves what the shutdown options call for. Background children are
+/// killed first, as their results would arrive too late to matter.
+fn persist(state: &mut State, options: Options) -> Result<()> {
+    state.snapshots.abort_background_save();
+    if let Some(aof) = &mut state.aof {
+        aof.abort_rewrite();
+        aof.sync()?;
+    }
+    let save = match options.save {
+        SaveMode::Save => true,
+        SaveMode::NoSave => false,
+        // The append only file already holds every write.
+        SaveMode::Default => state.aof.is_none(),
+    };
+    if save {
+        info!("Saving the final snapshot");
+        state.snapshots.save(&state.db)?;
+    }
+    Ok(())
+}
+
+/// Whether `sweep_clients` has anything to look for, with `rejected`
+/// clients waiting to be turned away.
+fn needs_sweep(state: &State, rejected: usize) -> bool {
+    !state.config.timeout.is_zero()
+        || state.config.client_output_buffer_limit.normal.soft > 0
+        || rejected > 0
+}
+
+/// Disconnects clients that have been silent for longer than the idle
+/// timeout, clients turned away by `maxclients` that never sent anything to
+/// be answered, and clients whose unread replies have stayed over the soft
+/// output buffer limit for too long. Returns how many turned away clients
+/// are still waiting.
+fn sweep_clients(state: &mut State, connections: &mut Connections, poll: &Poll) -> usize {
+    let timeout = state.config.timeout;
+    let limit = state.config.client_output_buffer_limit.normal;
+    let stats = &mut state.stats;
+    connections.retain(|_, connection| {
+        if connection.exceeds_output_limit(limit) {
+            log_output_limit(connection);
+            stats.client_output_buffer_limit_disconnections += 1;
+            close_connection(poll, connection);
+            return false;
+        }
+        let idle_limit = match connection.is_rejected() {
+            true => REJECTED_CLIENT_TIMEOUT,
+            false if timeout.is_zero() => return true,
+            false => timeout,
+        };
+        if connection.idle_time() < idle_limit {
+            return true;
+        }
+        info!("Closing idle connection {}", connection.info.addr);
+        close_connection(poll, connection);
+        false
+    });
+    connections.values().filter(|c| c.is_rejected()).count()
+}
+
+fn log_output_limit(connection: &Connection<Stream>) {
+    warn!(
+        "Closing client {}: {} bytes of replies pending, over its output buffer limit",
+        connection.info.addr,
+        connection.pending_response().len()
+    );
+}
+
+/// Stops polling a connection that is about to be dropped.
+fn close_connection(poll: &Poll, connection: &mut Connection<Stream>) {
+    if let Err(err) = poll.registry().deregister(connection.stream_mut()) {
+        warn!("Failed to deregister connection: {}", err);
+    }
+}
+
+/// Queues an error for a client outside of its request cycle.
+fn notify_error(connections: &mut Connections, poll: &Poll, client: Option<Token>, message: &str) {
+    let Some(token) = client else {
+        return;
+    };
+    // The client may have disconnected while it waited.
+    let Some(connection) = connections.get_mut(&token) else {
+        return;
+    };
+    let mut output = connection.output();
+    response_err(&mut output, ErrorCode::Internal.as_num(), message);
+    connection.queue_response(&output);
+    if connection.state == ReadyToRead {
+        connection.set_state(ReadyToWrite);
+        if let Err(err) =
+            poll.registry()
+                .reregister(&mut connection.stream, token, Interest::WRITABLE)
+        {
+            warn!("Failed to reregister connection: {}", err);
+        }
+    }
+}
+
+/// Serves the connections that became ready: reads and parses what they
+/// sent, executes it, and writes the replies. Reading and writing are shared
+/// out across the I/O threads, while commands run here one at a time. Fails
+/// only if an I/O thread has stopped.
+fn serve(
+    state: &mut State,
+    io_threads: &IoThreads,
+    connections: &mut Connections,
+    poll: &Poll,
+    reads: Vec<Token>,
+    mut writes: Vec<Token>,
+) -> Result<()> {
+    // Picked up on every read so CONFIG SET reaches open connections too.
+    let limits = state.config.limits();
+    for token in &reads {
+        if let Some(connection) = connections.get_mut(token) {
+            connection.set_limits(limits);
+        }
+    }
+    let mut touched = writes.clone();
+    for (token, result) in io_threads.run(Task::Read, connections, &reads)? {
+        // Gone if another client killed it earlier in this batch.
+        let Some(mut connection) = connections.remove(&token) else {
+            continue;
+        };
+        let result = result.map_err(Into::into).and_then(|open| {
+            execute_requests(state, &mut connection, open, connections, poll, token)
+        });
+        if let Err(err) = result {
+            warn!("Connection error: {}", err);
+            connection.set_state(Closing);
+        }
+        if connection.state == ReadyToWrite {
+            writes.push(token);
+        }
+        touched.push(token);
+        connections.insert(token, connection);
+    }
+    for (token, result) in io_threads.run(Task::Write, connections, &writes)? {
+        if let (Err(err), Some(connection)) = (result, connections.get_mut(&token)) {
+            warn!("Connection error: {}", err);
+            connection.set_state(Closing);
+        }
+    }
+    for token in touched {
+        let Some(connection) = connections.get_mut(&token) else {
+            continue;
+        };
+        let interest = match connection.state {
+            ReadyToRead => Interest::READABLE,
+            ReadyToWrite => Interest::WRITABLE,
+            Closing => {
+                close_connection(poll, connection);
+                connections.remove(&token);
+                continue;
+            }
+        };
+        if let Err(err) = poll
+            .registry()
+            .reregister(&mut connection.stream, token, interest)
+        {
+            warn!("Connection error: {}", err);
+            close_connection(poll, connection);
+            connections.remove(&token);
+        }
+    }
+    Ok(())
+}
+
+/// Executes every request parsed from the connection so far, queueing their
+/// responses in order. `open` is whether the peer is still connected.
+/// Malformed or failing commands are answered with an error reply; only
+/// errors that lose track of the frame boundary close the connection.
+fn execute_requests(
+    state: &mut State,
+    connection: &mut Connection<Stream>,
+    open: bool,
+    others: &mut Connections,
+    poll: &Poll,
+    token: Token,
+) -> Result<()> {
+    if connection.is_rejected() {
+        reject(connection);
+    }
+    while !connection.should_close_after_reply() {
+        let Some(request) = connection.next_parsed() else {
+            break;
+        };
+        let mut output = connection.output();
+        let mut reply = true;
+        match request {
+            Ok(request) => {
+                state.stats.total_commands_processed += 1;
+                match execute(state, request, connection, others, poll, token, &mut output) {
+                    Ok(answered) => reply = answered,
+                    Err(err) => {
+                        output.clear();
+                        match err.downcast_ref::<ProtocolError>() {
+                            Some(err) => err.reply(&mut output),
+                            None => response_err(
+                                &mut output,
+                                ErrorCode::Internal.as_num(),
+                                &err.to_string(),
+                            ),
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                let err = err.downcast::<ProtocolError>()?;
+                err.reply(&mut output);
+                if err.is_fatal() {
+                    connection.close_after_reply();
+                }
+            }
+        }
+        if reply {
+            connection.queue_response(&output);
+        }
+        let limit = state.config.client_output_buffer_limit.normal;
+        if connection.exceeds_output_limit(limit) {
+            log_output_limit(connection);
+            state.stats.client_output_buffer_limit_disconnections += 1;
+            connection.reset_write_buffer();
+            connection.set_state(Closing);
+            return Ok(());
+        }
+    }
+    if !open {
+        connection.close_after_reply();
+    }
+    if !connection.is_flushed() {
+        connection.set_state(ReadyToWrite);
+    } else if connection.should_close_after_reply() {
+        connection.set_state(Closing);
+    }
+    Ok(())
+}
+
+/// Tells a client accepted past `maxclients` why it is being closed, once
+/// its first bytes show which protocol to say it in.
+fn reject(connection: &mut Connection<Stream>) {
+    if connection.detect_protocol().is_none() {
+        return;
+    }
+    let mut output = connection.output();
+    ProtocolError::MaxClients.reply(&mut output);
+    connection.queue_response(&output);
+    connection.reset_read_buffer();
+    connection.close_after_reply();
+}
+
+/// What a server command's handler can reach: the server's state, the
+/// client that sent the command, every other client, and the reply.
+pub(crate) struct Context<'a> {
+    state: &'a mut State,
+    connection: &'a mut Connection<Stream>,
+    others: &'a mut Connections,
+    poll: &'a Poll,
+    token: Token,
+    output: &'a mut Output,
+    /// Cleared by a handler whose command goes unanswered.
+    reply: bool,
+}
+
+/// Runs `request` through its table entry and returns whether `output`
+/// should be sent back; a SHUTDOWN that goes ahead is never answered.
+/// Keyspace writes are refused once a shutdown is pending or while the
+/// append only file is broken, and logged to it once made.
+fn execute(
+    state: &mut State,
+    request: Request,
+    connection: &mut Connection<Stream>,
+    others: &mut Connections,
+    poll: &Poll,
+    token: Token,
+    output: &mut Output,
+) -> Result<bool> {
+    let Request { spec, command } = request;
+    let handler = match spec.handler {
+        Handler::Keyspace(handler) => handler,
+        Handler::Server(handler) => {
+            let mut ctx = Context {
+                state,
+                connection,
+                others,
+                poll,
+                token,
+                output,
+                reply: true,
+            };
+            handler(&mut ctx, command)?;
+            return Ok(ctx.reply);
+        }
+    };
+    if !spec.has_flag(table::WRITE) {
+        let result = handler(&mut state.db, command, output);
+        log_expired(state);
+        result?;
+        return Ok(true);
+    }
+    // The final snapshot or log sync may already be under way.
+    if state
+        .shutdown
+        .as_ref()
+        .is_some_and(|shutdown| !shutdown.aborted)
+    {
+        return Err(ProtocolError::ShuttingDown.into());
+    }
+    if let Some(aof) = &state.aof {
+        // Writes the log cannot hold would be lost on restart.
+        if let Some(err) = aof.write_error() {
+            return Err(ProtocolError::AofWriteFailed(err.to_string()).into());
+        }
+    }
+    let record = state
+        .aof
+        .as_ref()
+        .and_then(|_| aof::record(&command, now_ms()));
+    let result = handler(&mut state.db, command, output);
+    // Keys the write found expired went before it did.
+    log_expired(state);
+    result?;
+    if let (Some(aof), Some(record)) = (&mut state.aof, record) {
+        // The write has been made, so the client is told so; later ones are
+        // refused until a rewrite repairs the log.
+        if let Err(err) = aof.append(&record) {
+            error!("Failed to append to the append only file: {:#}", err);
+        }
+    }
+    Ok(true)
+}
+
+/// Logs the keys that expired since the last call as deleted, so a replay
+/// does not bring them back with writes logged after them.
+fn log_expired(state: &mut State) {
+    let expired = state.db.take_expired();
+    if expired.is_empty() {
+        return;
+    }
+    if let Some(aof) = &mut state.aof {
+        let record = aof::record(&Command::Del(expired), now_ms()).unwrap();
+        if let Err(err) = aof.append(&record) {
+            error!("Failed to append to the append only file: {:#}", err);
+        }
+    }
+}
+
+fn next(current: &mut Token) -> Token {
+    let next = current.0;
+    current.0 += 1;
+    Token(next)
+}
+
+fn interrupted(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Interrupted
+}
+
+/// Whether accept failed for want of file descriptors or memory, which
+/// closing connections frees up again.
+fn out_of_resources(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    )
+}
+
+fn aborted(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::ConnectionAborted
+}
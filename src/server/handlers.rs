@@ -0,0 +1,130 @@
+//! Handlers for the commands that act on the server or on the client's
+//! connection rather than the keyspace. The command table points at these,
+//! and the event loop runs them with a `Context`.
+
+use super::{close_connection, Context, Shutdown};
+use crate::commands::{self, shutdown, Command, ProtocolError};
+use crate::serialization::response_status;
+use anyhow::Result;
+use log::info;
+
+pub(crate) fn save(ctx: &mut Context, _: Command) -> Result<()> {
+    commands::save::invoke(&mut ctx.state.snapshots, &ctx.state.db, ctx.output)
+}
+
+pub(crate) fn bgsave(ctx: &mut Context, _: Command) -> Result<()> {
+    let state = &mut *ctx.state;
+    commands::bgsave::invoke(
+        &mut state.snapshots,
+        state.aof.as_ref(),
+        &state.db,
+        ctx.output,
+    )
+}
+
+pub(crate) fn bgrewriteaof(ctx: &mut Context, _: Command) -> Result<()> {
+    let state = &mut *ctx.state;
+    commands::bgrewriteaof::invoke(state.aof.as_mut(), &state.snapshots, &state.db, ctx.output)
+}
+
+pub(crate) fn lastsave(ctx: &mut Context, _: Command) -> Result<()> {
+    commands::lastsave::invoke(&ctx.state.snapshots, ctx.output)
+}
+
+pub(crate) fn config(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Config(subcommand) = command else {
+        unreachable!()
+    };
+    let state = &mut *ctx.state;
+    commands::config::invoke(&mut state.config, subcommand, ctx.output)?;
+    if let Some(aof) = &mut state.aof {
+        aof.set_policy(state.config.appendfsync);
+    }
+    Ok(())
+}
+
+pub(crate) fn shutdown(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Shutdown(request) = command else {
+        unreachable!()
+    };
+    match request {
+        shutdown::Request::Start(options) => {
+            if ctx.state.shutdown.is_some() {
+                return Err(ProtocolError::ShutdownInProgress.into());
+            }
+            let timeout = ctx.state.config.shutdown_timeout;
+            ctx.state.shutdown = Some(Shutdown::new(options, timeout, Some(ctx.token)));
+            // The client hears back only if the shutdown is called off.
+            ctx.reply = false;
+        }
+        shutdown::Request::Abort => {
+            let shutdown = ctx
+                .state
+                .shutdown
+                .as_mut()
+                .ok_or(ProtocolError::NoShutdownInProgress)?;
+            shutdown.aborted = true;
+            response_status(ctx.output, "OK");
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn client(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Client(subcommand) = command else {
+        unreachable!()
+    };
+    let killed = commands::client::invoke(subcommand, ctx.connection, ctx.others, ctx.output)?;
+    for token in killed {
+        if let Some(mut killed) = ctx.others.remove(&token) {
+            info!("Killed client {}", killed.info.addr);
+            close_connection(ctx.poll, &mut killed);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn command(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Command(subcommand) = command else {
+        unreachable!()
+    };
+    commands::command::invoke(subcommand, ctx.output)
+}
+
+pub(crate) fn info(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Info(sections) = command else {
+        unreachable!()
+    };
+    let clients = commands::info::Clients {
+        connected: ctx.others.len() + 1,
+        max: ctx.state.config.maxclients,
+    };
+    commands::info::invoke(sections, clients, &ctx.state.stats, ctx.output)
+}
+
+pub(crate) fn ping(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Ping(message) = command else {
+        unreachable!()
+    };
+    commands::ping::invoke(message, ctx.output)
+}
+
+pub(crate) fn hello(ctx: &mut Context, command: Command) -> Result<()> {
+    let Command::Hello(request) = command else {
+        unreachable!()
+    };
+    let protocol = commands::hello::negotiate(ctx.connection.protocol(), request.version)?;
+    request.authenticate()?;
+    if let Some(name) = request.name {
+        ctx.connection.info.name = (!name.is_empty()).then_some(name);
+    }
+    ctx.connection.set_protocol(protocol);
+    *ctx.output = ctx.connection.output();
+    commands::hello::invoke(ctx.output)
+}
+
+pub(crate) fn quit(ctx: &mut Context, _: Command) -> Result<()> {
+    response_status(ctx.output, "OK");
+    ctx.connection.close_after_reply();
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! SIGINT and SIGTERM, delivered to the event loop as readiness events.
+//!
+//! The handler only writes the signal number to one end of a socket pair;
+//! the other end is registered with the poll like any client, so signals are
+//! handled between events rather than in signal context.
+
+use mio::{event::Source, net::UnixStream, Interest, Registry, Token};
+use std::{
+    io::{self, Read},
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// Write end of the socket pair, for the handler. -1 until installed.
+static SENDER: AtomicI32 = AtomicI32::new(-1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+impl Signal {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Terminate => "SIGTERM",
+        }
+    }
+}
+
+pub struct Signals {
+    receiver: UnixStream,
+    // Kept open for the handler, which writes to its descriptor.
+    _sender: UnixStream,
+}
+
+impl Signals {
+    /// Installs handlers for SIGINT and SIGTERM. Only one `Signals` should
+    /// exist at a time, as the handlers write to the latest one.
+    pub fn install() -> io::Result<Signals> {
+        let (receiver, sender) = UnixStream::pair()?;
+        SENDER.store(sender.as_raw_fd(), Ordering::SeqCst);
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            // SAFETY: `action` is fully initialized and the handler only
+            // makes async-signal-safe calls.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(signal, &action, std::ptr::null_mut()) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(Signals {
+            receiver,
+            _sender: sender,
+        })
+    }
+
+    /// The signals delivered since the last call, oldest first.
+    pub fn drain(&mut self) -> io::Result<Vec<Signal>> {
+        let mut signals = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            match self.receiver.read(&mut buf) {
+                Ok(0) => return Ok(signals),
+                Ok(n) => signals.extend(buf[..n].iter().filter_map(|&b| match b as libc::c_int {
+                    libc::SIGINT => Some(Signal::Interrupt),
+                    libc::SIGTERM => Some(Signal::Terminate),
+                    _ => None,
+                })),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(signals),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+extern "C" fn handle(signal: libc::c_int) {
+    // SAFETY: write(2) is async-signal-safe. errno is restored so the code
+    // the signal interrupted does not see the handler's.
+    unsafe {
+        let errno = *libc::__errno_location();
+        let byte = signal as u8;
+        libc::write(
+            SENDER.load(Ordering::SeqCst),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+        *libc::__errno_location() = errno;
+    }
+}
+
+impl Source for Signals {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.receiver.register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.receiver.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.receiver.deregister(registry)
+    }
+}
@@ -0,0 +1,12 @@
+//! Counters kept for the life of the server and reported by INFO.
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Connections accepted and served, not counting rejected ones.
+    pub total_connections_received: u64,
+    pub total_commands_processed: u64,
+    /// Connections turned away by `maxclients`.
+    pub rejected_connections: u64,
+    /// Clients closed for letting too many replies pile up unread.
+    pub client_output_buffer_limit_disconnections: u64,
+}
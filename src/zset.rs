@@ -0,0 +1,261 @@
+use crate::{
+    avl_tree::set::AvlTreeSet,
+    hashtable::{fnv1a_hash, HashNode},
+    scalablehashmap::ScalableHashMap,
+};
+use container_of::container_of;
+use std::cmp::Ordering;
+
+/// Sorted set score. Ordered with `f64::total_cmp` so scores can live in the
+/// AVL tree; NaN is rejected before it gets here.
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Member index entry, linked into `ZSet::members` the same way `Entry` is
+/// linked into the keyspace.
+#[repr(C)]
+struct ZMember {
+    node: HashNode,
+    member: Vec<u8>,
+    score: f64,
+}
+
+impl ZMember {
+    fn check_equality(left: &HashNode, right: &HashNode) -> bool {
+        let left = unsafe { &*container_of!(left as *const HashNode, ZMember, node) };
+        let right = unsafe { &*container_of!(right as *const HashNode, ZMember, node) };
+        left.member == right.member
+    }
+}
+
+/// Sorted set: an AVL tree ordered by `(score, member)` for range queries
+/// plus a member -> score hash index for point lookups.
+pub struct ZSet {
+    tree: AvlTreeSet<(Score, Vec<u8>)>,
+    members: ScalableHashMap,
+}
+
+impl Default for ZSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZSet {
+    pub fn new() -> Self {
+        Self {
+            tree: AvlTreeSet::new(),
+            members: ScalableHashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lookup(&mut self, member: &[u8]) -> Option<&mut ZMember> {
+        let probe = ZMember {
+            node: HashNode::new(fnv1a_hash(member)),
+            member: member.to_vec(),
+            score: 0.0,
+        };
+        let node = self
+            .members
+            .lookup_mut(&probe.node, ZMember::check_equality)? as *mut HashNode;
+        Some(unsafe { &mut *container_of!(node, ZMember, node) })
+    }
+
+    pub fn score(&mut self, member: &[u8]) -> Option<f64> {
+        self.lookup(member).map(|m| m.score)
+    }
+
+    /// Adds `member` or moves it to `score`. Returns `true` if it was added.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        if let Some(existing) = self.lookup(member) {
+            let old = existing.score;
+            existing.score = score;
+            if Score(old) != Score(score) {
+                let _ = self.tree.delete(&(Score(old), member.to_vec()));
+                let _ = self.tree.insert((Score(score), member.to_vec()));
+            }
+            return false;
+        }
+        let entry = Box::leak(Box::new(ZMember {
+            node: HashNode::new(fnv1a_hash(member)),
+            member: member.to_vec(),
+            score,
+        }));
+        self.members.insert(&mut entry.node);
+        let _ = self.tree.insert((Score(score), member.to_vec()));
+        true
+    }
+
+    /// Adds `delta` to the member's score, inserting it at `delta` if it is
+    /// missing, and returns the new score. Leaves the set untouched and
+    /// returns `None` if the result would be NaN (e.g. `inf + -inf`).
+    pub fn incr(&mut self, member: &[u8], delta: f64) -> Option<f64> {
+        let score = self.score(member).unwrap_or(0.0) + delta;
+        if score.is_nan() {
+            return None;
+        }
+        self.insert(member, score);
+        Some(score)
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let probe = ZMember {
+            node: HashNode::new(fnv1a_hash(member)),
+            member: member.to_vec(),
+            score: 0.0,
+        };
+        let Some(node) = self.members.pop(&probe.node, ZMember::check_equality) else {
+            return false;
+        };
+        let entry = unsafe { Box::from_raw(container_of!(node.as_ptr(), ZMember, node)) };
+        let _ = self.tree.delete(&(Score(entry.score), entry.member));
+        true
+    }
+
+    /// Zero-based position of `member` in ascending score order.
+    pub fn rank(&mut self, member: &[u8]) -> Option<usize> {
+        let key = (Score(self.score(member)?), member.to_vec());
+        self.tree.rank(&key)
+    }
+
+    /// The member at zero-based position `rank` in ascending score order.
+    pub fn select(&self, rank: usize) -> Option<(&[u8], f64)> {
+        self.tree
+            .select(rank)
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Members in ascending `(score, member)` order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> + '_ {
+        self.tree
+            .iter()
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Members whose score passes both bounds, seeking straight to the first
+    /// one. `above_min` and `below_max` must be monotone in the score.
+    pub fn range_by_score(
+        &self,
+        above_min: impl Fn(f64) -> bool,
+        below_max: impl Fn(f64) -> bool,
+    ) -> impl DoubleEndedIterator<Item = (&[u8], f64)> + '_ {
+        self.tree
+            .range_by(
+                |(score, _)| above_min(score.0),
+                |(score, _)| below_max(score.0),
+            )
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+}
+
+impl Drop for ZSet {
+    fn drop(&mut self) {
+        let entries: Vec<*const ZMember> = self
+            .members
+            .nodes()
+            .map(|node| unsafe { container_of!(node as *const HashNode, ZMember, node) })
+            .collect();
+        self.members.destroy();
+        for entry in entries {
+            drop(unsafe { Box::from_raw(entry as *mut ZMember) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_score() {
+        let mut zset = ZSet::new();
+        assert!(zset.insert(b"alice", 10.0));
+        assert!(zset.insert(b"bob", 5.0));
+        assert!(!zset.insert(b"alice", 1.0));
+        assert_eq!(zset.len(), 2);
+        assert_eq!(zset.score(b"alice"), Some(1.0));
+        assert_eq!(zset.score(b"carol"), None);
+        let order: Vec<_> = zset.iter().collect();
+        assert_eq!(order, vec![(&b"alice"[..], 1.0), (&b"bob"[..], 5.0)]);
+    }
+
+    #[test]
+    fn test_ties_are_ordered_by_member() {
+        let mut zset = ZSet::new();
+        for member in [&b"c"[..], b"a", b"b"] {
+            zset.insert(member, 1.0);
+        }
+        let order: Vec<_> = zset.iter().map(|(member, _)| member).collect();
+        assert_eq!(order, vec![&b"a"[..], b"b", b"c"]);
+        assert_eq!(zset.rank(b"b"), Some(1));
+        assert_eq!(zset.rank(b"d"), None);
+        assert_eq!(zset.select(2), Some((&b"c"[..], 1.0)));
+        assert_eq!(zset.select(3), None);
+    }
+
+    #[test]
+    fn test_remove_and_incr() {
+        let mut zset = ZSet::new();
+        zset.insert(b"a", 1.0);
+        zset.insert(b"b", 2.0);
+        assert_eq!(zset.incr(b"a", 5.0), Some(6.0));
+        assert_eq!(zset.incr(b"c", -1.5), Some(-1.5));
+        zset.insert(b"d", f64::INFINITY);
+        assert_eq!(zset.incr(b"d", f64::NEG_INFINITY), None);
+        assert!(zset.remove(b"d"));
+        assert!(zset.remove(b"b"));
+        assert!(!zset.remove(b"b"));
+        let order: Vec<_> = zset.iter().collect();
+        assert_eq!(order, vec![(&b"c"[..], -1.5), (&b"a"[..], 6.0)]);
+        assert_eq!(zset.len(), 2);
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        let mut zset = ZSet::new();
+        for (member, score) in [(&b"a"[..], 1.0), (b"b", 2.0), (b"c", 2.0), (b"d", 3.0)] {
+            zset.insert(member, score);
+        }
+        let members: Vec<_> = zset
+            .range_by_score(|score| score >= 2.0, |score| score < 3.0)
+            .map(|(member, _)| member)
+            .collect();
+        assert_eq!(members, vec![&b"b"[..], b"c"]);
+        let last = zset
+            .range_by_score(|score| score > 1.0, |_| true)
+            .next_back();
+        assert_eq!(last, Some((&b"d"[..], 3.0)));
+        assert_eq!(
+            zset.range_by_score(|score| score > 3.0, |_| true).count(),
+            0
+        );
+    }
+}
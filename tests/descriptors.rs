@@ -0,0 +1,66 @@
+//! Runs a server out of file descriptors. The limit is per process, so this
+//! is a test binary of its own: other tests would run out too.
+
+use crabcache::Server;
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn test_accept_survives_running_out_of_descriptors() {
+    let dir = std::env::temp_dir().join(format!("crabcache-descriptors-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let server = Server::builder()
+        .dir(&dir)
+        .port(0)
+        .build()
+        .unwrap()
+        .spawn()
+        .unwrap();
+
+    // A low limit keeps the filler below small.
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: both calls only read or write the struct passed in.
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit), 0);
+        let lowered = libc::rlimit {
+            rlim_cur: limit.rlim_cur.min(256),
+            ..limit
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &lowered), 0);
+    }
+    let mut filler = Vec::new();
+    while let Ok(file) = File::open("/dev/null") {
+        filler.push(file);
+    }
+    // Room for the clients' ends and nothing more, so the server cannot
+    // accept them.
+    filler.truncate(filler.len() - 2);
+    let mut clients: Vec<TcpStream> = (0..2)
+        .map(|_| TcpStream::connect(server.addr()).unwrap())
+        .collect();
+    for client in &mut clients {
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(b"PING\r\n").unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    // Once descriptors are free again, the waiting clients are served.
+    drop(filler);
+    for client in &mut clients {
+        let mut reply = [0; 7];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+    }
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,264 @@
+//! Runs whole servers in-process on ephemeral ports and talks to them over
+//! real sockets, in both protocols.
+
+use crabcache::{Builder, Config, Server, ServerHandle};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Runs a server on an ephemeral port.
+fn start(builder: Builder) -> ServerHandle {
+    builder.port(0).build().unwrap().spawn().unwrap()
+}
+
+fn connect(server: &ServerHandle) -> TcpStream {
+    TcpStream::connect(server.addr()).unwrap()
+}
+
+/// A fresh directory for a test's snapshot.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crabcache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Sends RESP `requests` in one write and reads back `expected`.
+fn resp(stream: &mut TcpStream, requests: &str, expected: &str) {
+    stream.write_all(requests.as_bytes()).unwrap();
+    let mut reply = vec![0; expected.len()];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(String::from_utf8_lossy(&reply), expected);
+}
+
+/// Sends one native request: the argument count, then each argument's
+/// length and bytes, all little-endian. Returns the reply without its frame
+/// length.
+fn native(stream: &mut TcpStream, args: &[&[u8]]) -> Vec<u8> {
+    let mut request = (args.len() as u32).to_le_bytes().to_vec();
+    for arg in args {
+        request.extend((arg.len() as u32).to_le_bytes());
+        request.extend(*arg);
+    }
+    stream.write_all(&request).unwrap();
+    let mut length = [0; 4];
+    stream.read_exact(&mut length).unwrap();
+    let mut reply = vec![0; u32::from_le_bytes(length) as usize];
+    stream.read_exact(&mut reply).unwrap();
+    reply
+}
+
+/// A native array reply of strings.
+fn native_array(items: &[&[u8]]) -> Vec<u8> {
+    let mut reply = vec![4];
+    reply.extend((items.len() as u32).to_le_bytes());
+    for item in items {
+        reply.push(3);
+        reply.extend((item.len() as u32).to_le_bytes());
+        reply.extend(*item);
+    }
+    reply
+}
+
+#[test]
+fn test_resp_and_native_clients() {
+    let dir = temp_dir("protocols");
+    let server = start(Server::builder().dir(&dir));
+
+    let mut client = connect(&server);
+    resp(&mut client, "PING\r\n", "+PONG\r\n");
+    resp(
+        &mut client,
+        "*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n",
+        "+OK\r\n",
+    );
+
+    // GET key, natively.
+    let reply = native(&mut connect(&server), &[b"GET", b"key"]);
+    // String type, string length, value.
+    assert_eq!(reply[0], 3);
+    assert_eq!(&reply[1..5], 5u32.to_le_bytes());
+    assert_eq!(&reply[5..], b"value");
+
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_shutdown_persists_the_keyspace() {
+    let dir = temp_dir("persist");
+    let server = start(Server::builder().dir(&dir));
+    resp(&mut connect(&server), "SET survivor yes\r\n", "+OK\r\n");
+    server.stop().unwrap();
+
+    let server = start(Server::builder().dir(&dir));
+    resp(&mut connect(&server), "GET survivor\r\n", "$3\r\nyes\r\n");
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pending_shutdown_refuses_writes() {
+    let dir = temp_dir("refuse-writes");
+    let server = start(Server::builder().dir(&dir));
+    let mut client = connect(&server);
+    // SHUTDOWN itself is not answered; the server exits once the others are.
+    resp(
+        &mut client,
+        "SHUTDOWN NOSAVE\r\nSET k v\r\nGET k\r\n",
+        "-ERR the server is shutting down and no longer accepts writes\r\n$-1\r\n",
+    );
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    server.join().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rejected_clients_do_not_hold_a_slot() {
+    let dir = temp_dir("maxclients");
+    let server = start(Server::builder().dir(&dir).maxclients(1));
+    let mut first = connect(&server);
+    resp(&mut first, "PING\r\n", "+PONG\r\n");
+    // Turned away, but silent, so not told yet.
+    let mut rejected = connect(&server);
+    drop(first);
+
+    // Well before the rejected client times out, the free slot is taken.
+    let deadline = Instant::now() + Duration::from_millis(500);
+    loop {
+        let mut client = connect(&server);
+        client.write_all(b"PING\r\n").unwrap();
+        let mut reply = [0; 7];
+        client.read_exact(&mut reply).unwrap();
+        if &reply == b"+PONG\r\n" {
+            break;
+        }
+        assert!(Instant::now() < deadline, "no slot freed up");
+        thread::sleep(Duration::from_millis(10));
+    }
+    resp(
+        &mut rejected,
+        "PING\r\n",
+        "-ERR max number of clients reached\r\n",
+    );
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pipelines_keep_their_order_across_io_threads() {
+    let dir = temp_dir("io-threads");
+    let server = start(Server::builder().dir(&dir).io_threads(3));
+    let clients: Vec<_> = (0..8)
+        .map(|client| {
+            let mut stream = connect(&server);
+            thread::spawn(move || {
+                let mut requests = String::new();
+                let mut expected = String::new();
+                for i in 0..50 {
+                    let value = format!("{}-{}", client, i);
+                    requests += &format!("SET k{} {}\r\nGET k{}\r\n", client, value, client);
+                    expected += &format!("+OK\r\n${}\r\n{}\r\n", value.len(), value);
+                }
+                resp(&mut stream, &requests, &expected);
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_native_requests_with_many_arguments() {
+    let dir = temp_dir("arguments");
+    let server = start(Server::builder().dir(&dir));
+    let mut client = connect(&server);
+
+    let reply = native(
+        &mut client,
+        &[b"ZADD", b"z", b"1", b"a", b"2", b"b", b"3", b"c"],
+    );
+    let mut added = vec![2];
+    added.extend(3i64.to_le_bytes());
+    assert_eq!(reply, added);
+    let reply = native(
+        &mut client,
+        &[b"ZRANGEBYSCORE", b"z", b"1", b"3", b"LIMIT", b"1", b"1"],
+    );
+    assert_eq!(reply, native_array(&[b"b"]));
+    let reply = native(
+        &mut client,
+        &[
+            b"ZRANGEBYSCORE",
+            b"z",
+            b"-inf",
+            b"+inf",
+            b"WITHSCORES",
+            b"LIMIT",
+            b"1",
+            b"2",
+        ],
+    );
+    assert_eq!(reply, native_array(&[b"b", b"2", b"c", b"3"]));
+
+    // Argument counts whose first byte is `*` or a letter, sent first on a
+    // fresh connection, are still told apart from RESP.
+    for count in [42, 65] {
+        let keys: Vec<Vec<u8>> = (1..count).map(|i| format!("k{}", i).into_bytes()).collect();
+        let mut args: Vec<&[u8]> = vec![b"DEL"];
+        args.extend(keys.iter().map(|key| key.as_slice()));
+        let reply = native(&mut connect(&server), &args);
+        let mut deleted = vec![2];
+        deleted.extend(0i64.to_le_bytes());
+        assert_eq!(reply, deleted);
+    }
+
+    server.stop().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pidfile_and_socket_go_with_the_server() {
+    let dir = temp_dir("run-files");
+    let pidfile = dir.join("crabcache.pid");
+    let socket = dir.join("crabcache.sock");
+    let builder = || {
+        let config = Config {
+            pidfile: Some(pidfile.clone()),
+            ..Config::default()
+        };
+        Server::builder()
+            .config(config)
+            .dir(&dir)
+            .unixsocket(&socket)
+    };
+    let gone = || !pidfile.exists() && !socket.exists();
+
+    let server = start(builder());
+    assert!(pidfile.exists() && socket.exists());
+    server.stop().unwrap();
+    assert!(gone());
+
+    // A server that never runs cleans up too.
+    let server = builder().port(0).build().unwrap();
+    assert!(pidfile.exists() && socket.exists());
+    drop(server);
+    assert!(gone());
+
+    // As does one that fails to load its keyspace, after making both. A
+    // record of zero arguments is corrupt.
+    fs::write(dir.join("appendonly.aof"), [0; 8]).unwrap();
+    assert!(builder().port(0).appendonly(true).build().is_err());
+    assert!(gone());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,20 @@
+//! Raises real signals, so it runs as its own test binary: a signal raised
+//! in a shared one would reach handlers other tests install, or kill it.
+
+use crabcache::signals::{Signal, Signals};
+
+#[test]
+fn test_signals_are_queued() {
+    let mut signals = Signals::install().unwrap();
+    assert_eq!(signals.drain().unwrap(), vec![]);
+    // SAFETY: raise(3) only delivers the signal to this thread, and the
+    // handlers just installed catch both.
+    unsafe {
+        libc::raise(libc::SIGTERM);
+        libc::raise(libc::SIGINT);
+    }
+    assert_eq!(
+        signals.drain().unwrap(),
+        vec![Signal::Terminate, Signal::Interrupt]
+    );
+}